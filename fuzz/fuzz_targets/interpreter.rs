@@ -0,0 +1,146 @@
+// the same checks as tests/differential.rs, driven by libFuzzer:
+//
+//   cargo +nightly fuzz run interpreter
+//
+// input layout: an 8 byte seed, a settings byte (quirks profile in bits 0-1,
+// memory policy in bits 2-3), an event count, that many 3 byte key events
+// (step as u16 LE, then key in the low nibble and bit 7 set for a press),
+// and whatever is left is the rom
+#![no_main]
+
+#[path = "../../tests/reference/mod.rs"]
+mod reference;
+
+use chip8_rs::computer::{Computer, StepOutcome};
+use chip8_rs::memory::AccessPolicy;
+use chip8_rs::quirks::Quirks;
+use libfuzzer_sys::fuzz_target;
+use reference::{Reference, Step, Stop};
+
+const STEPS: usize = 2000;
+const CYCLES_PER_FRAME: usize = 10;
+
+struct Input<'a> {
+    seed: u64,
+    quirks: Quirks,
+    policy: AccessPolicy,
+    events: Vec<(usize, u8, bool)>,
+    rom: &'a [u8],
+}
+
+fn parse(data: &[u8]) -> Option<Input<'_>> {
+    let (seed, data) = data.split_first_chunk::<8>()?;
+    let (&settings, data) = data.split_first()?;
+    let (&count, data) = data.split_first()?;
+    let (events, rom) = data.split_at_checked(count as usize * 3)?;
+
+    let quirks = match settings & 3 {
+        0 => Quirks::default(),
+        1 => Quirks::cosmac_vip(),
+        2 => Quirks::chip48(),
+        _ => Quirks::xo_chip(),
+    };
+    let policy = match settings >> 2 & 3 {
+        0 => AccessPolicy::Wrap,
+        1 => AccessPolicy::Trap,
+        _ => AccessPolicy::Clamp,
+    };
+    let mut events: Vec<(usize, u8, bool)> = events.chunks(3)
+        .map(|e| (u16::from_le_bytes([e[0], e[1]]) as usize, e[2] & 0xF, e[2] & 0x80 != 0))
+        .collect();
+    events.sort_by_key(|e| e.0);
+
+    Some(Input { seed: u64::from_le_bytes(*seed), quirks, policy, events, rom })
+}
+
+fn check_invariants(input: &Input) {
+    let mut comp = Computer::with_seed(input.rom.to_vec(), input.quirks, input.seed);
+    comp.set_memory_policy(input.policy);
+    let len = comp.memory().len();
+    let mut events = input.events.iter().peekable();
+
+    for step in 0..STEPS {
+        while let Some((_, key, pressed)) = events.next_if(|e| e.0 <= step) {
+            if *pressed { comp.press(*key) } else { comp.release(*key) }
+        }
+
+        let pc = comp.cpu().pc;
+        let result = comp.tick();
+        let cpu = comp.cpu();
+        assert!(cpu.sp <= 16, "SP {} after {:#06x}", cpu.sp, pc);
+
+        match result {
+            Ok(StepOutcome::Exited) => return,
+            Ok(_) => assert!((pc as usize) < len, "executed at {:#06x} with {} bytes of memory", pc, len),
+            Err(e) => {
+                assert_eq!(cpu.pc, pc, "PC moved off the instruction that failed with {}", e);
+                return;
+            },
+        }
+
+        if (step + 1) % CYCLES_PER_FRAME == 0 {
+            comp.tick_timers();
+        }
+    }
+}
+
+fn check_against_reference(input: &Input) {
+    let mut comp = Computer::with_seed(input.rom.to_vec(), Quirks::default(), input.seed);
+    let mut model = Reference::new(comp.dump(), input.seed);
+    let mut events = input.events.iter().peekable();
+
+    for step in 0..STEPS {
+        while let Some((_, key, pressed)) = events.next_if(|e| e.0 <= step) {
+            if *pressed { comp.press(*key) } else { comp.release(*key) }
+            model.keys[*key as usize] = *pressed;
+        }
+
+        let at = model.pc;
+        let expected = model.step();
+        let actual = comp.tick();
+
+        match (expected, actual) {
+            (Err(Stop::Unsupported), _) => return,
+            (Err(Stop::Error), Err(_)) => {
+                assert_eq!(comp.cpu().pc, model.pc, "PC after failing at {:#05x}", at);
+                return;
+            },
+            (Ok(step), Ok(outcome)) => {
+                assert_eq!(outcome == StepOutcome::WaitingForKey, step == Step::WaitingForKey, "at {:#05x}: {:?}", at, outcome);
+
+                let cpu = comp.cpu();
+                assert_eq!(cpu.v, model.v, "V registers after {:#05x}", at);
+                assert_eq!(cpu.i, model.i, "I after {:#05x}", at);
+                assert_eq!(cpu.pc, model.pc, "PC after {:#05x}", at);
+                assert_eq!(cpu.stack(), &model.stack[..], "stack after {:#05x}", at);
+                assert_eq!((cpu.dt, cpu.st), (model.dt, model.st), "timers after {:#05x}", at);
+                assert!(comp.memory().as_slice() == &model.memory[..], "memory after {:#05x}", at);
+
+                if step == Step::Drew {
+                    let display = comp.display();
+                    for (y, row) in model.screen.iter().enumerate() {
+                        for (x, pixel) in row.iter().enumerate() {
+                            assert_eq!(display.get(x, y), *pixel, "pixel ({}, {}) after {:#05x}", x, y, at);
+                        }
+                    }
+                }
+            },
+            (expected, actual) => panic!("at {:#05x}: reference {:?}, computer {:?}", at, expected, actual),
+        }
+
+        if (step + 1) % CYCLES_PER_FRAME == 0 {
+            comp.tick_timers();
+            model.tick_timers();
+        }
+    }
+}
+
+fuzz_target!(|data: &[u8]| {
+    let Some(input) = parse(data) else { return };
+
+    check_invariants(&input);
+    // the reference only models the default quirks and wrapping memory
+    if input.quirks == Quirks::default() && input.policy == AccessPolicy::Wrap {
+        check_against_reference(&input);
+    }
+});
@@ -0,0 +1,485 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use crate::instruction::Instruction;
+
+const ORIGIN: u16 = 0x200;
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AsmError {
+    pub file: String,
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.file, self.line, self.message)
+    }
+}
+
+impl Error for AsmError {}
+
+struct SourceLine {
+    file: String,
+    line: usize,
+    text: String,
+}
+
+impl SourceLine {
+    fn error(&self, message: impl Into<String>) -> AsmError {
+        AsmError { file: self.file.clone(), line: self.line, message: message.into() }
+    }
+}
+
+// a parsed line: `[label:] [mnemonic operand, operand...]`, or `name EQU value`
+struct Statement {
+    label: Option<String>,
+    mnemonic: Option<String>,
+    operands: Vec<String>,
+}
+
+// assembles Cowgod style source into a ROM image that loads at 0x200;
+// includes are resolved relative to the current directory
+pub fn assemble(source: &str) -> Result<Vec<u8>, AsmError> {
+    let mut lines = Vec::new();
+    expand("<input>", source, Path::new("."), &mut lines, 0)?;
+    assemble_lines(&lines)
+}
+
+// like assemble(), with includes resolved relative to the file
+pub fn assemble_file(path: &Path) -> Result<Vec<u8>, AsmError> {
+    let name = path.display().to_string();
+    let source = fs::read_to_string(path)
+        .map_err(|e| AsmError { file: name.clone(), line: 0, message: e.to_string() })?;
+
+    let mut lines = Vec::new();
+    expand(&name, &source, &base_dir(path), &mut lines, 0)?;
+    assemble_lines(&lines)
+}
+
+fn base_dir(path: &Path) -> PathBuf {
+    path.parent().map(Path::to_path_buf).unwrap_or_default()
+}
+
+// reads the source into lines, replacing INCLUDE "file" with the contents of that file
+fn expand(file: &str, source: &str, base: &Path, out: &mut Vec<SourceLine>, depth: usize) -> Result<(), AsmError> {
+    for (n, text) in source.lines().enumerate() {
+        let line = SourceLine { file: file.to_string(), line: n + 1, text: text.to_string() };
+        let stmt = parse_line(&line)?;
+
+        let is_include = stmt.mnemonic.as_deref().is_some_and(|m| m.eq_ignore_ascii_case("include"));
+        if !is_include {
+            out.push(line);
+            continue;
+        }
+
+        if stmt.label.is_some() || stmt.operands.len() != 1 {
+            return Err(line.error("expected INCLUDE \"file\""));
+        }
+        if depth >= MAX_INCLUDE_DEPTH {
+            return Err(line.error("includes nested too deeply"));
+        }
+
+        let path = base.join(unquote(&stmt.operands[0]).ok_or_else(|| line.error("expected INCLUDE \"file\""))?);
+        let included = fs::read_to_string(&path).map_err(|e| line.error(format!("{}: {}", path.display(), e)))?;
+        expand(&path.display().to_string(), &included, &base_dir(&path), out, depth + 1)?;
+    }
+
+    Ok(())
+}
+
+fn unquote(s: &str) -> Option<&str> {
+    s.strip_prefix('"')?.strip_suffix('"')
+}
+
+// splits on commas that aren't inside a string
+fn split_operands(s: &str) -> Vec<String> {
+    let mut out = Vec::new();
+    let mut current = String::new();
+    let mut quoted = false;
+
+    for c in s.chars() {
+        match c {
+            '"' => {
+                quoted = !quoted;
+                current.push(c);
+            },
+            ',' if !quoted => {
+                out.push(current.trim().to_string());
+                current.clear();
+            },
+            _ => current.push(c),
+        }
+    }
+    if !current.trim().is_empty() || !out.is_empty() {
+        out.push(current.trim().to_string());
+    }
+    out
+}
+
+fn strip_comment(s: &str) -> &str {
+    let mut quoted = false;
+    for (i, c) in s.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            ';' if !quoted => return &s[..i],
+            _ => {}
+        }
+    }
+    s
+}
+
+fn parse_line(line: &SourceLine) -> Result<Statement, AsmError> {
+    let mut text = strip_comment(&line.text).trim();
+    let mut stmt = Statement { label: None, mnemonic: None, operands: Vec::new() };
+
+    if let Some(colon) = text.find(':') {
+        if !text[..colon].contains('"') {
+            let label = text[..colon].trim();
+            if !is_identifier(label) {
+                return Err(line.error(format!("invalid label name: {}", label)));
+            }
+            stmt.label = Some(label.to_string());
+            text = text[colon + 1..].trim();
+        }
+    }
+
+    if text.is_empty() {
+        return Ok(stmt);
+    }
+
+    let (first, rest) = match text.find(char::is_whitespace) {
+        Some(i) => (&text[..i], text[i..].trim()),
+        None => (text, ""),
+    };
+
+    // NAME EQU value
+    if let Some(value) = rest.strip_prefix("EQU ").or_else(|| rest.strip_prefix("equ ")) {
+        if stmt.label.is_some() || !is_identifier(first) {
+            return Err(line.error("expected NAME EQU value"));
+        }
+        stmt.label = Some(first.to_string());
+        stmt.mnemonic = Some("EQU".to_string());
+        stmt.operands = vec![value.trim().to_string()];
+        return Ok(stmt);
+    }
+
+    stmt.mnemonic = Some(first.to_ascii_uppercase());
+    stmt.operands = split_operands(rest);
+    Ok(stmt)
+}
+
+fn is_identifier(s: &str) -> bool {
+    let mut chars = s.chars();
+    let valid = match chars.next() {
+        Some(c) => (c.is_ascii_alphabetic() || c == '_') && chars.all(|c| c.is_ascii_alphanumeric() || c == '_'),
+        None => false,
+    };
+
+    valid && register(s).is_none() && !matches!(s.to_ascii_uppercase().as_str(),
+        "I" | "DT" | "ST" | "K" | "F" | "HF" | "B" | "R" | "LONG")
+}
+
+fn register(s: &str) -> Option<u8> {
+    let s = s.strip_prefix('V').or_else(|| s.strip_prefix('v'))?;
+    if s.len() == 1 {
+        u8::from_str_radix(s, 16).ok()
+    }
+    else {
+        None
+    }
+}
+
+fn parse_number(s: &str) -> Option<i64> {
+    let lower = s.to_ascii_lowercase();
+    if let Some(hex) = lower.strip_prefix("0x").or_else(|| lower.strip_prefix('$')) {
+        i64::from_str_radix(hex, 16).ok()
+    }
+    else if let Some(bin) = lower.strip_prefix("0b") {
+        i64::from_str_radix(bin, 2).ok()
+    }
+    else {
+        lower.parse().ok()
+    }
+}
+
+struct Assembler<'a> {
+    symbols: &'a HashMap<String, i64>,
+    line: &'a SourceLine,
+}
+
+impl<'a> Assembler<'a> {
+    // terms separated by + and -, each a number or a symbol
+    fn eval(&self, expr: &str) -> Result<i64, AsmError> {
+        let mut total = 0;
+        let mut sign = 1;
+        let mut term = String::new();
+
+        for c in expr.trim().chars() {
+            match c {
+                '+' | '-' if !term.trim().is_empty() => {
+                    total += sign * self.term(term.trim())?;
+                    term.clear();
+                    sign = if c == '-' { -1 } else { 1 };
+                },
+                '-' => sign = -sign,
+                '+' => {},
+                _ => term.push(c),
+            }
+        }
+
+        if term.trim().is_empty() {
+            return Err(self.line.error(format!("invalid expression: {}", expr.trim())));
+        }
+        Ok(total + sign * self.term(term.trim())?)
+    }
+
+    fn term(&self, term: &str) -> Result<i64, AsmError> {
+        if let Some(n) = parse_number(term) {
+            return Ok(n);
+        }
+        if term.starts_with(|c: char| c.is_ascii_digit()) {
+            return Err(self.line.error(format!("invalid number: {}", term)));
+        }
+        self.symbols.get(term).copied().ok_or_else(|| self.line.error(format!("undefined symbol: {}", term)))
+    }
+
+    fn value(&self, expr: &str, min: i64, max: i64, what: &str) -> Result<i64, AsmError> {
+        let v = self.eval(expr)?;
+        if v < min || v > max {
+            return Err(self.line.error(format!("{} out of range: {}", what, expr)));
+        }
+        Ok(v)
+    }
+
+    fn byte(&self, expr: &str) -> Result<u8, AsmError> {
+        Ok(self.value(expr, -128, 0xFF, "byte")? as u8)
+    }
+
+    fn addr(&self, expr: &str) -> Result<u16, AsmError> {
+        Ok(self.value(expr, 0, 0xFFF, "address")? as u16)
+    }
+
+    fn nibble(&self, expr: &str) -> Result<u8, AsmError> {
+        Ok(self.value(expr, 0, 0xF, "value")? as u8)
+    }
+
+    fn reg(&self, s: &str) -> Result<u8, AsmError> {
+        register(s).ok_or_else(|| self.line.error(format!("expected a register, found {}", s)))
+    }
+
+    fn instruction(&self, mnemonic: &str, ops: &[String]) -> Result<Vec<u8>, AsmError> {
+        let upper: Vec<String> = ops.iter().map(|o| o.to_ascii_uppercase()).collect();
+        let ops_upper: Vec<&str> = upper.iter().map(String::as_str).collect();
+        let arity = |n: usize| -> Result<(), AsmError> {
+            if ops.len() != n {
+                return Err(self.line.error(format!("{} takes {} operand(s)", mnemonic, n)));
+            }
+            Ok(())
+        };
+
+        let ins = match (mnemonic, ops_upper.as_slice()) {
+            ("CLS", []) => Instruction::Cls,
+            ("RET", []) => Instruction::Ret,
+            ("SCR", []) => Instruction::Scr,
+            ("SCL", []) => Instruction::Scl,
+            ("EXIT", []) => Instruction::Exit,
+            ("LOW", []) => Instruction::Low,
+            ("HIGH", []) => Instruction::High,
+            ("AUDIO", []) => Instruction::Audio,
+            ("SCD", [_]) => Instruction::Scd(self.nibble(&ops[0])?),
+            ("SCU", [_]) => Instruction::Scu(self.nibble(&ops[0])?),
+            ("PLANE", [_]) => Instruction::Plane(self.nibble(&ops[0])?),
+            ("JP", ["V0", _]) => Instruction::JpV0(self.addr(&ops[1])?),
+            ("JP", [_]) => Instruction::Jp(self.addr(&ops[0])?),
+            ("CALL", [_]) => Instruction::Call(self.addr(&ops[0])?),
+            ("SE", [x, y]) if register(y).is_some() => Instruction::SeVxVy { x: self.reg(x)?, y: self.reg(y)? },
+            ("SE", [x, _]) => Instruction::SeVxByte { x: self.reg(x)?, kk: self.byte(&ops[1])? },
+            ("SNE", [x, y]) if register(y).is_some() => Instruction::SneVxVy { x: self.reg(x)?, y: self.reg(y)? },
+            ("SNE", [x, _]) => Instruction::SneVxByte { x: self.reg(x)?, kk: self.byte(&ops[1])? },
+            ("SAVE", [x, y]) => Instruction::SaveVxVy { x: self.reg(x)?, y: self.reg(y)? },
+            ("LOAD", [x, y]) => Instruction::LoadVxVy { x: self.reg(x)?, y: self.reg(y)? },
+            ("LD", ["I", target]) if target.starts_with("LONG ") => {
+                let addr = self.value(&ops[1][5..], 0, 0xFFFF, "address")? as u16;
+                let mut out = Instruction::LdILong.encode().to_be_bytes().to_vec();
+                out.extend(addr.to_be_bytes());
+                return Ok(out);
+            },
+            ("LD", ["I", _]) => Instruction::LdI(self.addr(&ops[1])?),
+            ("LD", ["DT", x]) => Instruction::LdDtVx { x: self.reg(x)? },
+            ("LD", ["ST", x]) => Instruction::LdStVx { x: self.reg(x)? },
+            ("LD", ["F", x]) => Instruction::LdFVx { x: self.reg(x)? },
+            ("LD", ["HF", x]) => Instruction::LdHfVx { x: self.reg(x)? },
+            ("LD", ["B", x]) => Instruction::LdBVx { x: self.reg(x)? },
+            ("LD", ["[I]", x]) => Instruction::LdIVx { x: self.reg(x)? },
+            ("LD", ["R", x]) => Instruction::LdRVx { x: self.reg(x)? },
+            ("LD", [x, "DT"]) => Instruction::LdVxDt { x: self.reg(x)? },
+            ("LD", [x, "K"]) => Instruction::LdVxK { x: self.reg(x)? },
+            ("LD", [x, "[I]"]) => Instruction::LdVxI { x: self.reg(x)? },
+            ("LD", [x, "R"]) => Instruction::LdVxR { x: self.reg(x)? },
+            ("LD", [x, y]) if register(y).is_some() => Instruction::LdVxVy { x: self.reg(x)?, y: self.reg(y)? },
+            ("LD", [x, _]) => Instruction::LdVxByte { x: self.reg(x)?, kk: self.byte(&ops[1])? },
+            ("ADD", ["I", x]) => Instruction::AddIVx { x: self.reg(x)? },
+            ("ADD", [x, y]) if register(y).is_some() => Instruction::AddVxVy { x: self.reg(x)?, y: self.reg(y)? },
+            ("ADD", [x, _]) => Instruction::AddVxByte { x: self.reg(x)?, kk: self.byte(&ops[1])? },
+            ("OR", [x, y]) => Instruction::OrVxVy { x: self.reg(x)?, y: self.reg(y)? },
+            ("AND", [x, y]) => Instruction::AndVxVy { x: self.reg(x)?, y: self.reg(y)? },
+            ("XOR", [x, y]) => Instruction::XorVxVy { x: self.reg(x)?, y: self.reg(y)? },
+            ("SUB", [x, y]) => Instruction::SubVxVy { x: self.reg(x)?, y: self.reg(y)? },
+            ("SUBN", [x, y]) => Instruction::SubnVxVy { x: self.reg(x)?, y: self.reg(y)? },
+            ("SHR", [x]) => Instruction::ShrVxVy { x: self.reg(x)?, y: self.reg(x)? },
+            ("SHR", [x, y]) => Instruction::ShrVxVy { x: self.reg(x)?, y: self.reg(y)? },
+            ("SHL", [x]) => Instruction::ShlVxVy { x: self.reg(x)?, y: self.reg(x)? },
+            ("SHL", [x, y]) => Instruction::ShlVxVy { x: self.reg(x)?, y: self.reg(y)? },
+            ("RND", [x, _]) => Instruction::RndVxByte { x: self.reg(x)?, kk: self.byte(&ops[1])? },
+            ("DRW", [x, y, _]) => Instruction::Drw { x: self.reg(x)?, y: self.reg(y)?, n: self.nibble(&ops[2])? },
+            ("SKP", [x]) => Instruction::SkpVx { x: self.reg(x)? },
+            ("SKNP", [x]) => Instruction::SknpVx { x: self.reg(x)? },
+            ("PITCH", [x]) => Instruction::PitchVx { x: self.reg(x)? },
+            ("CLS" | "RET" | "SCR" | "SCL" | "EXIT" | "LOW" | "HIGH" | "AUDIO", _) => return arity(0).map(|_| Vec::new()),
+            ("SCD" | "SCU" | "PLANE" | "CALL" | "SKP" | "SKNP" | "PITCH", _) => return arity(1).map(|_| Vec::new()),
+            ("SE" | "SNE" | "SAVE" | "LOAD" | "OR" | "AND" | "XOR" | "SUB" | "SUBN" | "RND", _) => {
+                return arity(2).map(|_| Vec::new());
+            },
+            ("DRW", _) => return arity(3).map(|_| Vec::new()),
+            ("JP" | "LD" | "ADD" | "SHR" | "SHL", _) => {
+                return Err(self.line.error(format!("invalid operands for {}", mnemonic)));
+            },
+            _ => return Err(self.line.error(format!("unknown mnemonic: {}", mnemonic))),
+        };
+
+        Ok(ins.encode().to_be_bytes().to_vec())
+    }
+
+    fn data(&self, mnemonic: &str, ops: &[String]) -> Result<Vec<u8>, AsmError> {
+        let mut out = Vec::new();
+
+        for op in ops {
+            match (mnemonic, unquote(op)) {
+                ("DB", Some(s)) => out.extend(s.bytes()),
+                ("DB", None) => out.push(self.byte(op)?),
+                (_, _) => out.extend((self.value(op, -0x8000, 0xFFFF, "word")? as u16).to_be_bytes()),
+            }
+        }
+        Ok(out)
+    }
+}
+
+fn size(stmt: &Statement, line: &SourceLine) -> Result<usize, AsmError> {
+    let mnemonic = stmt.mnemonic.as_deref().unwrap_or("");
+
+    Ok(match mnemonic {
+        "" | "EQU" | "ORG" => 0,
+        "DB" => stmt.operands.iter().map(|op| unquote(op).map_or(1, str::len)).sum(),
+        "DW" => stmt.operands.len() * 2,
+        "LD" if stmt.operands.len() == 2 && stmt.operands[1].to_ascii_uppercase().starts_with("LONG ") => 4,
+        _ => {
+            if stmt.operands.iter().any(|op| op.is_empty()) {
+                return Err(line.error("empty operand"));
+            }
+            2
+        },
+    })
+}
+
+fn assemble_lines(lines: &[SourceLine]) -> Result<Vec<u8>, AsmError> {
+    let stmts = lines.iter().map(parse_line).collect::<Result<Vec<_>, _>>()?;
+    let mut symbols: HashMap<String, i64> = HashMap::new();
+
+    // pass 1: addresses of labels and values of constants
+    let mut addr = ORIGIN as i64;
+    for (stmt, line) in stmts.iter().zip(lines) {
+        let asm = Assembler { symbols: &symbols, line };
+
+        if stmt.mnemonic.as_deref() == Some("ORG") {
+            addr = org(&asm, stmt, addr)?;
+        }
+
+        if let Some(label) = &stmt.label {
+            if symbols.contains_key(label) {
+                return Err(line.error(format!("duplicate symbol: {}", label)));
+            }
+            let value = match stmt.mnemonic.as_deref() {
+                Some("EQU") => asm.eval(&stmt.operands[0])?,
+                _ => addr,
+            };
+            symbols.insert(label.clone(), value);
+        }
+
+        addr += size(stmt, line)? as i64;
+    }
+
+    // pass 2: code generation
+    let mut out: Vec<u8> = Vec::new();
+    for (stmt, line) in stmts.iter().zip(lines) {
+        let asm = Assembler { symbols: &symbols, line };
+
+        let bytes = match stmt.mnemonic.as_deref() {
+            None | Some("EQU") => continue,
+            Some("ORG") => {
+                let target = org(&asm, stmt, ORIGIN as i64 + out.len() as i64)?;
+                out.resize((target - ORIGIN as i64) as usize, 0);
+                continue;
+            },
+            Some(m @ ("DB" | "DW")) => asm.data(m, &stmt.operands)?,
+            Some(m) => asm.instruction(m, &stmt.operands)?,
+        };
+
+        if ORIGIN as usize + out.len() + bytes.len() > 0x10000 {
+            return Err(line.error("program does not fit in memory"));
+        }
+        out.extend(bytes);
+    }
+
+    Ok(out)
+}
+
+// ORG may only move forward; the gap is filled with zeros
+fn org(asm: &Assembler, stmt: &Statement, current: i64) -> Result<i64, AsmError> {
+    if stmt.operands.len() != 1 {
+        return Err(asm.line.error("ORG takes 1 operand"));
+    }
+    let target = asm.value(&stmt.operands[0], ORIGIN as i64, 0xFFFF, "address")?;
+    if target < current {
+        return Err(asm.line.error("ORG can't move backwards"));
+    }
+    Ok(target)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolves_labels_and_constants() {
+        let source = "
+            SPEED EQU 3
+            start:
+                LD V0, SPEED
+                CALL sub
+                JP start
+            sub:
+                ADD V0, 1
+                RET
+            ";
+        assert_eq!(assemble(source), Ok(vec![0x60, 0x03, 0x22, 0x06, 0x12, 0x00, 0x70, 0x01, 0x00, 0xEE]));
+    }
+
+    #[test]
+    fn emits_data() {
+        assert_eq!(assemble("DB 1, 0x02, \"hi\"\nDW 0x1234"), Ok(vec![0x01, 0x02, b'h', b'i', 0x12, 0x34]));
+    }
+
+    #[test]
+    fn reports_the_failing_line() {
+        let err = assemble("CLS\nLD V0, 0x100").unwrap_err();
+        assert_eq!((err.file.as_str(), err.line), ("<input>", 2));
+
+        let err = assemble("JP nowhere").unwrap_err();
+        assert_eq!(err.line, 1);
+    }
+}
@@ -0,0 +1,234 @@
+use std::io::{self, Write};
+use crate::state::{StateError, StateReader, StateWriter};
+
+#[derive(Clone, Copy)]
+pub struct AudioSource {
+    active: bool,
+    frequency: f32,
+    volume: f32,
+    phase: f32,
+    pattern: Option<[u8; 16]>,
+    pitch: u8,
+}
+
+impl Default for AudioSource {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AudioSource {
+    pub fn new() -> Self {
+        Self {
+            active: false,
+            frequency: 440.0,
+            volume: 0.25,
+            phase: 0.0,
+            pattern: None,
+            pitch: 64,
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+
+    pub fn set_active(&mut self, active: bool) {
+        self.active = active;
+    }
+
+    pub fn set_frequency(&mut self, frequency: f32) {
+        self.frequency = frequency;
+    }
+
+    pub fn set_volume(&mut self, volume: f32) {
+        self.volume = volume;
+    }
+
+    // XO-CHIP F002: once a pattern is loaded it replaces the square wave
+    pub fn set_pattern(&mut self, pattern: [u8; 16]) {
+        self.pattern = Some(pattern);
+        self.phase = 0.0;
+    }
+
+    pub fn pattern(&self) -> Option<[u8; 16]> {
+        self.pattern
+    }
+
+    // XO-CHIP Fx3A
+    pub fn set_pitch(&mut self, pitch: u8) {
+        self.pitch = pitch;
+    }
+
+    pub fn pitch(&self) -> u8 {
+        self.pitch
+    }
+
+    pub(crate) fn write_state(&self, out: &mut StateWriter) {
+        out.bool(self.active);
+        out.f32(self.frequency);
+        out.f32(self.volume);
+        out.f32(self.phase);
+        out.bool(self.pattern.is_some());
+        out.bytes(&self.pattern.unwrap_or([0; 16]));
+        out.u8(self.pitch);
+    }
+
+    pub(crate) fn read_state(r: &mut StateReader) -> Result<Self, StateError> {
+        let active = r.bool()?;
+        let frequency = r.f32()?;
+        let volume = r.f32()?;
+        let phase = r.f32()?;
+        let has_pattern = r.bool()?;
+        let pattern: [u8; 16] = r.bytes(16)?.try_into().unwrap();
+        let pitch = r.u8()?;
+
+        if !(0.0..128.0).contains(&phase) {
+            return Err(StateError::Invalid("audio phase"));
+        }
+        Ok(Self { active, frequency, volume, phase, pattern: has_pattern.then_some(pattern), pitch })
+    }
+
+    // fills `buf` with mono samples in -1.0..=1.0, silence while the buzzer is off
+    pub fn fill(&mut self, buf: &mut [f32], sample_rate: u32) {
+        match self.pattern {
+            Some(pattern) => self.fill_pattern(buf, sample_rate, pattern),
+            None => self.fill_square(buf, sample_rate),
+        }
+    }
+
+    fn fill_square(&mut self, buf: &mut [f32], sample_rate: u32) {
+        let step = self.frequency / sample_rate as f32;
+
+        for sample in buf.iter_mut() {
+            *sample = if !self.active {
+                0.0
+            } else if self.phase < 0.5 {
+                self.volume
+            } else {
+                -self.volume
+            };
+
+            self.phase = (self.phase + step) % 1.0;
+        }
+    }
+
+    // plays the 128 bit pattern at 4000 * 2^((pitch - 64) / 48) bits per second
+    fn fill_pattern(&mut self, buf: &mut [f32], sample_rate: u32, pattern: [u8; 16]) {
+        let rate = 4000.0 * 2f32.powf((self.pitch as f32 - 64.0) / 48.0);
+        let step = rate / sample_rate as f32;
+
+        for sample in buf.iter_mut() {
+            let pos = self.phase as usize;
+            let bit = (pattern[pos / 8] >> (7 - pos % 8)) & 0x01;
+
+            *sample = if !self.active {
+                0.0
+            } else if bit == 1 {
+                self.volume
+            } else {
+                -self.volume
+            };
+
+            self.phase = (self.phase + step) % 128.0;
+        }
+    }
+}
+
+// writes mono samples as a 16-bit PCM WAV file
+pub fn write_wav<W: Write>(mut out: W, samples: &[f32], sample_rate: u32) -> io::Result<()> {
+    let data_len = (samples.len() * 2) as u32;
+
+    out.write_all(b"RIFF")?;
+    out.write_all(&(36 + data_len).to_le_bytes())?;
+    out.write_all(b"WAVE")?;
+
+    out.write_all(b"fmt ")?;
+    out.write_all(&16u32.to_le_bytes())?;
+    out.write_all(&1u16.to_le_bytes())?; // PCM
+    out.write_all(&1u16.to_le_bytes())?; // mono
+    out.write_all(&sample_rate.to_le_bytes())?;
+    out.write_all(&(sample_rate * 2).to_le_bytes())?; // byte rate
+    out.write_all(&2u16.to_le_bytes())?; // block align
+    out.write_all(&16u16.to_le_bytes())?; // bits per sample
+
+    out.write_all(b"data")?;
+    out.write_all(&data_len.to_le_bytes())?;
+    for sample in samples {
+        let pcm = (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+        out.write_all(&pcm.to_le_bytes())?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::computer::Computer;
+    use crate::quirks::Quirks;
+
+    #[test]
+    fn silent_while_the_sound_timer_is_zero() {
+        let mut comp = Computer::with_seed(Vec::new(), Quirks::default(), 0);
+        let mut buf = [1.0; 64];
+        comp.audio_mut().fill(&mut buf, 8000);
+        assert!(buf.iter().all(|s| *s == 0.0));
+
+        comp.cpu_mut().st = 5;
+        comp.audio_mut().fill(&mut buf, 8000);
+        assert!(buf.iter().all(|s| s.abs() == 0.25));
+    }
+
+    #[test]
+    fn plays_a_square_wave() {
+        let mut audio = AudioSource::new();
+        audio.set_active(true);
+        audio.set_frequency(1000.0);
+        audio.set_volume(0.5);
+
+        // 8 samples per period, half of them high
+        let mut buf = [0.0; 16];
+        audio.fill(&mut buf, 8000);
+        let period = [0.5, 0.5, 0.5, 0.5, -0.5, -0.5, -0.5, -0.5];
+        assert_eq!(buf[..8], period);
+        assert_eq!(buf[8..], period);
+
+        // and carries on where it left off
+        let mut buf = [0.0; 2];
+        audio.fill(&mut buf, 8000);
+        assert_eq!(buf, [0.5, 0.5]);
+    }
+
+    #[test]
+    fn plays_xo_chip_patterns_bit_by_bit() {
+        let mut audio = AudioSource::new();
+        audio.set_active(true);
+        let mut pattern = [0xAA; 16];
+        pattern[0] = 0xF0;
+        audio.set_pattern(pattern);
+
+        // pitch 64 is 4000 bits a second
+        let mut buf = [0.0; 12];
+        audio.fill(&mut buf, 4000);
+        assert_eq!(buf, [0.25, 0.25, 0.25, 0.25, -0.25, -0.25, -0.25, -0.25, 0.25, -0.25, 0.25, -0.25]);
+    }
+
+    #[test]
+    fn writes_a_wav_header_and_clamped_samples() {
+        let mut out = Vec::new();
+        write_wav(&mut out, &[0.0, 1.0, -1.0, 2.0], 8000).unwrap();
+
+        let u16_at = |pos: usize| u16::from_le_bytes([out[pos], out[pos + 1]]);
+        let u32_at = |pos: usize| u32::from_le_bytes(out[pos..pos + 4].try_into().unwrap());
+
+        assert_eq!(out.len(), 44 + 8);
+        assert_eq!((&out[0..4], u32_at(4), &out[8..12]), (&b"RIFF"[..], 36 + 8, &b"WAVE"[..]));
+        assert_eq!((&out[12..16], u32_at(16), u16_at(20), u16_at(22)), (&b"fmt "[..], 16, 1, 1));
+        assert_eq!((u32_at(24), u32_at(28), u16_at(32), u16_at(34)), (8000, 16000, 2, 16));
+        assert_eq!((&out[36..40], u32_at(40)), (&b"data"[..], 8));
+
+        let samples: Vec<i16> = out[44..].chunks(2).map(|b| i16::from_le_bytes([b[0], b[1]])).collect();
+        assert_eq!(samples, [0, i16::MAX, -i16::MAX, i16::MAX]);
+    }
+}
@@ -0,0 +1,219 @@
+use std::{env, fs, process};
+use chip8_rs::audio;
+use chip8_rs::computer::Computer;
+use chip8_rs::movie::{Movie, Replay};
+use chip8_rs::quirks::Quirks;
+use chip8_rs::trace::{BinaryTraceWriter, JsonTraceWriter, TraceSink};
+
+const SAMPLE_RATE: u32 = 44100;
+
+const USAGE: &str = "usage: chip8-headless <rom> [options]
+
+options:
+  --frames N       run N frames at 60 Hz (default 600)
+  --cycles N       run N instructions instead of a number of frames
+  --cpf N          instructions per frame (default 10)
+  --quirks NAME    quirks profile: default, vip, chip48, schip, xo
+  --seed N         seed for the random number generator (default: random)
+  --input FILE     a movie: scripted input, one \"<frame> press|release <key>\" per line,
+                   its seed, cpf and quirks lines apply unless given as options
+  --fb FILE        write the final framebuffer as a PBM image
+  --regs FILE      write the final register state as text
+  --mem FILE       write the final memory contents as raw bytes
+  --wav FILE       write the buzzer output as a WAV file
+  --trace FILE     log every instruction as JSON lines
+  --trace-bin FILE log every instruction in the compact binary trace format";
+
+struct Options {
+    rom: String,
+    frames: usize,
+    cycles: Option<usize>,
+    cycles_per_frame: Option<usize>,
+    quirks: Option<Quirks>,
+    seed: Option<u64>,
+    input: Option<String>,
+    fb: Option<String>,
+    regs: Option<String>,
+    mem: Option<String>,
+    wav: Option<String>,
+    trace: Option<String>,
+    trace_bin: Option<String>,
+}
+
+fn parse_args(args: &[String]) -> Result<Options, String> {
+    let mut opts = Options {
+        rom: String::new(),
+        frames: 600,
+        cycles: None,
+        cycles_per_frame: None,
+        quirks: None,
+        seed: None,
+        input: None,
+        fb: None,
+        regs: None,
+        mem: None,
+        wav: None,
+        trace: None,
+        trace_bin: None,
+    };
+
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        if !arg.starts_with("--") {
+            opts.rom = arg.clone();
+            continue;
+        }
+
+        let value = iter.next().ok_or(format!("missing value for {}", arg))?;
+        let number = || value.parse::<usize>().map_err(|_| format!("invalid number for {}: {}", arg, value));
+
+        match arg.as_str() {
+            "--frames" => opts.frames = number()?,
+            "--cycles" => opts.cycles = Some(number()?),
+            "--cpf" => opts.cycles_per_frame = Some(number()?),
+            "--quirks" => opts.quirks = Some(value.parse()?),
+            "--seed" => opts.seed = Some(value.parse().map_err(|_| format!("invalid number for {}: {}", arg, value))?),
+            "--input" => opts.input = Some(value.clone()),
+            "--fb" => opts.fb = Some(value.clone()),
+            "--regs" => opts.regs = Some(value.clone()),
+            "--mem" => opts.mem = Some(value.clone()),
+            "--wav" => opts.wav = Some(value.clone()),
+            "--trace" => opts.trace = Some(value.clone()),
+            "--trace-bin" => opts.trace_bin = Some(value.clone()),
+            _ => return Err(format!("unknown option {}", arg)),
+        }
+    }
+
+    if opts.rom.is_empty() {
+        return Err("no rom given".to_string());
+    }
+    if opts.trace.is_some() && opts.trace_bin.is_some() {
+        return Err("--trace and --trace-bin can't be used together".to_string());
+    }
+    Ok(opts)
+}
+
+fn registers(comp: &Computer) -> String {
+    let cpu = comp.cpu();
+    let mut out = String::new();
+
+    for (n, v) in cpu.v.iter().enumerate() {
+        out.push_str(&format!("V{:X}={:#04x}\n", n, v));
+    }
+    out.push_str(&format!("I={:#06x}\n", cpu.i));
+    out.push_str(&format!("PC={:#06x}\n", cpu.pc));
+    out.push_str(&format!("SP={}\n", cpu.sp));
+    out.push_str(&format!("DT={}\n", cpu.dt));
+    out.push_str(&format!("ST={}\n", cpu.st));
+
+    let stack: Vec<String> = cpu.stack().iter().map(|a| format!("{:#06x}", a)).collect();
+    out.push_str(&format!("STACK=[{}]\n", stack.join(", ")));
+    out
+}
+
+fn run(opts: &Options) -> Result<bool, String> {
+    let rom = fs::read(&opts.rom).map_err(|e| format!("{}: {}", opts.rom, e))?;
+    let mut movie = match &opts.input {
+        Some(path) => Movie::parse(&fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?)?,
+        None => Movie::default(),
+    };
+    movie.seed = opts.seed.or(movie.seed);
+    movie.quirks = opts.quirks.or(movie.quirks);
+
+    let cycles_per_frame = opts.cycles_per_frame.or(movie.cycles_per_frame).unwrap_or(10);
+    if cycles_per_frame == 0 {
+        return Err("--cpf must be at least 1".to_string());
+    }
+
+    let mut comp = movie.computer(rom, Quirks::default());
+    let mut replay = Replay::new(&movie);
+
+    let trace_path = opts.trace.as_ref().or(opts.trace_bin.as_ref());
+    if let Some(path) = trace_path {
+        let file = std::io::BufWriter::new(fs::File::create(path).map_err(|e| format!("{}: {}", path, e))?);
+        let sink: Box<dyn TraceSink> = match opts.trace {
+            Some(_) => Box::new(JsonTraceWriter::new(file)),
+            None => Box::new(BinaryTraceWriter::new(file)),
+        };
+        comp.set_trace_sink(sink);
+    }
+    let mut samples = Vec::new();
+    let mut remaining = match opts.cycles {
+        Some(cycles) => cycles,
+        None => opts.frames.checked_mul(cycles_per_frame).ok_or("--frames times --cpf is too large")?,
+    };
+    let mut ok = true;
+
+    while remaining > 0 && !comp.is_halted() {
+        replay.apply(&mut comp);
+
+        let cycles = remaining.min(cycles_per_frame);
+        let executed = match comp.run_frame(cycles) {
+            Ok(executed) => executed,
+            Err(e) => {
+                eprintln!("frame {}: {}", comp.frames(), e);
+                ok = false;
+                break;
+            },
+        };
+
+        // --cycles counts instructions, frames that stop early at a vblank wait
+        // only use part of their budget
+        if opts.cycles.is_none() {
+            remaining -= cycles;
+        }
+        else if executed > 0 || !replay.is_finished() {
+            remaining -= executed;
+        }
+        else {
+            eprintln!("stopped after {} instructions waiting for a key", comp.cycles());
+            break;
+        }
+
+        if opts.wav.is_some() {
+            let mut buf = vec![0.0; SAMPLE_RATE as usize / 60];
+            comp.audio_mut().fill(&mut buf, SAMPLE_RATE);
+            samples.extend(buf);
+        }
+    }
+
+    if let (Some(path), Some(mut sink)) = (trace_path, comp.take_trace_sink()) {
+        sink.finish().map_err(|e| format!("{}: {}", path, e))?;
+    }
+    if let Some(path) = &opts.fb {
+        fs::write(path, comp.display().to_pbm()).map_err(|e| format!("{}: {}", path, e))?;
+    }
+    if let Some(path) = &opts.regs {
+        fs::write(path, registers(&comp)).map_err(|e| format!("{}: {}", path, e))?;
+    }
+    if let Some(path) = &opts.mem {
+        fs::write(path, comp.dump()).map_err(|e| format!("{}: {}", path, e))?;
+    }
+    if let Some(path) = &opts.wav {
+        let file = fs::File::create(path).map_err(|e| format!("{}: {}", path, e))?;
+        audio::write_wav(std::io::BufWriter::new(file), &samples, SAMPLE_RATE).map_err(|e| format!("{}: {}", path, e))?;
+    }
+
+    Ok(ok)
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+
+    let opts = match parse_args(&args) {
+        Ok(opts) => opts,
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, USAGE);
+            process::exit(2);
+        }
+    };
+
+    match run(&opts) {
+        Ok(true) => {},
+        Ok(false) => process::exit(1),
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(2);
+        }
+    }
+}
@@ -1,513 +1,919 @@
-use std::usize;
-use rand::Rng;
+use crate::audio::AudioSource;
 use crate::cpu::Cpu;
 use crate::display::Display;
+use crate::error::EmulatorError;
+use crate::instruction::{decode, Instruction};
+use crate::memory::{Access, AccessKind, AccessPolicy, Memory};
+use crate::movie::InputEvent;
+use crate::quirks::{IndexIncrement, Quirks};
+use crate::rng::{RandomSource, Xorshift};
+use crate::state::{self, StateError, StateReader, StateWriter};
+use crate::trace::{TraceRecord, TraceSink};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StepOutcome {
+    Executed(Instruction),
+    WaitingForKey,
+    WaitingForVblank,
+    Exited,
+}
+
+const BIG_FONT_ADDR: u16 = 0x50;
+
+const FONT: [u8; 80] =
+    [0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
+    0x20, 0x60, 0x20, 0x20, 0x70, // 1
+    0xF0, 0x10, 0xF0, 0x80, 0xF0, // 2
+    0xF0, 0x10, 0xF0, 0x10, 0xF0, // 3
+    0x90, 0x90, 0xF0, 0x10, 0x10, // 4
+    0xF0, 0x80, 0xF0, 0x10, 0xF0, // 5
+    0xF0, 0x80, 0xF0, 0x90, 0xF0, // 6
+    0xF0, 0x10, 0x20, 0x40, 0x40, // 7
+    0xF0, 0x90, 0xF0, 0x90, 0xF0, // 8
+    0xF0, 0x90, 0xF0, 0x10, 0xF0, // 9
+    0xF0, 0x90, 0xF0, 0x90, 0x90, // A
+    0xE0, 0x90, 0xE0, 0x90, 0xE0, // B
+    0xF0, 0x80, 0x80, 0x80, 0xF0, // C
+    0xE0, 0x90, 0x90, 0x90, 0xE0, // D
+    0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
+    0xF0, 0x80, 0xF0, 0x80, 0x80]; //F
+
+const BIG_FONT: [u8; 160] =
+    [0xFF, 0xFF, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, // 0
+    0x18, 0x78, 0x78, 0x18, 0x18, 0x18, 0x18, 0x18, 0xFF, 0xFF, // 1
+    0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // 2
+    0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 3
+    0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0x03, 0x03, // 4
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 5
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 6
+    0xFF, 0xFF, 0x03, 0x03, 0x06, 0x0C, 0x18, 0x18, 0x18, 0x18, // 7
+    0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 8
+    0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 9
+    0x7E, 0xFF, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3, // A
+    0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, // B
+    0x3C, 0xFF, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0xFF, 0x3C, // C
+    0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC, // D
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // E
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xC0, 0xC0]; //F
+
+// fonts at 0x000 and BIG_FONT_ADDR, the rom at 0x200
+fn boot_memory(size: usize, rom: &[u8]) -> Memory {
+    let mut mem = Memory::new(size);
+    mem.load(0x000, &FONT);
+    mem.load(BIG_FONT_ADDR as usize, &BIG_FONT);
+    mem.load(0x200, rom);
+
+    mem
+}
 
 pub struct Computer {
-    memory: [u8; 4096],
+    rom: Vec<u8>,
+    memory: Memory,
     cpu: Cpu,
     display: Display,
-    keyboard: [bool; 16]
+    keyboard: [bool; 16],
+    audio: AudioSource,
+    quirks: Quirks,
+    vblank: bool,
+    rpl: [u8; 16],
+    halted: bool,
+    log_accesses: bool,
+    accesses: Vec<Access>,
+    trace: Option<Box<dyn TraceSink>>,
+    cycles: u64,
+    frames: u64,
+    rng: Box<dyn RandomSource>,
+    recording: Option<Vec<InputEvent>>,
 }
 
 impl Computer {
+    // back to the state the computer was created in, rom and fonts reloaded;
+    // quirks, memory policy, random source, trace sink and recording stay as they are
     pub fn reset(&mut self) {
+        let mut memory = boot_memory(self.quirks.memory_size, &self.rom);
+        memory.set_policy(self.memory.policy());
+        self.memory = memory;
+
         self.cpu = Cpu::new();
         self.display = Display::new();
+        self.keyboard = [false; 16];
+        self.audio = AudioSource::new();
+        self.vblank = true;
+        self.rpl = [0; 16];
+        self.halted = false;
+        self.accesses.clear();
+        self.cycles = 0;
+        self.frames = 0;
+    }
 
-        for n in 0..16 {
-            self.keyboard[n] = false;
-        }
-        for n in 0..4096 {
-            self.memory[n] = 0;
-        }
+    pub fn is_halted(&self) -> bool {
+        self.halted
     }
 
     pub fn dump(&self) -> Vec<u8> {
-        self.memory.to_vec()
-    }
-
-    pub fn new(data: Vec<u8> ) -> Self {
-        let mut mem = [0; 4096];
-
-        let sprites = 
-        [0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
-        0x20, 0x60, 0x20, 0x20, 0x70, // 1
-        0xF0, 0x10, 0xF0, 0x80, 0xF0, // 2
-        0xF0, 0x10, 0xF0, 0x10, 0xF0, // 3
-        0x90, 0x90, 0xF0, 0x10, 0x10, // 4
-        0xF0, 0x80, 0xF0, 0x10, 0xF0, // 5
-        0xF0, 0x80, 0xF0, 0x90, 0xF0, // 6
-        0xF0, 0x10, 0x20, 0x40, 0x40, // 7
-        0xF0, 0x90, 0xF0, 0x90, 0xF0, // 8
-        0xF0, 0x90, 0xF0, 0x10, 0xF0, // 9
-        0xF0, 0x90, 0xF0, 0x90, 0x90, // A
-        0xE0, 0x90, 0xE0, 0x90, 0xE0, // B
-        0xF0, 0x80, 0x80, 0x80, 0xF0, // C
-        0xE0, 0x90, 0x90, 0x90, 0xE0, // D
-        0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
-        0xF0, 0x80, 0xF0, 0x80, 0x80]; //F
-
-        for (pos, e) in sprites.iter().enumerate() {
-            mem[pos] = *e; 
-        }
+        self.memory.as_slice().to_vec()
+    }
 
-        for (pos, e) in data.iter().enumerate() {
-            mem[0x200 + pos] = *e;
-        }
+    // snapshot of the whole machine, see load_state(); the trace sink and
+    // debugger access log are not part of it
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut out = StateWriter::new();
+        out.bytes(state::MAGIC);
+        out.u16(state::VERSION);
+
+        self.quirks.write_state(&mut out);
+        self.cpu.write_state(&mut out);
+        self.memory.write_state(&mut out);
+        self.display.write_state(&mut out);
+        self.audio.write_state(&mut out);
+
+        let keys = self.keyboard.iter().enumerate().fold(0u16, |keys, (n, pressed)| keys | ((*pressed as u16) << n));
+        out.u16(keys);
+        out.bytes(&self.rpl);
+        out.bool(self.vblank);
+        out.bool(self.halted);
+        out.u64(self.cycles);
+        out.u64(self.frames);
+
+        let rng = self.rng.save();
+        out.u16(rng.len() as u16);
+        out.bytes(&rng);
+
+        out.into_inner()
+    }
 
+    // restores a save_state() snapshot; on error the computer is left untouched
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), StateError> {
+        let mut r = StateReader::new(data);
+        if r.bytes(state::MAGIC.len()).ok() != Some(&state::MAGIC[..]) {
+            return Err(StateError::BadMagic);
+        }
+        let version = r.u16()?;
+        if version != state::VERSION {
+            return Err(StateError::UnsupportedVersion(version));
+        }
+
+        let quirks = Quirks::read_state(&mut r)?;
+        let cpu = Cpu::read_state(&mut r)?;
+        let memory = Memory::read_state(&mut r)?;
+        let display = Display::read_state(&mut r)?;
+        let audio = AudioSource::read_state(&mut r)?;
+
+        let keys = r.u16()?;
+        let rpl: [u8; 16] = r.bytes(16)?.try_into().unwrap();
+        let vblank = r.bool()?;
+        let halted = r.bool()?;
+        let cycles = r.u64()?;
+        let frames = r.u64()?;
+        let rng_len = r.u16()? as usize;
+        let rng = r.bytes(rng_len)?;
+
+        if !r.is_empty() {
+            return Err(StateError::Invalid("trailing data"));
+        }
+        self.rng.restore(rng)?;
+
+        self.quirks = quirks;
+        self.cpu = cpu;
+        self.memory = memory;
+        self.display = display;
+        self.audio = audio;
+        for (n, key) in self.keyboard.iter_mut().enumerate() {
+            *key = keys & (1 << n) != 0;
+        }
+        self.rpl = rpl;
+        self.vblank = vblank;
+        self.halted = halted;
+        self.cycles = cycles;
+        self.frames = frames;
+        self.accesses.clear();
+
+        // going back in time drops the input recorded for the discarded future
+        if let Some(events) = &mut self.recording {
+            events.retain(|e| e.frame <= frames);
+        }
+
+        Ok(())
+    }
 
-        Self {
-            cpu: Cpu::new(),
-            display: Display::new(),
-            memory: mem,
-            keyboard: [false; 16]
+    // after Fx55/Fx65 moved registers 0..=x
+    fn increment_i(&mut self, x: u8) {
+        match self.quirks.load_store_i {
+            IndexIncrement::Unchanged => {},
+            IndexIncrement::ByX => self.cpu.i = self.cpu.i.wrapping_add(x as u16),
+            IndexIncrement::ByXPlusOne => self.cpu.i = self.cpu.i.wrapping_add(x as u16 + 1),
         }
     }
 
-    pub fn set_key(&mut self, k: piston::Key){
-        match k {
-            piston::Key::D1 => {
-                self.keyboard[0x00] = true;
-            },
-            piston::Key::D2 => {
-                self.keyboard[0x01] = true;
-            },
-            piston::Key::D3 => {
-                self.keyboard[0x02] = true;
-            },
-            piston::Key::D4 => {
-                self.keyboard[0x03] = true;
-            },
-            piston::Key::Q => {
-                self.keyboard[0x04] = true;
-            },
-            piston::Key::W => {
-                self.keyboard[0x05] = true;
-            },
-            piston::Key::E => {
-                self.keyboard[0x06] = true;
-            },
-            piston::Key::R => {
-                self.keyboard[0x07] = true;
-            },
-            piston::Key::A => {
-                self.keyboard[0x08] = true;
-            },
-            piston::Key::S => {
-                self.keyboard[0x09] = true;
-            },
-            piston::Key::D => {
-                self.keyboard[0x0A] = true;
-            },
-            piston::Key::F => {
-                self.keyboard[0x0B] = true;
-            },
-            piston::Key::Z => {
-                self.keyboard[0x0C] = true;
-            },
-            piston::Key::X => {
-                self.keyboard[0x0D] = true;
-            },
-            piston::Key::C => {
-                self.keyboard[0x0E] = true;
-            },
-            piston::Key::V => {
-                self.keyboard[0x0F] = true;
-            },
-            _ => {}
-        }
+    pub fn memory_policy(&self) -> AccessPolicy {
+        self.memory.policy()
     }
 
-    pub fn reset_key(&mut self, k: piston::Key){
-        match k {
-            piston::Key::D1 => {
-                self.keyboard[0x00] = false;
-            },
-            piston::Key::D2 => {
-                self.keyboard[0x01] = false;
-            },
-            piston::Key::D3 => {
-                self.keyboard[0x02] = false;
-            },
-            piston::Key::D4 => {
-                self.keyboard[0x03] = false;
-            },
-            piston::Key::Q => {
-                self.keyboard[0x04] = false;
-            },
-            piston::Key::W => {
-                self.keyboard[0x05] = false;
-            },
-            piston::Key::E => {
-                self.keyboard[0x06] = false;
-            },
-            piston::Key::R => {
-                self.keyboard[0x07] = false;
-            },
-            piston::Key::A => {
-                self.keyboard[0x08] = false;
-            },
-            piston::Key::S => {
-                self.keyboard[0x09] = false;
-            },
-            piston::Key::D => {
-                self.keyboard[0x0A] = false;
-            },
-            piston::Key::F => {
-                self.keyboard[0x0B] = false;
-            },
-            piston::Key::Z => {
-                self.keyboard[0x0C] = false;
-            },
-            piston::Key::X => {
-                self.keyboard[0x0D] = false;
-            },
-            piston::Key::C => {
-                self.keyboard[0x0E] = false;
-            },
-            piston::Key::V => {
-                self.keyboard[0x0F] = false;
-            },
-            _ => {}
-        }
+    pub fn set_memory_policy(&mut self, policy: AccessPolicy) {
+        self.memory.set_policy(policy);
     }
 
-    pub fn tick(&mut self) {
-        let m1 = self.memory[self.cpu.pc as usize];
-        let m2: u8 = self.memory[(self.cpu.pc + 1) as usize];
-        let val: u16 = ((m1 as u16) << 8) | (m2 as u16);
+    // records the memory reads and writes instructions make, apart from fetches
+    pub fn set_access_log(&mut self, enabled: bool) {
+        self.log_accesses = enabled;
+        self.accesses.clear();
+    }
 
-        self.cpu.pc += 2;
+    pub fn take_accesses(&mut self) -> Vec<Access> {
+        std::mem::take(&mut self.accesses)
+    }
 
-        //println!("pc: {} - {:#06x}", self.cpu.pc, val);
+    // every completed instruction is reported to the sink until it is taken back
+    pub fn set_trace_sink(&mut self, sink: Box<dyn TraceSink>) {
+        self.trace = Some(sink);
+    }
 
-        if val == 0x00E0 { // cls
-            println!("cls");
-            self.display.clear();
-        }
-        else if val == 0x00EE { //ret
-            match self.cpu.stack_pop() {
-                Ok(stack_val) => {
-                    self.cpu.pc = stack_val;
-                },
-                Err(e) => {
-                    println!("{}", e);
-                }
-            }
-        }
-        else if val >= 0x1000 && val < 0x2000 { // jump
-            self.cpu.pc = val & 0x0FFF;
-            let ct: Vec<u8> = self.display().dump().into_iter().map(|m| m as u8).collect();
-            //fs::write("fb.dat", ct).unwrap();
-        }
-        else if val >= 0x2000 && val < 0x3000 { // call
-            let address = val & 0x0FFF;
-
-            match self.cpu.stack_push(self.cpu.pc) {
-                Ok(_) => {
-                    self.cpu.pc = address;
-                },
-                Err(e) => {
-                    println!("{}", e);
-                }
-            }
-            
-        }
-        else if val >= 0x3000 && val < 0x4000 { //skip instruction
-            let x = (val & 0x0F00) >> 8 as u8;
-            let kk = (val & 0x00FF) as u8;
+    pub fn take_trace_sink(&mut self) -> Option<Box<dyn TraceSink>> {
+        self.trace.take()
+    }
 
-            if self.cpu.v[x as usize] == kk {
-                self.cpu.pc += 2;
-            }
+    // instructions completed since the computer was created
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
 
-        }
-        else if val >= 0x4000 && val < 0x5000 { //skip instruction if not eq
-            let x = (val & 0x0F00) >> 8 as u8;
-            let kk = (val & 0x00FF) as u8;
+    // timer ticks, i.e. 60 Hz frames, since the computer was created
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
 
-            if self.cpu.v[x as usize] != kk {
-                self.cpu.pc += 2;
-            }
+    // records every key change from now on with the frame it happened on
+    pub fn start_recording(&mut self) {
+        self.recording = Some(Vec::new());
+    }
 
-        }
-        else if val >= 0x5000 && val < 0x6000 { //skip instruction if not eq
-            let x = (val & 0x0F00) >> 8 as u8;
-            let y = (val & 0x00F0) >> 4 as u8;
+    pub fn stop_recording(&mut self) -> Vec<InputEvent> {
+        self.recording.take().unwrap_or_default()
+    }
 
-            if self.cpu.v[x as usize] == self.cpu.v[y as usize] {
-                self.cpu.pc += 2;
-            }
+    // replaces where Cxkk gets its numbers from
+    pub fn set_random_source(&mut self, source: Box<dyn RandomSource>) {
+        self.rng = source;
+    }
 
-        }
-        else if val >= 0x6000 && val < 0x7000 { // set register vx
+    pub fn quirks(&self) -> Quirks {
+        self.quirks
+    }
 
-            //println!("set vx");
+    // memory_size only takes effect when the computer is created
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.quirks = quirks;
+    }
 
-            let x = val & 0x0FFF;
-            let idx = x >> 8;
+    // RND results differ from run to run, see with_seed()
+    pub fn new(data: Vec<u8>, quirks: Quirks) -> Self {
+        Self::with_random_source(data, quirks, Box::new(Xorshift::from_entropy()))
+    }
 
-            self.cpu.v[idx as usize] = (val & 0x00FF) as u8;
-        }
-        else if val >= 0x7000 && val < 0x8000 { // add register vx
+    // same seed, same random numbers
+    pub fn with_seed(data: Vec<u8>, quirks: Quirks, seed: u64) -> Self {
+        Self::with_random_source(data, quirks, Box::new(Xorshift::new(seed)))
+    }
 
-            let x = ((val & 0x0F00) >> 8) as u8;
-            let kk = (val & 0x00FF) as u8;
-            let sum = self.cpu.v[x as usize] as u16 + kk as u16;
-            self.cpu.v[x as usize] = (sum & 0x00FF) as u8;
+    pub fn with_random_source(data: Vec<u8>, quirks: Quirks, rng: Box<dyn RandomSource>) -> Self {
+        Self {
+            cpu: Cpu::new(),
+            display: Display::new(),
+            memory: boot_memory(quirks.memory_size, &data),
+            rom: data,
+            keyboard: [false; 16],
+            audio: AudioSource::new(),
+            quirks,
+            vblank: true,
+            rpl: [0; 16],
+            halted: false,
+            log_accesses: false,
+            accesses: Vec::new(),
+            trace: None,
+            cycles: 0,
+            frames: 0,
+            rng,
+            recording: None,
         }
-        else if val >= 0x8000 && val < 0x9000 && (val & 0x000F) == 0x0000  { //ld vx vy
-            let x = ((val & 0x0F00) >> 8) as u8;
-            let y = ((val & 0x00F0) >> 4) as u8;
+    }
 
-            self.cpu.v[x as usize] = self.cpu.v[y as usize];
-        }
-        else if val >= 0x8000 && val < 0x9000 && (val & 0x000F) == 0x0001  { //or vx vy
-            let x = ((val & 0x0F00) >> 8) as u8;
-            let y = ((val & 0x00F0) >> 4) as u8;
+    // hex keypad input, keys 0x0-0xF
+    pub fn press(&mut self, key: u8) {
+        self.set_key(key, true);
+    }
 
-            self.cpu.v[x as usize] = self.cpu.v[x as usize] | self.cpu.v[y as usize];
-        }
-        else if val >= 0x8000 && val < 0x9000 && (val & 0x000F) == 0x0002  { //and vx vy
-            let x = ((val & 0x0F00) >> 8) as u8;
-            let y = ((val & 0x00F0) >> 4) as u8;
+    pub fn release(&mut self, key: u8) {
+        self.set_key(key, false);
+    }
 
-            self.cpu.v[x as usize] = self.cpu.v[x as usize] & self.cpu.v[y as usize];
+    fn set_key(&mut self, key: u8, pressed: bool) {
+        let key = key & 0x0F;
+        if let Some(events) = &mut self.recording {
+            if self.keyboard[key as usize] != pressed {
+                events.push(InputEvent { frame: self.frames, key, pressed });
+            }
         }
-        else if val >= 0x8000 && val < 0x9000 && (val & 0x000F) == 0x0003  { //xor vx vy
-            let x = ((val & 0x0F00) >> 8) as u8;
-            let y = ((val & 0x00F0) >> 4) as u8;
+        self.keyboard[key as usize] = pressed;
+    }
 
-            self.cpu.v[x as usize] = self.cpu.v[x as usize] ^ self.cpu.v[y as usize];
+    // executes up to `cycles_per_frame` instructions, fewer if one waits for the
+    // next frame, then counts the timers down once (call at 60 Hz); returns how
+    // many instructions completed
+    pub fn run_frame(&mut self, cycles_per_frame: usize) -> Result<usize, EmulatorError> {
+        let start = self.cycles;
+        for _ in 0..cycles_per_frame {
+            match self.tick()? {
+                StepOutcome::WaitingForVblank | StepOutcome::Exited => break,
+                _ => {}
+            }
         }
-        else if val >= 0x8000 && val < 0x9000 && (val & 0x000F) == 0x0004  { //add vx vy
-            let x = ((val & 0x0F00) >> 8) as u8;
-            let y = ((val & 0x00F0) >> 4) as u8;
+        self.tick_timers();
 
-            if (self.cpu.v[x as usize] as u16 + self.cpu.v[y as usize] as u16) > 255 {
-                self.cpu.v[0x000F] = 1;
-            }
-            else {
-                self.cpu.v[0x000F] = 0;
-            }
+        Ok((self.cycles - start) as usize)
+    }
 
-            self.cpu.v[x as usize]  = ((self.cpu.v[x as usize] as u16 + self.cpu.v[y as usize] as u16) & 0x00FF) as u8;
-        }
-        else if val >= 0x8000 && val < 0x9000 && (val & 0x000F) == 0x0005  { //sub vx vy 
-            let x = ((val & 0x0F00) >> 8) as u8;
-            let y = ((val & 0x00F0) >> 4) as u8;
+    pub fn tick_timers(&mut self) {
+        self.cpu.dt = self.cpu.dt.saturating_sub(1);
+        self.cpu.st = self.cpu.st.saturating_sub(1);
+        self.vblank = true;
+        self.frames += 1;
+    }
 
-            if self.cpu.v[x as usize] >= self.cpu.v[y as usize] {
-                self.cpu.v[0x000F] = 1;
-                self.cpu.v[x as usize] = self.cpu.v[x as usize] - self.cpu.v[y as usize];
-            }
-            else {
-                self.cpu.v[0x000F] = 0;
-                self.cpu.v[x as usize] = ((self.cpu.v[x as usize] as u16) + 0x0100 - (self.cpu.v[y as usize] as u16)) as u8;
-            }
+    pub fn tick(&mut self) -> Result<StepOutcome, EmulatorError> {
+        if self.halted {
+            return Ok(StepOutcome::Exited);
+        }
 
+        let pc = self.cpu.pc;
+        if pc as usize >= self.memory.len() {
+            return Err(EmulatorError::PcOutOfRange { pc });
         }
-        else if val >= 0x8000 && val < 0x9000 && (val & 0x000F) == 0x0006  { //shr vx vy 
-            let x = ((val & 0x0F00) >> 8) as u8;
-            //let y = ((val & 0x00F0) >> 4) as u8;
 
-            if self.cpu.v[x as usize] & 0x0001 == 0x0001 {
-                self.cpu.v[0x000F] = 1;
-            }
-            else {
-                self.cpu.v[0x000F] = 0;
-            }
+        let val = self.fetch(pc)?;
 
-            self.cpu.v[x as usize] = self.cpu.v[x as usize] >> 1;
-        }
-        else if val >= 0x8000 && val < 0x9000 && (val & 0x000F) == 0x0007  { //sub vy vx 
-            let x = ((val & 0x0F00) >> 8) as u8;
-            let y = ((val & 0x00F0) >> 4) as u8;
+        let instruction = decode(val).map_err(|_| EmulatorError::UnknownOpcode { pc, opcode: val })?;
 
-            if self.cpu.v[y as usize] >= self.cpu.v[x as usize] {
-                self.cpu.v[0x000F] = 1;
-                self.cpu.v[x as usize] = self.cpu.v[y as usize] - self.cpu.v[x as usize];
-            }
-            else {
-                self.cpu.v[0x000F] = 0;
-                self.cpu.v[x as usize] = ((self.cpu.v[y as usize] as u16) + 0x0100 - (self.cpu.v[x as usize] as u16)) as u8;
-            }
+        let before = self.cpu;
+        let logged = self.accesses.len();
+        self.cpu.pc = self.cpu.pc.wrapping_add(2);
 
-        }
-        else if val >= 0x8000 && val < 0x9000 && (val & 0x000F) == 0x000E  { //shl vx vy 
-            let x = ((val & 0x0F00) >> 8) as u8;
-            //let y = ((val & 0x00F0) >> 4) as u8;
+        // leave pc on the faulting instruction so the caller can inspect it
+        let outcome = self.execute(instruction).inspect_err(|_| self.cpu.pc = pc)?;
 
-            if (self.cpu.v[x as usize] >> 7) & 0x0001 == 0x0001 {
-                self.cpu.v[0x000F] = 1;
-            }
-            else {
-                self.cpu.v[0x000F] = 0;
+        if matches!(outcome, StepOutcome::Executed(_)) || instruction == Instruction::Exit {
+            self.cycles += 1;
+            if self.trace.is_some() {
+                self.trace_instruction(before, val, instruction, logged);
             }
-
-            self.cpu.v[x as usize] = self.cpu.v[x as usize] << 1;
         }
-        else if val >= 0x9000 && val < 0xA000 && (val & 0x000F) == 0x0000 { // sne vx vy
-            let x = ((val & 0x0F00) >> 8) as u8;
-            let y = ((val & 0x00F0) >> 4) as u8;
 
-            if self.cpu.v[x as usize] != self.cpu.v[y as usize] {
-                self.cpu.pc += 2;
-            }
+        Ok(outcome)
+    }
 
+    fn trace_instruction(&mut self, before: Cpu, opcode: u16, instruction: Instruction, logged: usize) {
+        let writes = self.accesses[logged..].iter()
+            .filter(|a| a.kind == AccessKind::Write)
+            .map(|a| (a.addr, a.value))
+            .collect();
+
+        // the log is only kept for the trace unless a debugger asked for it too
+        if !self.log_accesses {
+            self.accesses.truncate(logged);
         }
-        else if val >= 0xA000 && val < 0xB000 { // ld i
-            self.cpu.i = val & 0x0FFF;
-        }
-        else if val >= 0xB000 && val < 0xC000 { // jmp v0 addr
-            let mut addr = val & 0x0FFF;
-            addr += self.cpu.v[0 as usize] as u16;
-            self.cpu.pc = addr;
+
+        let record = TraceRecord { cycle: self.cycles, pc: before.pc, opcode, instruction, before, after: self.cpu, writes };
+        if let Some(trace) = self.trace.as_mut() {
+            trace.record(&record);
         }
-        else if val >= 0xC000 && val < 0xD000 { // rnd vx, byte
-            let x = (val & 0x0F00) >> 8 as u8;
-            let kk = (val & 0x00FF) as u8;
+    }
 
-            let mut rng = rand::thread_rng();
+    fn fetch(&self, addr: u16) -> Result<u16, EmulatorError> {
+        let m1 = self.memory.read(addr as usize)?;
+        let m2: u8 = self.memory.read(addr as usize + 1)?;
 
-            let r: u8 = rng.gen();
+        Ok(((m1 as u16) << 8) | (m2 as u16))
+    }
 
-            self.cpu.v[x as usize] = kk & r;
+    fn read_byte(&mut self, addr: usize) -> Result<u8, EmulatorError> {
+        let value = self.memory.read(addr)?;
+        if self.log_accesses || self.trace.is_some() {
+            self.accesses.push(Access { addr: self.memory.resolve(addr)?, kind: AccessKind::Read, value });
         }
-        else if val >= 0xD000 && val < 0xE000 { // display
-            let x: u8 = ((val & 0x0F00) >> 8) as u8;
-            let y: u8 = ((val & 0x00F0) >> 4) as u8;
-            let n: u8 = (val & 0x000F) as u8;
-
-            let start_idx = self.cpu.i as usize;
-            //let end_idx = (self.cpu.i + n as u16) as usize;
+        Ok(value)
+    }
 
-            let mut sprite:[u8; 15] = [0; 15];
+    fn write_byte(&mut self, addr: usize, value: u8) -> Result<(), EmulatorError> {
+        self.memory.write(addr, value)?;
+        if self.log_accesses || self.trace.is_some() {
+            self.accesses.push(Access { addr: self.memory.resolve(addr)?, kind: AccessKind::Write, value });
+        }
+        Ok(())
+    }
 
-            //println!("start_idx: {}, end_idx: {}", start_idx, end_idx);
+    // skips the next instruction, which is four bytes long if it is an XO-CHIP F000 nnnn
+    fn skip(&mut self) -> Result<(), EmulatorError> {
+        let size = if self.quirks.long_skip && self.fetch(self.cpu.pc)? == 0xF000 { 4 } else { 2 };
+        self.cpu.pc = self.cpu.pc.wrapping_add(size);
 
-            for d in 0..n {
-                sprite[d as usize] = self.memory[start_idx + d as usize];
-            }
+        Ok(())
+    }
 
-            //sprite.copy_from_slice(&self.memory[start_idx..end_idx]);
+    fn execute(&mut self, instruction: Instruction) -> Result<StepOutcome, EmulatorError> {
+        match instruction {
+            Instruction::Scd(n) => {
+                self.display.scroll_down(n as usize);
+            },
+            Instruction::Scu(n) => {
+                self.display.scroll_up(n as usize);
+            },
+            Instruction::Cls => {
+                self.display.clear();
+            },
+            Instruction::Scr => {
+                self.display.scroll_right(4);
+            },
+            Instruction::Scl => {
+                self.display.scroll_left(4);
+            },
+            Instruction::Exit => {
+                self.halted = true;
+                return Ok(StepOutcome::Exited);
+            },
+            Instruction::Low => {
+                self.display.set_hires(false);
+            },
+            Instruction::High => {
+                self.display.set_hires(true);
+            },
+            Instruction::Ret => {
+                self.cpu.pc = self.cpu.stack_pop()?;
+            },
+            Instruction::Jp(addr) => {
+                self.cpu.pc = addr;
+            },
+            Instruction::Call(addr) => {
+                self.cpu.stack_push(self.cpu.pc)?;
+                self.cpu.pc = addr;
+            },
+            Instruction::SeVxByte { x, kk } => {
+                if self.cpu.v[x as usize] == kk {
+                    self.skip()?;
+                }
+            },
+            Instruction::SneVxByte { x, kk } => {
+                if self.cpu.v[x as usize] != kk {
+                    self.skip()?;
+                }
+            },
+            Instruction::SeVxVy { x, y } => {
+                if self.cpu.v[x as usize] == self.cpu.v[y as usize] {
+                    self.skip()?;
+                }
+            },
+            Instruction::LdVxByte { x, kk } => {
+                self.cpu.v[x as usize] = kk;
+            },
+            Instruction::AddVxByte { x, kk } => {
+                self.cpu.v[x as usize] = self.cpu.v[x as usize].wrapping_add(kk);
+            },
+            Instruction::LdVxVy { x, y } => {
+                self.cpu.v[x as usize] = self.cpu.v[y as usize];
+            },
+            Instruction::OrVxVy { x, y } => {
+                self.cpu.v[x as usize] |= self.cpu.v[y as usize];
+                if self.quirks.logic_resets_vf {
+                    self.cpu.v[0x0F] = 0;
+                }
+            },
+            Instruction::AndVxVy { x, y } => {
+                self.cpu.v[x as usize] &= self.cpu.v[y as usize];
+                if self.quirks.logic_resets_vf {
+                    self.cpu.v[0x0F] = 0;
+                }
+            },
+            Instruction::XorVxVy { x, y } => {
+                self.cpu.v[x as usize] ^= self.cpu.v[y as usize];
+                if self.quirks.logic_resets_vf {
+                    self.cpu.v[0x0F] = 0;
+                }
+            },
+            Instruction::AddVxVy { x, y } => {
+                let (sum, carry) = self.cpu.v[x as usize].overflowing_add(self.cpu.v[y as usize]);
+                self.cpu.v[x as usize] = sum;
+                self.cpu.v[0x0F] = carry as u8;
+            },
+            Instruction::SubVxVy { x, y } => {
+                let (diff, borrow) = self.cpu.v[x as usize].overflowing_sub(self.cpu.v[y as usize]);
+                self.cpu.v[x as usize] = diff;
+                self.cpu.v[0x0F] = !borrow as u8;
+            },
+            Instruction::ShrVxVy { x, y } => {
+                let src = if self.quirks.shift_uses_vy { y } else { x };
+                let flag = self.cpu.v[src as usize] & 0x01;
+                self.cpu.v[x as usize] = self.cpu.v[src as usize] >> 1;
+                self.cpu.v[0x0F] = flag;
+            },
+            Instruction::SubnVxVy { x, y } => {
+                let (diff, borrow) = self.cpu.v[y as usize].overflowing_sub(self.cpu.v[x as usize]);
+                self.cpu.v[x as usize] = diff;
+                self.cpu.v[0x0F] = !borrow as u8;
+            },
+            Instruction::ShlVxVy { x, y } => {
+                let src = if self.quirks.shift_uses_vy { y } else { x };
+                let flag = (self.cpu.v[src as usize] >> 7) & 0x01;
+                self.cpu.v[x as usize] = self.cpu.v[src as usize] << 1;
+                self.cpu.v[0x0F] = flag;
+            },
+            Instruction::SneVxVy { x, y } => {
+                if self.cpu.v[x as usize] != self.cpu.v[y as usize] {
+                    self.skip()?;
+                }
+            },
+            Instruction::LdI(addr) => {
+                self.cpu.i = addr;
+            },
+            Instruction::JpV0(addr) => {
+                let reg = if self.quirks.jump_uses_vx { (addr >> 8) as usize } else { 0 };
+                self.cpu.pc = addr + self.cpu.v[reg] as u16;
+            },
+            Instruction::RndVxByte { x, kk } => {
+                self.cpu.v[x as usize] = kk & self.rng.next_byte();
+            },
+            Instruction::Drw { x, y, n } => {
+                if self.quirks.display_wait {
+                    if !self.vblank {
+                        self.cpu.pc = self.cpu.pc.wrapping_sub(2);
+                        return Ok(StepOutcome::WaitingForVblank);
+                    }
+                    self.vblank = false;
+                }
 
-            //println!("{:?}", sprite);
+                let (width, height) = (self.display.width(), self.display.height());
+                let vx = self.cpu.v[x as usize] as usize % width;
+                let vy = self.cpu.v[y as usize] as usize % height;
 
-            let vx: u8 = self.cpu.v[x as usize];
-            let vy: u8 = self.cpu.v[y as usize];
+                // Dxy0 draws a 16x16 sprite made of two bytes per row
+                let (rows, cols) = if n == 0 { (16, 16) } else { (n as usize, 8) };
+                let bytes_per_row = cols / 8;
 
-            for i in 0..n {
-                //println!("sprite line: {}", i);
-                for b in 0..8 {
+                self.cpu.v[0x0F] = 0;
 
-                    //println!("sprite bit: {}", b);
+                // with both XO-CHIP planes selected the sprite data for plane 2 follows plane 1
+                let mut addr = self.cpu.i as usize;
 
-                    let mut bit = false;
-                    if (sprite[i as usize] >> (8 - b - 1) & 0b00000001) == 0b00000001 {
-                        bit = true;
-                        
+                for plane in [0x01, 0x02] {
+                    if self.display.planes() & plane == 0 {
+                        continue;
                     }
 
-                    //println!("sprite val: {}", bit);
-
-                    let px = self.display.get((vx + b) as usize, (vy + i) as usize) ^ bit;
-
-                    if px {
-                        self.cpu.v[0x0f] = 0;
+                    for row in 0..rows {
+                        let mut sprite: u16 = 0;
+                        for _ in 0..bytes_per_row {
+                            sprite = (sprite << 8) | self.read_byte(addr)? as u16;
+                            addr += 1;
+                        }
+
+                        for b in 0..cols {
+                            let bit = (sprite >> (cols - b - 1)) & 0x01 == 0x01;
+                            let (mut px, mut py) = (vx + b, vy + row);
+
+                            if self.quirks.wrap_sprites {
+                                px %= width;
+                                py %= height;
+                            }
+
+                            if !bit || px >= width || py >= height {
+                                continue;
+                            }
+
+                            if self.display.toggle(px, py, plane) {
+                                self.cpu.v[0x0F] = 1;
+                            }
+                        }
                     }
-                    else {
-                        self.cpu.v[0x0f] = 1;
+                }
+            },
+            Instruction::SkpVx { x } => {
+                if self.keyboard[(self.cpu.v[x as usize] & 0x0F) as usize] {
+                    self.skip()?; //key pressed
+                }
+            },
+            Instruction::SknpVx { x } => {
+                if !self.keyboard[(self.cpu.v[x as usize] & 0x0F) as usize] {
+                    self.skip()?; //key not pressed
+                }
+            },
+            Instruction::LdILong => {
+                self.cpu.i = self.fetch(self.cpu.pc)?;
+                self.cpu.pc = self.cpu.pc.wrapping_add(2);
+            },
+            Instruction::Plane(n) => {
+                self.display.set_planes(n);
+            },
+            Instruction::Audio => {
+                let mut pattern = [0; 16];
+                for (d, byte) in pattern.iter_mut().enumerate() {
+                    *byte = self.read_byte(self.cpu.i as usize + d)?;
+                }
+                self.audio.set_pattern(pattern);
+            },
+            Instruction::LdVxDt { x } => {
+                self.cpu.v[x as usize] = self.cpu.dt;
+            },
+            Instruction::LdVxK { x } => {
+                match self.keyboard.iter().position(|&pressed| pressed) {
+                    Some(key) => {
+                        self.cpu.v[x as usize] = key as u8;
+                    },
+                    None => {
+                        self.cpu.pc = self.cpu.pc.wrapping_sub(2); //key not pressed
+                        return Ok(StepOutcome::WaitingForKey);
                     }
+                }
+            },
+            Instruction::LdDtVx { x } => {
+                self.cpu.dt = self.cpu.v[x as usize];
+            },
+            Instruction::LdStVx { x } => {
+                self.cpu.st = self.cpu.v[x as usize];
+            },
+            Instruction::AddIVx { x } => {
+                self.cpu.i = self.cpu.i.wrapping_add(self.cpu.v[x as usize] as u16);
+            },
+            Instruction::LdFVx { x } => {
+                self.cpu.i = 5 * (self.cpu.v[x as usize] & 0x0F) as u16;
+            },
+            Instruction::LdHfVx { x } => {
+                self.cpu.i = BIG_FONT_ADDR + 10 * (self.cpu.v[x as usize] & 0x0F) as u16;
+            },
+            Instruction::LdBVx { x } => {
+                let vx = self.cpu.v[x as usize];
+                let i = self.cpu.i as usize;
 
-                    //println!("sprite set");
-                    self.display.set((vx + b) as usize, (vy + i) as usize, px);
+                self.write_byte(i, vx / 100)?;
+                self.write_byte(i + 1, (vx / 10) % 10)?;
+                self.write_byte(i + 2, vx % 10)?;
+            },
+            Instruction::PitchVx { x } => {
+                self.audio.set_pitch(self.cpu.v[x as usize]);
+            },
+            Instruction::LdIVx { x } => {
+                for d in 0..=x as usize {
+                    self.write_byte(self.cpu.i as usize + d, self.cpu.v[d])?;
                 }
-            }
+                self.increment_i(x);
+            },
+            Instruction::LdVxI { x } => {
+                for d in 0..=x as usize {
+                    self.cpu.v[d] = self.read_byte(self.cpu.i as usize + d)?;
+                }
+                self.increment_i(x);
+            },
+            Instruction::SaveVxVy { x, y } => {
+                for d in 0..=x.abs_diff(y) {
+                    let reg = register_step(x, y, d);
+                    self.write_byte(self.cpu.i as usize + d as usize, self.cpu.v[reg])?;
+                }
+            },
+            Instruction::LoadVxVy { x, y } => {
+                for d in 0..=x.abs_diff(y) {
+                    let reg = register_step(x, y, d);
+                    self.cpu.v[reg] = self.read_byte(self.cpu.i as usize + d as usize)?;
+                }
+            },
+            Instruction::LdRVx { x } => {
+                self.rpl[..=x as usize].copy_from_slice(&self.cpu.v[..=x as usize]);
+            },
+            Instruction::LdVxR { x } => {
+                self.cpu.v[..=x as usize].copy_from_slice(&self.rpl[..=x as usize]);
+            },
         }
-        else if val & 0xF0FF == 0xE09E { //skp Vx
-            let x: u8 = ((val & 0x0F00) >> 8) as u8;
 
-            if self.keyboard[self.cpu.v[x as usize] as usize] {
-                self.cpu.pc += 2; //key pressed
-            }
+        Ok(StepOutcome::Executed(instruction))
+    }
 
-        }
-        else if val & 0xF0FF == 0xE0A1 { //sknp Vx
-            
-            let x: u8 = ((val & 0x0F00) >> 8) as u8;
+    pub fn cpu(&self) -> Cpu {
+        self.cpu
+    }
 
-            if !self.keyboard[self.cpu.v[x as usize] as usize] {
-                self.cpu.pc += 2; //key not pressed
-            }
-        }
-        else if val & 0xF0FF == 0xF007 { //ld vx dt
-            let x: u8 = ((val & 0x0F00) >> 8) as u8;
-            self.cpu.v[x as usize] = self.cpu.dt;
-        }
-        else if val & 0xF0FF == 0xF00A { //ld vx k
-            let x: usize = ((val & 0x0F00) >> 8) as usize;
+    // for debuggers; the stack pointer should stay within 0..=16
+    pub fn cpu_mut(&mut self) -> &mut Cpu {
+        &mut self.cpu
+    }
 
-            let mut keypressed = false;
-            let mut pressed_key: usize = 0;
+    pub fn memory(&self) -> &Memory {
+        &self.memory
+    }
 
-            for d in 0x00..0x0F {
-                if self.keyboard[d] {
-                    keypressed = true;
-                    pressed_key = d;
-                }
-            }
+    pub fn memory_mut(&mut self) -> &mut Memory {
+        &mut self.memory
+    }
 
-            if keypressed {
-                self.cpu.v[x] = pressed_key as u8; 
-            }
-            else {
-                self.cpu.pc -= 2; //key not pressed
-            }
+    pub fn display(&self) -> Display {
+        self.display
+    }
 
+    pub fn sound_active(&self) -> bool {
+        self.cpu.st > 0
+    }
+
+    // the buzzer follows the sound timer; frontends pull samples from it with fill()
+    pub fn audio_mut(&mut self) -> &mut AudioSource {
+        self.audio.set_active(self.sound_active());
+        &mut self.audio
+    }
+}
+
+// the d-th register XO-CHIP 5xy2/5xy3 moves, counting from Vx towards Vy
+fn register_step(x: u8, y: u8, d: u8) -> usize {
+    if x <= y {
+        (x + d) as usize
+    }
+    else {
+        (x - d) as usize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reset_restores_the_boot_state() {
+        // overwrite the first font byte and the rom's first instruction, then spin
+        let rom = vec![0x60, 0xFF, 0xA0, 0x00, 0xF0, 0x55, 0xA2, 0x00, 0xF0, 0x55, 0x12, 0x0A];
+        let mut comp = Computer::with_seed(rom.clone(), Quirks::default(), 0);
+        comp.set_memory_policy(AccessPolicy::Trap);
+        comp.press(3);
+        comp.run_frame(10).unwrap();
+        comp.run_frame(10).unwrap();
+        assert_eq!(comp.memory().as_slice()[0], 0xFF);
+
+        comp.reset();
+
+        let mut fresh = Computer::with_seed(rom, Quirks::default(), 0);
+        fresh.set_memory_policy(AccessPolicy::Trap);
+        assert_eq!(comp.save_state(), fresh.save_state());
+    }
+
+    #[test]
+    fn waiting_at_the_top_of_64k_memory_keeps_pc() {
+        let quirks = Quirks { display_wait: true, ..Quirks::xo_chip() };
+        let mut comp = Computer::with_seed(Vec::new(), quirks, 0);
+
+        comp.memory_mut().load(0xFFFE, &[0xF0, 0x0A]);
+        comp.cpu_mut().pc = 0xFFFE;
+        assert_eq!(comp.tick(), Ok(StepOutcome::WaitingForKey));
+        assert_eq!(comp.cpu().pc, 0xFFFE);
+
+        // the first Dxyn after a frame draws and wraps to 0, the second one waits
+        comp.memory_mut().load(0xFFFE, &[0xD0, 0x01]);
+        comp.tick().unwrap();
+        assert_eq!(comp.cpu().pc, 0x0000);
+        comp.cpu_mut().pc = 0xFFFE;
+        assert_eq!(comp.tick(), Ok(StepOutcome::WaitingForVblank));
+        assert_eq!(comp.cpu().pc, 0xFFFE);
+    }
+
+    #[test]
+    fn run_frame_counts_instructions_up_to_a_vblank_wait() {
+        // 6001 D015 1202 on the VIP: one sprite a frame
+        let mut comp = Computer::with_seed(vec![0x60, 0x01, 0xD0, 0x15, 0x12, 0x02], Quirks::cosmac_vip(), 0);
+
+        assert_eq!(comp.run_frame(10), Ok(3));
+        assert_eq!(comp.run_frame(10), Ok(2));
+        assert_eq!(comp.run_frame(1), Ok(1));
+        assert_eq!(comp.cycles(), 6);
+    }
+
+    // 200 LD V1 5, 202 LD ST V1, 204 RND V0 FF, 206 DRW V0 V1 5, 208 JP 204
+    fn busy() -> Computer {
+        let mut comp = Computer::with_seed(vec![0x61, 0x05, 0xF1, 0x18, 0xC0, 0xFF, 0xD0, 0x15, 0x12, 0x04], Quirks::default(), 7);
+        comp.press(0xC);
+        for _ in 0..3 {
+            comp.run_frame(10).unwrap();
         }
-        else if val & 0xF0FF == 0xF015 { //ld dt vx
-            let x: u8 = ((val & 0x0F00) >> 8) as u8;
-            self.cpu.dt = self.cpu.v[x as usize];
-        }
-        else if val & 0xF0FF == 0xF018 { //ld st vx
-            let x: u8 = ((val & 0x0F00) >> 8) as u8;
-            self.cpu.st = self.cpu.v[x as usize];
-        }
-        else if val & 0xF0FF == 0xF01E { //add i vx
-            let x: u8 = ((val & 0x0F00) >> 8) as u8;
-            self.cpu.i += self.cpu.v[x as usize] as u16;
+        comp
+    }
+
+    #[test]
+    fn a_loaded_state_runs_on_like_the_original() {
+        let mut comp = busy();
+        let saved = comp.save_state();
+        for _ in 0..5 {
+            comp.run_frame(10).unwrap();
         }
-        else if val & 0xF0FF == 0xF029 { //ld f vx
-            let x: u8 = ((val & 0x0F00) >> 8) as u8;
-            self.cpu.i += (5 as u16) * (self.cpu.v[x as usize] as u16);
+
+        let mut restored = Computer::with_seed(Vec::new(), Quirks::cosmac_vip(), 99);
+        restored.load_state(&saved).unwrap();
+        assert_eq!(restored.save_state(), saved);
+        assert_eq!(restored.quirks(), Quirks::default());
+        for _ in 0..5 {
+            restored.run_frame(10).unwrap();
         }
-        else if val & 0xF0FF == 0xF033 { //ld b vx
-            let x: u8 = ((val & 0x0F00) >> 8) as u8;
-            self.memory[self.cpu.i as usize] = (self.cpu.v[x as usize] - (self.cpu.v[x as usize] % 100)) / 100;
-            self.memory[(self.cpu.i + 1) as usize] = ((self.cpu.v[x as usize] % 100) - (self.cpu.v[x as usize] % 10)) / 10;
-            self.memory[(self.cpu.i + 2) as usize] = self.cpu.v[x as usize] % 10;
+
+        assert_eq!(restored.save_state(), comp.save_state());
+    }
+
+    #[test]
+    fn rejects_bad_states_and_leaves_the_computer_alone() {
+        let mut comp = busy();
+        let saved = comp.save_state();
+        let before = comp.save_state();
+
+        let mut magic = saved.clone();
+        magic[0] = b'X';
+        assert_eq!(comp.load_state(&magic), Err(StateError::BadMagic));
+        assert_eq!(comp.load_state(b"C8"), Err(StateError::BadMagic));
+
+        let mut version = saved.clone();
+        version[4..6].copy_from_slice(&(state::VERSION + 1).to_le_bytes());
+        assert_eq!(comp.load_state(&version), Err(StateError::UnsupportedVersion(state::VERSION + 1)));
+
+        for len in 6..saved.len() {
+            assert_eq!(comp.load_state(&saved[..len]), Err(StateError::Truncated), "{} bytes", len);
         }
-        else if val & 0xF0FF == 0xF055 { //ld i vx
-            let x: u8 = ((val & 0x0F00) >> 8) as u8;
-            
-            for d in 0..x {
-                self.memory[(self.cpu.i + d as u16) as usize] = self.cpu.v[d as usize];
-            }
 
+        let mut trailing = saved.clone();
+        trailing.push(0);
+        assert_eq!(comp.load_state(&trailing), Err(StateError::Invalid("trailing data")));
+
+        assert_eq!(comp.save_state(), before);
+    }
+
+    #[test]
+    fn save_and_load_register_ranges_in_either_direction() {
+        // 200 LD I 300, 202 SAVE V1-V3, 204 LD I 310, 206 SAVE V6-V4, 208 LD I 300, 20A LOAD V9-V7
+        let rom = vec![0xA3, 0x00, 0x51, 0x32, 0xA3, 0x10, 0x56, 0x42, 0xA3, 0x00, 0x59, 0x73];
+        let mut comp = Computer::with_seed(rom, Quirks::xo_chip(), 0);
+        comp.cpu_mut().v[..7].copy_from_slice(&[0, 1, 2, 3, 4, 5, 6]);
+        for _ in 0..6 {
+            comp.tick().unwrap();
         }
-        else if val & 0xF0FF == 0xF065 { //ld vx i
-            let x: u8 = ((val & 0x0F00) >> 8) as u8;
-            
-            for d in 0..x {
-                self.cpu.v[d as usize] = self.memory[(self.cpu.i + d as u16) as usize];
-            }
 
+        assert_eq!(&comp.memory().as_slice()[0x300..0x303], &[1, 2, 3]);
+        assert_eq!(&comp.memory().as_slice()[0x310..0x313], &[6, 5, 4]);
+        assert_eq!(&comp.cpu().v[7..10], &[3, 2, 1]);
+    }
+
+    #[test]
+    fn dxy0_draws_16x16_sprites_in_high_resolution() {
+        // 200 HIGH, 202 LD I 300, 204 DRW V0 V1 0, 206 DRW V0 V1 0
+        let mut comp = Computer::with_seed(vec![0x00, 0xFF, 0xA3, 0x00, 0xD0, 0x10, 0xD0, 0x10], Quirks::super_chip(), 0);
+        // left half on even rows, right half on odd ones
+        let sprite: Vec<u8> = (0..16).flat_map(|row| if row % 2 == 0 { [0xFF, 0x00] } else { [0x00, 0xFF] }).collect();
+        comp.memory_mut().load(0x300, &sprite);
+        comp.cpu_mut().v[0] = 8;
+        comp.cpu_mut().v[1] = 2;
+        for _ in 0..3 {
+            comp.tick().unwrap();
+        }
+
+        let display = comp.display();
+        assert_eq!(comp.cpu().v[0xF], 0);
+        assert!(display.get(8, 2) && display.get(15, 2) && !display.get(16, 2));
+        assert!(!display.get(15, 3) && display.get(16, 3) && display.get(23, 3));
+        assert!(display.get(23, 17) && !display.get(24, 17) && !display.get(8, 18));
+        assert_eq!(display.dump().iter().filter(|px| **px).count(), 16 * 8);
+
+        comp.tick().unwrap();
+        assert_eq!(comp.cpu().v[0xF], 1);
+        assert!(comp.display().dump().iter().all(|px| !px));
+    }
+
+    #[test]
+    fn fx30_points_at_the_big_font() {
+        // 200 LD HF V0
+        let mut comp = Computer::with_seed(vec![0xF0, 0x30], Quirks::super_chip(), 0);
+        for digit in 0..16 {
+            comp.cpu_mut().pc = 0x200;
+            comp.cpu_mut().v[0] = digit | 0xF0;
+            comp.tick().unwrap();
+
+            let i = comp.cpu().i as usize;
+            assert_eq!(i, 0x50 + 10 * digit as usize);
+            assert_eq!(&comp.memory().as_slice()[i..i + 10], &BIG_FONT[10 * digit as usize..][..10]);
         }
     }
 
-    pub fn display(&self) -> Display {
-        self.display.clone()
+    #[test]
+    fn fx75_and_fx85_keep_registers_in_the_flags() {
+        // 200 LD R V7, 202 LD V3 R
+        let mut comp = Computer::with_seed(vec![0xF7, 0x75, 0xF3, 0x85], Quirks::super_chip(), 0);
+        comp.cpu_mut().v[..8].copy_from_slice(&[10, 11, 12, 13, 14, 15, 16, 17]);
+        comp.tick().unwrap();
+
+        comp.cpu_mut().v[..8].fill(0);
+        comp.tick().unwrap();
+        assert_eq!(&comp.cpu().v[..8], &[10, 11, 12, 13, 0, 0, 0, 0]);
+
+        // the flags outlive a save state round trip
+        let state = comp.save_state();
+        let mut other = Computer::with_seed(Vec::new(), Quirks::super_chip(), 0);
+        other.load_state(&state).unwrap();
+        other.memory_mut().load(0x200, &[0xF7, 0x85]);
+        other.cpu_mut().pc = 0x200;
+        other.tick().unwrap();
+        assert_eq!(&other.cpu().v[..8], &[10, 11, 12, 13, 14, 15, 16, 17]);
     }
-}
\ No newline at end of file
+}
@@ -1,4 +1,7 @@
-#[derive(Clone, Copy)]
+use crate::error::EmulatorError;
+use crate::state::{StateError, StateReader, StateWriter};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub struct Cpu {
     pub v: [u8; 16],
     pub sp: u8,
@@ -9,6 +12,12 @@ pub struct Cpu {
     stack: [u16; 16]
 }
 
+impl Default for Cpu {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Cpu {
     pub fn new() -> Self {
         Self {
@@ -22,9 +31,14 @@ impl Cpu {
         }
     }
 
-    pub fn stack_push(&mut self, stack_val: u16) -> Result<u16, &'static str> {
+    // return addresses currently on the stack, oldest first
+    pub fn stack(&self) -> &[u16] {
+        &self.stack[..self.sp as usize]
+    }
+
+    pub fn stack_push(&mut self, stack_val: u16) -> Result<u16, EmulatorError> {
         if self.sp >= 16 {
-            return Err("stack overflow");
+            return Err(EmulatorError::StackOverflow);
         }
         self.stack[self.sp as usize] = stack_val;
         self.sp += 1;
@@ -32,9 +46,9 @@ impl Cpu {
         Ok(stack_val)
     }
 
-    pub fn stack_pop(&mut self) -> Result<u16, &'static str> {
-        if self.sp <= 0 {
-            return  Err("stack overflow");
+    pub fn stack_pop(&mut self) -> Result<u16, EmulatorError> {
+        if self.sp == 0 {
+            return Err(EmulatorError::StackUnderflow);
         }
 
         self.sp -= 1;
@@ -42,4 +56,34 @@ impl Cpu {
         Ok(self.stack[self.sp as usize])
     }
 
+    pub(crate) fn write_state(&self, out: &mut StateWriter) {
+        out.bytes(&self.v);
+        out.u8(self.sp);
+        out.u8(self.dt);
+        out.u8(self.st);
+        out.u16(self.pc);
+        out.u16(self.i);
+        for addr in self.stack {
+            out.u16(addr);
+        }
+    }
+
+    pub(crate) fn read_state(r: &mut StateReader) -> Result<Self, StateError> {
+        let mut cpu = Self::new();
+        cpu.v.copy_from_slice(r.bytes(16)?);
+        cpu.sp = r.u8()?;
+        cpu.dt = r.u8()?;
+        cpu.st = r.u8()?;
+        cpu.pc = r.u16()?;
+        cpu.i = r.u16()?;
+        for addr in cpu.stack.iter_mut() {
+            *addr = r.u16()?;
+        }
+
+        if cpu.sp > 16 {
+            return Err(StateError::Invalid("stack pointer"));
+        }
+        Ok(cpu)
+    }
+
 }
\ No newline at end of file
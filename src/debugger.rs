@@ -0,0 +1,565 @@
+use std::collections::BTreeSet;
+use std::fmt;
+use std::str::FromStr;
+use crate::computer::{Computer, StepOutcome};
+use crate::cpu::Cpu;
+use crate::error::EmulatorError;
+use crate::instruction::{decode, Instruction};
+use crate::memory::{Access, AccessKind};
+use crate::rewind::Rewind;
+
+const DEFAULT_STEP_LIMIT: u64 = 10_000_000;
+
+// reverse stepping restores the closest snapshot and replays from there, so
+// history reaches back HISTORY_INTERVAL * HISTORY_SNAPSHOTS instructions
+const HISTORY_INTERVAL: u64 = 1000;
+const HISTORY_SNAPSHOTS: usize = 1000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Register {
+    V(u8),
+    I,
+    Pc,
+    Sp,
+    Dt,
+    St,
+}
+
+impl Register {
+    pub fn read(&self, cpu: &Cpu) -> u16 {
+        match self {
+            Register::V(x) => cpu.v[(*x & 0x0F) as usize] as u16,
+            Register::I => cpu.i,
+            Register::Pc => cpu.pc,
+            Register::Sp => cpu.sp as u16,
+            Register::Dt => cpu.dt as u16,
+            Register::St => cpu.st as u16,
+        }
+    }
+}
+
+impl fmt::Display for Register {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Register::V(x) => write!(f, "V{:X}", x),
+            Register::I => write!(f, "I"),
+            Register::Pc => write!(f, "PC"),
+            Register::Sp => write!(f, "SP"),
+            Register::Dt => write!(f, "DT"),
+            Register::St => write!(f, "ST"),
+        }
+    }
+}
+
+impl FromStr for Register {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_uppercase().as_str() {
+            "I" => Ok(Register::I),
+            "PC" => Ok(Register::Pc),
+            "SP" => Ok(Register::Sp),
+            "DT" => Ok(Register::Dt),
+            "ST" => Ok(Register::St),
+            reg => match reg.strip_prefix('V').and_then(|x| u8::from_str_radix(x, 16).ok()) {
+                Some(x) if x <= 0x0F => Ok(Register::V(x)),
+                _ => Err(format!("unknown register: {}", s)),
+            },
+        }
+    }
+}
+
+// an inclusive range of memory addresses, watched for reads, writes or both
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Watchpoint {
+    pub start: usize,
+    pub end: usize,
+    pub read: bool,
+    pub write: bool,
+}
+
+impl Watchpoint {
+    fn matches(&self, access: &Access) -> bool {
+        let kind = match access.kind {
+            AccessKind::Read => self.read,
+            AccessKind::Write => self.write,
+        };
+        kind && (self.start..=self.end).contains(&access.addr)
+    }
+}
+
+// fires when the register changes, or only when it changes to `value`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RegisterWatch {
+    pub reg: Register,
+    pub value: Option<u16>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WatchEvent {
+    Memory(Access),
+    Register { reg: Register, old: u16, new: u16 },
+}
+
+// what a watchpoint caught, and the instruction responsible
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WatchHit {
+    pub pc: u16,
+    pub instruction: Instruction,
+    pub event: WatchEvent,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StopReason {
+    Stepped,
+    Breakpoint(u16),
+    Watchpoint(WatchHit),
+    Exited,
+    // Fx0A is waiting and nothing can press a key while the debugger runs
+    WaitingForKey,
+    // step_out with nothing on the stack to return to
+    NotInSubroutine,
+    StepLimit,
+    Error(EmulatorError),
+}
+
+// runs a Computer one instruction at a time, counting the timers down every
+// `cycles_per_frame` instructions the same way run_frame() does
+pub struct Debugger {
+    comp: Computer,
+    breakpoints: BTreeSet<u16>,
+    watchpoints: Vec<Watchpoint>,
+    register_watches: Vec<RegisterWatch>,
+    cycles_per_frame: usize,
+    frame_cycles: usize,
+    cycles: u64,
+    step_limit: u64,
+    history: Rewind,
+}
+
+impl Debugger {
+    pub fn new(comp: Computer, cycles_per_frame: usize) -> Self {
+        Self {
+            comp,
+            breakpoints: BTreeSet::new(),
+            watchpoints: Vec::new(),
+            register_watches: Vec::new(),
+            cycles_per_frame: cycles_per_frame.max(1),
+            frame_cycles: 0,
+            cycles: 0,
+            step_limit: DEFAULT_STEP_LIMIT,
+            history: Rewind::new(HISTORY_SNAPSHOTS),
+        }
+    }
+
+    pub fn computer(&self) -> &Computer {
+        &self.comp
+    }
+
+    pub fn computer_mut(&mut self) -> &mut Computer {
+        &mut self.comp
+    }
+
+    pub fn into_inner(self) -> Computer {
+        self.comp
+    }
+
+    // instructions executed so far
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    pub fn step_limit(&self) -> u64 {
+        self.step_limit
+    }
+
+    // how many instructions continue/step_over/step_out may run before giving up
+    pub fn set_step_limit(&mut self, limit: u64) {
+        self.step_limit = limit;
+    }
+
+    // returns false if there already was a breakpoint at `addr`
+    pub fn add_breakpoint(&mut self, addr: u16) -> bool {
+        self.breakpoints.insert(addr)
+    }
+
+    pub fn remove_breakpoint(&mut self, addr: u16) -> bool {
+        self.breakpoints.remove(&addr)
+    }
+
+    pub fn clear_breakpoints(&mut self) {
+        self.breakpoints.clear();
+    }
+
+    pub fn breakpoints(&self) -> impl Iterator<Item = u16> + '_ {
+        self.breakpoints.iter().copied()
+    }
+
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) {
+        self.watchpoints.push(watchpoint);
+        self.comp.set_access_log(true);
+    }
+
+    pub fn remove_watchpoint(&mut self, watchpoint: &Watchpoint) -> bool {
+        let len = self.watchpoints.len();
+        self.watchpoints.retain(|w| w != watchpoint);
+        self.comp.set_access_log(!self.watchpoints.is_empty());
+        self.watchpoints.len() != len
+    }
+
+    pub fn watchpoints(&self) -> &[Watchpoint] {
+        &self.watchpoints
+    }
+
+    pub fn add_register_watch(&mut self, watch: RegisterWatch) {
+        self.register_watches.push(watch);
+    }
+
+    pub fn remove_register_watch(&mut self, watch: &RegisterWatch) -> bool {
+        let len = self.register_watches.len();
+        self.register_watches.retain(|w| w != watch);
+        self.register_watches.len() != len
+    }
+
+    pub fn register_watches(&self) -> &[RegisterWatch] {
+        &self.register_watches
+    }
+
+    pub fn clear_watchpoints(&mut self) {
+        self.watchpoints.clear();
+        self.register_watches.clear();
+        self.comp.set_access_log(false);
+    }
+
+    // the instruction at PC, None if it doesn't decode
+    pub fn current_instruction(&self) -> Option<Instruction> {
+        let pc = self.comp.cpu().pc as usize;
+        let memory = self.comp.memory();
+        let hi = memory.read(pc).ok()?;
+        let lo = memory.read(pc + 1).ok()?;
+
+        decode(((hi as u16) << 8) | lo as u16).ok()
+    }
+
+    // a save state with the frame position tacked on the end
+    fn snapshot(&mut self) {
+        if self.cycles.is_multiple_of(HISTORY_INTERVAL) && self.history.latest().is_none_or(|(at, _)| at != self.cycles) {
+            let mut state = self.comp.save_state();
+            state.extend((self.frame_cycles as u64).to_le_bytes());
+            self.history.push(self.cycles, state);
+        }
+    }
+
+    fn tick(&mut self) -> Result<(StepOutcome, Option<WatchHit>), EmulatorError> {
+        self.snapshot();
+        let before = self.comp.cpu();
+        let outcome = match self.comp.tick() {
+            Ok(outcome) => outcome,
+            Err(e) => {
+                // what the failed instruction got to touch mustn't be blamed on the next one
+                self.comp.take_accesses();
+                return Err(e);
+            },
+        };
+        self.cycles += 1;
+        self.frame_cycles += 1;
+
+        let hit = match outcome {
+            StepOutcome::Executed(instruction) => self.check_watches(&before, instruction),
+            _ => None,
+        };
+
+        if self.frame_cycles >= self.cycles_per_frame || outcome == StepOutcome::WaitingForVblank {
+            self.comp.tick_timers();
+            self.frame_cycles = 0;
+        }
+
+        Ok((outcome, hit))
+    }
+
+    // the first watchpoint the last instruction set off; timers counting down don't count
+    fn check_watches(&mut self, before: &Cpu, instruction: Instruction) -> Option<WatchHit> {
+        let accesses = self.comp.take_accesses();
+        let hit = |event| Some(WatchHit { pc: before.pc, instruction, event });
+
+        for access in accesses {
+            if self.watchpoints.iter().any(|w| w.matches(&access)) {
+                return hit(WatchEvent::Memory(access));
+            }
+        }
+
+        let after = self.comp.cpu();
+        for watch in &self.register_watches {
+            let (old, new) = (watch.reg.read(before), watch.reg.read(&after));
+            if old != new && watch.value.is_none_or(|v| v == new) {
+                return hit(WatchEvent::Register { reg: watch.reg, old, new });
+            }
+        }
+
+        None
+    }
+
+    // steps until `done` holds, stopping early at breakpoints (other than the one
+    // execution starts on), watchpoints, errors, exit and Fx0A
+    fn run_until(&mut self, done: impl Fn(&Computer) -> bool) -> StopReason {
+        for n in 0..self.step_limit {
+            let pc = self.comp.cpu().pc;
+            if n > 0 && self.breakpoints.contains(&pc) {
+                return StopReason::Breakpoint(pc);
+            }
+
+            match self.tick() {
+                Err(e) => return StopReason::Error(e),
+                Ok((_, Some(hit))) => return StopReason::Watchpoint(hit),
+                Ok((StepOutcome::Exited, _)) => return StopReason::Exited,
+                Ok((StepOutcome::WaitingForKey, _)) => return StopReason::WaitingForKey,
+                Ok(_) => {}
+            }
+
+            if done(&self.comp) {
+                return StopReason::Stepped;
+            }
+        }
+
+        StopReason::StepLimit
+    }
+
+    pub fn step(&mut self) -> StopReason {
+        match self.tick() {
+            Ok((_, Some(hit))) => StopReason::Watchpoint(hit),
+            Ok((StepOutcome::Exited, _)) => StopReason::Exited,
+            Ok(_) => StopReason::Stepped,
+            Err(e) => StopReason::Error(e),
+        }
+    }
+
+    // like step(), but runs a whole subroutine when PC is on a CALL
+    pub fn step_over(&mut self) -> StopReason {
+        match self.current_instruction() {
+            Some(ins @ Instruction::Call(_)) => {
+                let cpu = self.comp.cpu();
+                let ret = cpu.pc.wrapping_add(ins.size());
+                self.run_until(|c| c.cpu().pc == ret && c.cpu().sp == cpu.sp)
+            },
+            _ => self.step(),
+        }
+    }
+
+    // runs until the current subroutine returns
+    pub fn step_out(&mut self) -> StopReason {
+        let sp = self.comp.cpu().sp;
+        if sp == 0 {
+            return StopReason::NotInSubroutine;
+        }
+        self.run_until(|c| c.cpu().sp < sp)
+    }
+
+    pub fn cont(&mut self) -> StopReason {
+        self.run_until(|_| false)
+    }
+
+    // goes back to just before the last instruction; keys pressed since the
+    // closest snapshot are not replayed. false once there is no history left
+    pub fn reverse_step(&mut self) -> bool {
+        if self.cycles == 0 {
+            return false;
+        }
+        let target = self.cycles - 1;
+
+        while self.history.latest().is_some_and(|(at, _)| at > target) {
+            self.history.pop();
+        }
+        let (at, state) = match self.history.latest() {
+            Some((at, state)) => (at, state.to_vec()),
+            None => return false,
+        };
+
+        let (state, frame_cycles) = state.split_at(state.len() - 8);
+        if self.comp.load_state(state).is_err() {
+            return false;
+        }
+        self.frame_cycles = u64::from_le_bytes(frame_cycles.try_into().unwrap()) as usize;
+        self.cycles = at;
+
+        // the replayed instructions were already traced the first time round
+        let sink = self.comp.take_trace_sink();
+        while self.cycles < target {
+            if self.tick().is_err() {
+                break;
+            }
+        }
+        self.comp.take_accesses();
+        if let Some(sink) = sink {
+            self.comp.set_trace_sink(sink);
+        }
+
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::AccessPolicy;
+    use crate::quirks::Quirks;
+
+    // 200 CALL 206, 202 LD V1 1, 204 JP 204, 206 LD V0 5, 208 RET
+    const PROGRAM: &[u8] = &[0x22, 0x06, 0x61, 0x01, 0x12, 0x04, 0x60, 0x05, 0x00, 0xEE];
+
+    fn debugger(program: &[u8]) -> Debugger {
+        let mut dbg = Debugger::new(Computer::with_seed(program.to_vec(), Quirks::default(), 0), 10);
+        dbg.set_step_limit(1000);
+        dbg
+    }
+
+    #[test]
+    fn stops_at_breakpoints_but_not_the_one_it_starts_on() {
+        let mut dbg = debugger(PROGRAM);
+        assert!(dbg.add_breakpoint(0x202));
+        assert!(!dbg.add_breakpoint(0x202));
+
+        assert_eq!(dbg.cont(), StopReason::Breakpoint(0x202));
+        assert_eq!(dbg.computer().cpu().v[0], 5);
+        assert_eq!(dbg.cont(), StopReason::StepLimit);
+
+        assert!(dbg.remove_breakpoint(0x202));
+        assert_eq!(dbg.breakpoints().count(), 0);
+    }
+
+    #[test]
+    fn step_over_runs_the_whole_call() {
+        let mut dbg = debugger(PROGRAM);
+
+        assert_eq!(dbg.step_over(), StopReason::Stepped);
+        assert_eq!((dbg.computer().cpu().pc, dbg.cycles()), (0x202, 3));
+
+        // anything other than a call is a single step
+        assert_eq!(dbg.step_over(), StopReason::Stepped);
+        assert_eq!((dbg.computer().cpu().pc, dbg.cycles()), (0x204, 4));
+    }
+
+    #[test]
+    fn step_out_returns_to_the_caller() {
+        let mut dbg = debugger(PROGRAM);
+        dbg.step();
+        assert_eq!(dbg.computer().cpu().pc, 0x206);
+
+        assert_eq!(dbg.step_out(), StopReason::Stepped);
+        assert_eq!((dbg.computer().cpu().pc, dbg.computer().cpu().sp), (0x202, 0));
+    }
+
+    #[test]
+    fn step_out_at_the_top_level_does_nothing() {
+        let mut dbg = debugger(PROGRAM);
+
+        assert_eq!(dbg.step_out(), StopReason::NotInSubroutine);
+        assert_eq!(dbg.cycles(), 0);
+    }
+
+    #[test]
+    fn reports_why_it_stopped() {
+        // 00FD exits, Fx0A waits, 5001 is invalid
+        assert_eq!(debugger(&[0x00, 0xFD]).cont(), StopReason::Exited);
+        assert_eq!(debugger(&[0xF0, 0x0A]).cont(), StopReason::WaitingForKey);
+        assert!(matches!(debugger(&[0x50, 0x01]).step(), StopReason::Error(EmulatorError::UnknownOpcode { pc: 0x200, .. })));
+    }
+
+    // 200 LD I 300, 202 LD V0 7, 204 LD [I] V0, 206 LD V0 [I], 208 LD V0 7, 20A ADD V0 1, 20C JP 20C
+    const MEMORY: &[u8] = &[0xA3, 0x00, 0x60, 0x07, 0xF0, 0x55, 0xF0, 0x65, 0x60, 0x07, 0x70, 0x01, 0x12, 0x0C];
+
+    fn watch(start: usize, end: usize, read: bool, write: bool) -> Watchpoint {
+        Watchpoint { start, end, read, write }
+    }
+
+    fn memory_hit(pc: u16, kind: AccessKind, value: u8) -> StopReason {
+        let instruction = decode(u16::from_be_bytes([MEMORY[pc as usize - 0x200], MEMORY[pc as usize - 0x1FF]])).unwrap();
+        StopReason::Watchpoint(WatchHit { pc, instruction, event: WatchEvent::Memory(Access { addr: 0x300, kind, value }) })
+    }
+
+    #[test]
+    fn watchpoints_catch_reads_and_writes_separately() {
+        let mut dbg = debugger(MEMORY);
+        dbg.add_watchpoint(watch(0x300, 0x300, false, true));
+        assert_eq!(dbg.cont(), memory_hit(0x204, AccessKind::Write, 7));
+        assert_eq!(dbg.cont(), StopReason::StepLimit);
+
+        let mut dbg = debugger(MEMORY);
+        dbg.add_watchpoint(watch(0x2FF, 0x301, true, false));
+        assert_eq!(dbg.cont(), memory_hit(0x206, AccessKind::Read, 7));
+    }
+
+    #[test]
+    fn a_failed_instruction_leaves_no_accesses_behind() {
+        // 200 LD I FFE, 202 LD [I] V3, 204 LD V0 1
+        let mut dbg = debugger(&[0xAF, 0xFE, 0xF3, 0x55, 0x60, 0x01]);
+        dbg.computer_mut().set_memory_policy(AccessPolicy::Trap);
+        dbg.add_watchpoint(watch(0xFFE, 0xFFF, false, true));
+
+        assert_eq!(dbg.step(), StopReason::Stepped);
+        // writes FFE and FFF, then traps on 1000
+        assert_eq!(dbg.step(), StopReason::Error(EmulatorError::MemoryOutOfBounds { addr: 0x1000 }));
+
+        dbg.computer_mut().cpu_mut().pc = 0x204;
+        assert_eq!(dbg.step(), StopReason::Stepped);
+    }
+
+    #[test]
+    fn watchpoints_only_cover_their_range() {
+        let mut dbg = debugger(MEMORY);
+        let w = watch(0x301, 0x3FF, true, true);
+        dbg.add_watchpoint(w);
+        assert_eq!(dbg.cont(), StopReason::StepLimit);
+
+        let mut dbg = debugger(MEMORY);
+        dbg.add_watchpoint(w);
+        assert!(dbg.remove_watchpoint(&w));
+        assert!(!dbg.remove_watchpoint(&w));
+        assert!(dbg.watchpoints().is_empty());
+    }
+
+    #[test]
+    fn register_watches_fire_on_changes() {
+        let mut dbg = debugger(MEMORY);
+        dbg.add_register_watch(RegisterWatch { reg: Register::V(0), value: None });
+
+        let change = |pc, instruction, old, new| {
+            StopReason::Watchpoint(WatchHit { pc, instruction, event: WatchEvent::Register { reg: Register::V(0), old, new } })
+        };
+        assert_eq!(dbg.cont(), change(0x202, Instruction::LdVxByte { x: 0, kk: 7 }, 0, 7));
+        // reloading the same value at 0x206 and 0x208 isn't a change
+        assert_eq!(dbg.cont(), change(0x20A, Instruction::AddVxByte { x: 0, kk: 1 }, 7, 8));
+    }
+
+    #[test]
+    fn register_watches_can_wait_for_a_value() {
+        let mut dbg = debugger(MEMORY);
+        dbg.add_register_watch(RegisterWatch { reg: Register::V(0), value: Some(8) });
+        dbg.add_register_watch(RegisterWatch { reg: Register::I, value: Some(0x123) });
+
+        assert!(matches!(dbg.cont(), StopReason::Watchpoint(WatchHit { pc: 0x20A, .. })));
+    }
+
+    #[test]
+    fn reverse_steps_back_through_every_instruction() {
+        // 200 LD I 300, 202 RND V0 FF, 204 LD [I] V0, 206 ADD V1 1, 208 LD DT V1, 20A JP 202
+        let mut dbg = debugger(&[0xA3, 0x00, 0xC0, 0xFF, 0xF0, 0x55, 0x71, 0x01, 0xF1, 0x15, 0x12, 0x02]);
+        assert!(!dbg.reverse_step());
+
+        // far enough to need a snapshot other than the first one
+        let mut states = vec![dbg.computer().save_state()];
+        for _ in 0..HISTORY_INTERVAL + 200 {
+            assert_eq!(dbg.step(), StopReason::Stepped);
+            states.push(dbg.computer().save_state());
+        }
+
+        while let Some(state) = states.pop() {
+            assert_eq!(dbg.cycles(), states.len() as u64);
+            assert!(dbg.computer().save_state() == state, "differs after {} instructions", states.len());
+            assert_eq!(dbg.reverse_step(), !states.is_empty());
+        }
+
+        // and forward again the same way
+        assert_eq!(dbg.step(), StopReason::Stepped);
+        assert_eq!(dbg.cycles(), 1);
+    }
+}
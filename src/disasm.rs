@@ -0,0 +1,223 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use crate::instruction::{decode, Instruction};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LineKind {
+    Code(Instruction),
+    Data,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Line {
+    pub addr: u16,
+    pub bytes: Vec<u8>,
+    pub kind: LineKind,
+}
+
+pub struct Listing {
+    pub lines: Vec<Line>,
+    pub labels: BTreeMap<u16, String>,
+}
+
+// label prefixes, in order of precedence when an address is used more than one way
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum LabelKind {
+    Data,
+    Jump,
+    Sub,
+}
+
+impl LabelKind {
+    fn name(&self, addr: u16) -> String {
+        match self {
+            LabelKind::Data => format!("D{:03X}", addr),
+            LabelKind::Jump => format!("L{:03X}", addr),
+            LabelKind::Sub => format!("S{:03X}", addr),
+        }
+    }
+}
+
+fn is_skip(ins: &Instruction) -> bool {
+    matches!(ins,
+        Instruction::SeVxByte { .. } | Instruction::SneVxByte { .. } |
+        Instruction::SeVxVy { .. } | Instruction::SneVxVy { .. } |
+        Instruction::SkpVx { .. } | Instruction::SknpVx { .. })
+}
+
+fn word(rom: &[u8], off: usize) -> Option<u16> {
+    if off + 1 < rom.len() {
+        Some(((rom[off] as u16) << 8) | rom[off + 1] as u16)
+    }
+    else {
+        None
+    }
+}
+
+fn add_ref(refs: &mut BTreeMap<u16, LabelKind>, addr: u16, kind: LabelKind) {
+    let entry = refs.entry(addr).or_insert(kind);
+    *entry = (*entry).max(kind);
+}
+
+// bytes that fit in the 64 KiB address space from `origin` on
+pub fn addressable(rom: &[u8], origin: u16) -> &[u8] {
+    &rom[..rom.len().min(0x10000 - origin as usize)]
+}
+
+// follows control flow from the entry point; anything never reached is treated as data.
+// Bytes past the end of the address space are left out, see addressable()
+pub fn disassemble(rom: &[u8], origin: u16) -> Listing {
+    let rom = addressable(rom, origin);
+    let mut starts: Vec<Option<Instruction>> = vec![None; rom.len()];
+    let mut covered = vec![false; rom.len()];
+    let mut refs: BTreeMap<u16, LabelKind> = BTreeMap::new();
+
+    let mut work = vec![origin];
+    while let Some(entry) = work.pop() {
+        let mut pc = entry;
+
+        while let Some(off) = (pc as usize).checked_sub(origin as usize) {
+            let op = match word(rom, off) {
+                Some(op) => op,
+                None => break,
+            };
+            if starts[off].is_some() || covered[off] || covered[off + 1] {
+                break;
+            }
+            let ins = match decode(op) {
+                Ok(ins) => ins,
+                Err(_) => break,
+            };
+
+            let size = ins.size() as usize;
+            if off + size > rom.len() {
+                break;
+            }
+            starts[off] = Some(ins);
+            covered[off..off + size].fill(true);
+
+            let next = pc.wrapping_add(size as u16);
+
+            match ins {
+                Instruction::Jp(addr) => {
+                    add_ref(&mut refs, addr, LabelKind::Jump);
+                    work.push(addr);
+                    break;
+                },
+                Instruction::JpV0(addr) => {
+                    // jump tables can't be followed statically
+                    add_ref(&mut refs, addr, LabelKind::Jump);
+                    break;
+                },
+                Instruction::Call(addr) => {
+                    add_ref(&mut refs, addr, LabelKind::Sub);
+                    work.push(addr);
+                },
+                Instruction::Ret | Instruction::Exit => break,
+                Instruction::LdI(addr) => {
+                    add_ref(&mut refs, addr, LabelKind::Data);
+                },
+                Instruction::LdILong => {
+                    add_ref(&mut refs, word(rom, off + 2).unwrap(), LabelKind::Data);
+                },
+                _ if is_skip(&ins) => {
+                    let skipped = match word(rom, off + size) {
+                        Some(0xF000) => 4,
+                        _ => 2,
+                    };
+                    work.push(next.wrapping_add(skipped));
+                },
+                _ => {}
+            }
+
+            pc = next;
+        }
+    }
+
+    let mut lines = Vec::new();
+    let mut off = 0;
+    while off < rom.len() {
+        let addr = origin + off as u16;
+
+        if let Some(ins) = starts[off] {
+            let size = ins.size() as usize;
+            lines.push(Line { addr, bytes: rom[off..off + size].to_vec(), kind: LineKind::Code(ins) });
+            off += size;
+            continue;
+        }
+
+        // up to 6 data bytes per line, broken up at code and referenced addresses
+        let mut end = off + 1;
+        while end < rom.len() && end - off < 6 && !covered[end] && !refs.contains_key(&(origin + end as u16)) {
+            end += 1;
+        }
+        lines.push(Line { addr, bytes: rom[off..end].to_vec(), kind: LineKind::Data });
+        off = end;
+    }
+
+    // only keep labels that land on the start of a line
+    let labels = refs.into_iter()
+        .filter(|(addr, _)| lines.iter().any(|l| l.addr == *addr))
+        .map(|(addr, kind)| (addr, kind.name(addr)))
+        .collect();
+
+    Listing { lines, labels }
+}
+
+impl Listing {
+    fn target(&self, addr: u16) -> String {
+        match self.labels.get(&addr) {
+            Some(label) => label.clone(),
+            None => format!("0x{:03X}", addr),
+        }
+    }
+
+    pub fn mnemonic(&self, line: &Line) -> String {
+        match line.kind {
+            LineKind::Code(Instruction::Jp(addr)) => format!("JP {}", self.target(addr)),
+            LineKind::Code(Instruction::Call(addr)) => format!("CALL {}", self.target(addr)),
+            LineKind::Code(Instruction::LdI(addr)) => format!("LD I, {}", self.target(addr)),
+            LineKind::Code(Instruction::JpV0(addr)) => format!("JP V0, {}", self.target(addr)),
+            LineKind::Code(Instruction::LdILong) => {
+                let addr = ((line.bytes[2] as u16) << 8) | line.bytes[3] as u16;
+                match self.labels.get(&addr) {
+                    Some(label) => format!("LD I, LONG {}", label),
+                    None => format!("LD I, LONG 0x{:04X}", addr),
+                }
+            },
+            LineKind::Code(ins) => ins.to_string(),
+            LineKind::Data => {
+                let bytes: Vec<String> = line.bytes.iter().map(|b| format!("0x{:02X}", b)).collect();
+                format!("DB {}", bytes.join(", "))
+            },
+        }
+    }
+}
+
+impl fmt::Display for Listing {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for line in &self.lines {
+            if let Some(label) = self.labels.get(&line.addr) {
+                writeln!(f, "{}:", label)?;
+            }
+
+            let hex: Vec<String> = line.bytes.iter().map(|b| format!("{:02X}", b)).collect();
+            writeln!(f, "    {:<38}; {:03X}  {}", self.mnemonic(line), line.addr, hex.join(""))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stops_at_the_end_of_the_address_space() {
+        let rom = vec![0x12; 70_000];
+        let listing = disassemble(&rom, 0x200);
+
+        let last = listing.lines.last().unwrap();
+        assert_eq!(last.addr as usize + last.bytes.len(), 0x10000);
+    }
+}
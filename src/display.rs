@@ -1,39 +1,252 @@
+use crate::state::{StateError, StateReader, StateWriter};
 
+pub const WIDTH: usize = 128;
+pub const HEIGHT: usize = 64;
+
+// each pixel holds one bit per bitplane; plain CHIP-8 and SUPER-CHIP only use plane 1
 #[derive(Copy, Clone)]
 pub struct Display {
-    framebuffer: [bool; 64*32],
+    framebuffer: [u8; WIDTH*HEIGHT],
+    hires: bool,
+    planes: u8,
+}
+
+impl Default for Display {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Display {
     pub fn new() -> Self {
-        Self { framebuffer: [true; 64*32] }
+        Self { framebuffer: [0; WIDTH*HEIGHT], hires: false, planes: 0x01 }
+    }
+
+    // 64x32 in low resolution mode, 128x64 in SUPER-CHIP high resolution mode
+    pub fn width(&self) -> usize {
+        if self.hires { WIDTH } else { WIDTH / 2 }
+    }
+
+    pub fn height(&self) -> usize {
+        if self.hires { HEIGHT } else { HEIGHT / 2 }
+    }
+
+    pub fn hires(&self) -> bool {
+        self.hires
+    }
+
+    pub fn set_hires(&mut self, hires: bool) {
+        self.hires = hires;
+        self.framebuffer = [0; WIDTH*HEIGHT];
+    }
+
+    // bitmask of the planes that drawing, clearing and scrolling act on (XO-CHIP Fn01)
+    pub fn planes(&self) -> u8 {
+        self.planes
+    }
+
+    pub fn set_planes(&mut self, planes: u8) {
+        self.planes = planes & 0x03;
+    }
+
+    // colour index 0-3 made up of the bits of both planes
+    pub fn pixel(&self, x: usize, y: usize) -> u8 {
+        if x < self.width() && y < self.height() {
+            self.framebuffer[y * WIDTH + x]
+        }
+        else {
+            0
+        }
     }
 
     pub fn set(&mut self, x: usize, y: usize, data: bool) {
-        let idx = y * 64 + x;
-        if idx < 2048 {
-            self.framebuffer[idx] = data;
+        if x < self.width() && y < self.height() {
+            let idx = y * WIDTH + x;
+            if data {
+                self.framebuffer[idx] |= self.planes;
+            }
+            else {
+                self.framebuffer[idx] &= !self.planes;
+            }
         }
         //println!("idx: {}, fb: {}, data: {}", idx, self.framebuffer[idx], data);
     }
 
     pub fn get(&self, x: usize, y: usize) -> bool {
-        let idx = y * 64 + x;
-        if idx < 2048 {
-            self.framebuffer[idx]
+        self.pixel(x, y) != 0
+    }
+
+    // flips one pixel on a single plane, returns true if it was switched off
+    pub fn toggle(&mut self, x: usize, y: usize, plane: u8) -> bool {
+        if x < self.width() && y < self.height() {
+            let idx = y * WIDTH + x;
+            let was_set = self.framebuffer[idx] & plane != 0;
+            self.framebuffer[idx] ^= plane;
+            was_set
         }
-        else{
+        else {
             false
         }
     }
 
     pub fn clear(&mut self) {
-        for i in 0..64*32 {
-            self.framebuffer[i] = false;
+        for px in self.framebuffer.iter_mut() {
+            *px &= !self.planes;
+        }
+    }
+
+    fn scroll(&mut self, dx: isize, dy: isize) {
+        let old = self.framebuffer;
+        let (width, height) = (self.width() as isize, self.height() as isize);
+
+        for y in 0..height {
+            for x in 0..width {
+                let (sx, sy) = (x - dx, y - dy);
+                let moved = if sx >= 0 && sx < width && sy >= 0 && sy < height {
+                    old[(sy as usize) * WIDTH + sx as usize] & self.planes
+                }
+                else {
+                    0
+                };
+
+                let idx = (y as usize) * WIDTH + x as usize;
+                self.framebuffer[idx] = (old[idx] & !self.planes) | moved;
+            }
+        }
+    }
+
+    pub fn scroll_down(&mut self, n: usize) {
+        self.scroll(0, n as isize);
+    }
+
+    pub fn scroll_up(&mut self, n: usize) {
+        self.scroll(0, -(n as isize));
+    }
+
+    pub fn scroll_right(&mut self, n: usize) {
+        self.scroll(n as isize, 0);
+    }
+
+    pub fn scroll_left(&mut self, n: usize) {
+        self.scroll(-(n as isize), 0);
+    }
+
+    // plain PBM (P1) image of the visible screen
+    pub fn to_pbm(&self) -> String {
+        let mut out = format!("P1\n{} {}\n", self.width(), self.height());
+        for y in 0..self.height() {
+            out.extend((0..self.width()).map(|x| if self.get(x, y) { '1' } else { '0' }));
+            out.push('\n');
+        }
+        out
+    }
+
+    pub(crate) fn write_state(&self, out: &mut StateWriter) {
+        out.bool(self.hires);
+        out.u8(self.planes);
+        out.bytes(&self.framebuffer);
+    }
+
+    pub(crate) fn read_state(r: &mut StateReader) -> Result<Self, StateError> {
+        let hires = r.bool()?;
+        let planes = r.u8()?;
+        let mut framebuffer = [0; WIDTH*HEIGHT];
+        framebuffer.copy_from_slice(r.bytes(WIDTH*HEIGHT)?);
+
+        if planes > 0x03 || framebuffer.iter().any(|px| *px > 0x03) {
+            return Err(StateError::Invalid("display"));
         }
+        Ok(Self { framebuffer, hires, planes })
     }
 
     pub fn dump(&self) -> Vec<bool> {
-        self.framebuffer.clone().to_vec()
+        let mut out = Vec::with_capacity(self.width() * self.height());
+        for y in 0..self.height() {
+            for x in 0..self.width() {
+                out.push(self.get(x, y));
+            }
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lit(display: &Display) -> Vec<(usize, usize)> {
+        let mut out = Vec::new();
+        for y in 0..display.height() {
+            for x in 0..display.width() {
+                if display.get(x, y) {
+                    out.push((x, y));
+                }
+            }
+        }
+        out
+    }
+
+    fn with_pixels(hires: bool, pixels: &[(usize, usize)]) -> Display {
+        let mut display = Display::new();
+        display.set_hires(hires);
+        for (x, y) in pixels {
+            display.set(*x, *y, true);
+        }
+        display
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn switching_resolution_clears_and_resizes() {
+        let mut display = with_pixels(false, &[(3, 4)]);
+        assert_eq!((display.width(), display.height()), (64, 32));
+
+        display.set_hires(true);
+        assert!(display.hires());
+        assert_eq!((display.width(), display.height()), (128, 64));
+        assert_eq!(lit(&display), []);
+
+        // off the low resolution screen, so ignored
+        display.set_hires(false);
+        display.set(100, 40, true);
+        assert_eq!(lit(&display), []);
+    }
+
+    #[test]
+    fn scrolls_by_whole_pixels_in_low_resolution() {
+        let mut display = with_pixels(false, &[(0, 0), (63, 31), (10, 28)]);
+        display.scroll_down(3);
+        assert_eq!(lit(&display), [(0, 3), (10, 31)]);
+
+        display.scroll_up(2);
+        assert_eq!(lit(&display), [(0, 1), (10, 29)]);
+
+        display.scroll_right(4);
+        assert_eq!(lit(&display), [(4, 1), (14, 29)]);
+
+        display.scroll_left(5);
+        assert_eq!(lit(&display), [(9, 29)]);
+    }
+
+    #[test]
+    fn scrolls_in_high_resolution() {
+        let mut display = with_pixels(true, &[(0, 0), (127, 63), (100, 40)]);
+        display.scroll_down(15);
+        assert_eq!(lit(&display), [(0, 15), (100, 55)]);
+
+        display.scroll_right(4);
+        assert_eq!(lit(&display), [(4, 15), (104, 55)]);
+
+        display.scroll_left(10);
+        assert_eq!(lit(&display), [(94, 55)]);
+    }
+
+    #[test]
+    fn scrolling_only_moves_the_selected_planes() {
+        let mut display = with_pixels(false, &[(1, 1)]);
+        display.set_planes(0x02);
+        display.set(5, 5, true);
+
+        display.scroll_down(1);
+        assert_eq!((display.pixel(1, 1), display.pixel(5, 6), display.pixel(5, 5)), (0x01, 0x02, 0));
+    }
+}
@@ -0,0 +1,29 @@
+use std::error::Error;
+use std::fmt;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EmulatorError {
+    UnknownOpcode { pc: u16, opcode: u16 },
+    StackOverflow,
+    StackUnderflow,
+    MemoryOutOfBounds { addr: usize },
+    PcOutOfRange { pc: u16 },
+}
+
+impl fmt::Display for EmulatorError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EmulatorError::UnknownOpcode { pc, opcode } => {
+                write!(f, "unknown opcode {:#06x} at {:#05x}", opcode, pc)
+            },
+            EmulatorError::StackOverflow => write!(f, "stack overflow"),
+            EmulatorError::StackUnderflow => write!(f, "stack underflow"),
+            EmulatorError::MemoryOutOfBounds { addr } => {
+                write!(f, "memory access out of bounds at {:#06x}", addr)
+            },
+            EmulatorError::PcOutOfRange { pc } => write!(f, "program counter out of range: {:#06x}", pc),
+        }
+    }
+}
+
+impl Error for EmulatorError {}
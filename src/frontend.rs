@@ -0,0 +1,185 @@
+use std::thread;
+use std::time::{Duration, Instant};
+use crate::computer::Computer;
+use crate::display::Display;
+use crate::movie::Replay;
+use crate::rewind::Rewind;
+
+// CHIP-8 timers run at 60 Hz, so that's one frame of emulated time
+pub const FRAME_RATE: u32 = 60;
+pub const SAVE_SLOTS: usize = 4;
+// ten seconds of frames
+const REWIND_FRAMES: usize = 600;
+
+// what a frontend reports between frames
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Input {
+    Press(u8),
+    Release(u8),
+    Slot(usize),    // pick the quick save slot Save and Load use
+    Save,
+    Load,
+    Rewind(bool),   // step backwards one frame at a time while held
+    Quit,
+}
+
+// a video, audio and input backend the runner drives once per frame
+pub trait Frontend {
+    fn present(&mut self, display: &Display);
+
+    // everything that happened since the last call, without blocking
+    fn poll_input(&mut self) -> Vec<Input>;
+
+    // the rate push_audio() wants samples at, None to skip generating them
+    fn sample_rate(&self) -> Option<u32> {
+        None
+    }
+
+    // one frame of mono samples in -1.0..=1.0
+    fn push_audio(&mut self, _samples: &[f32]) {}
+
+    // status lines such as "saved to slot 1" or why the program stopped
+    fn message(&mut self, _text: &str) {}
+}
+
+// the main loop: runs a computer at a fixed number of instructions per frame,
+// feeding it the frontend's input and handing back its screen and sound
+pub struct Runner {
+    comp: Computer,
+    cycles_per_frame: usize,
+    frame_rate: Option<u32>,
+    replay: Option<Replay>,
+    history: Rewind,
+    slots: [Option<Vec<u8>>; SAVE_SLOTS],
+    slot: usize,
+    rewinding: bool,
+    halted: bool,
+}
+
+impl Runner {
+    pub fn new(comp: Computer, cycles_per_frame: usize) -> Self {
+        Self {
+            comp,
+            cycles_per_frame,
+            frame_rate: Some(FRAME_RATE),
+            replay: None,
+            history: Rewind::new(REWIND_FRAMES),
+            slots: Default::default(),
+            slot: 0,
+            rewinding: false,
+            halted: false,
+        }
+    }
+
+    // frames per second run() keeps to, None to run as fast as possible
+    pub fn set_frame_rate(&mut self, frame_rate: Option<u32>) {
+        self.frame_rate = frame_rate;
+    }
+
+    // input from a movie, applied on top of the frontend's
+    pub fn set_replay(&mut self, replay: Replay) {
+        self.replay = Some(replay);
+    }
+
+    pub fn computer(&self) -> &Computer {
+        &self.comp
+    }
+
+    pub fn computer_mut(&mut self) -> &mut Computer {
+        &mut self.comp
+    }
+
+    pub fn into_computer(self) -> Computer {
+        self.comp
+    }
+
+    // set once the program fails, until a load or rewind gets it going again
+    pub fn is_halted(&self) -> bool {
+        self.halted
+    }
+
+    // runs until the frontend quits
+    pub fn run(&mut self, frontend: &mut dyn Frontend) {
+        let mut deadline = Instant::now();
+
+        while self.frame(frontend) {
+            if let Some(rate) = self.frame_rate.filter(|r| *r > 0) {
+                deadline += Duration::from_secs(1) / rate;
+                let now = Instant::now();
+                if deadline > now {
+                    thread::sleep(deadline - now);
+                }
+                else {
+                    // too slow to keep up, don't try to catch up later
+                    deadline = now;
+                }
+            }
+        }
+    }
+
+    // keeps a replay in step with the computer after it moved back in time
+    fn seek_replay(&mut self) {
+        if let Some(replay) = &mut self.replay {
+            replay.seek(self.comp.frames());
+        }
+    }
+
+    // handles input, then runs and presents one frame; false once the frontend quits
+    pub fn frame(&mut self, frontend: &mut dyn Frontend) -> bool {
+        for input in frontend.poll_input() {
+            match input {
+                Input::Press(key) => self.comp.press(key),
+                Input::Release(key) => self.comp.release(key),
+                Input::Slot(slot) => {
+                    self.slot = slot % SAVE_SLOTS;
+                    frontend.message(&format!("save slot {}", self.slot + 1));
+                },
+                Input::Save => {
+                    self.slots[self.slot] = Some(self.comp.save_state());
+                    frontend.message(&format!("saved to slot {}", self.slot + 1));
+                },
+                Input::Load => match &self.slots[self.slot] {
+                    Some(state) => match self.comp.load_state(state) {
+                        Ok(()) => {
+                            self.seek_replay();
+                            self.halted = false;
+                            frontend.message(&format!("loaded slot {}", self.slot + 1));
+                        },
+                        Err(e) => frontend.message(&e.to_string()),
+                    },
+                    None => frontend.message(&format!("slot {} is empty", self.slot + 1)),
+                },
+                Input::Rewind(held) => self.rewinding = held,
+                Input::Quit => return false,
+            }
+        }
+
+        if self.rewinding {
+            if let Some((_, state)) = self.history.pop() {
+                if self.comp.load_state(&state).is_ok() {
+                    self.seek_replay();
+                    self.halted = false;
+                }
+            }
+        }
+        else if !self.halted {
+            if let Some(replay) = &mut self.replay {
+                replay.apply(&mut self.comp);
+            }
+            self.history.push(self.comp.frames(), self.comp.save_state());
+            if let Err(e) = self.comp.run_frame(self.cycles_per_frame) {
+                frontend.message(&e.to_string());
+                self.halted = true;
+            }
+        }
+
+        if let Some(rate) = frontend.sample_rate() {
+            let mut samples = vec![0.0; (rate / FRAME_RATE) as usize];
+            self.comp.audio_mut().fill(&mut samples, rate);
+            frontend.push_audio(&samples);
+        }
+        frontend.present(&self.comp.display());
+
+        true
+    }
+}
@@ -0,0 +1,471 @@
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::ops::Range;
+use crate::debugger::{Debugger, StopReason, WatchEvent, Watchpoint};
+use crate::error::EmulatorError;
+use crate::memory::AccessKind;
+
+// registers in g/G packet order: V0-VF, I, PC, SP, DT, ST; the 16 bit ones are big endian like CHIP-8 memory
+const REGISTERS: [(&str, usize); 21] = [
+    ("v0", 1), ("v1", 1), ("v2", 1), ("v3", 1), ("v4", 1), ("v5", 1), ("v6", 1), ("v7", 1),
+    ("v8", 1), ("v9", 1), ("va", 1), ("vb", 1), ("vc", 1), ("vd", 1), ("ve", 1), ("vf", 1),
+    ("i", 2), ("pc", 2), ("sp", 1), ("dt", 1), ("st", 1),
+];
+
+// largest packet we accept, as advertised in qSupported; a memory reply is
+// two hex digits per byte, so m and M move at most half that
+const PACKET_SIZE: usize = 0x4000;
+
+// continue runs this many instructions at a time, checking for ^C in between
+const CONTINUE_CHUNK: u64 = 10_000;
+
+const MONITOR_HELP: &str = "monitor commands: press K, release K\n";
+
+// waits for one debugger to connect on `addr` and serves it until it detaches
+pub fn serve(dbg: &mut Debugger, addr: impl ToSocketAddrs) -> io::Result<()> {
+    let listener = TcpListener::bind(addr)?;
+    let (stream, _) = listener.accept()?;
+    serve_connection(dbg, stream)
+}
+
+pub fn serve_connection(dbg: &mut Debugger, stream: TcpStream) -> io::Result<()> {
+    stream.set_nodelay(true)?;
+    Session { dbg, stream, ack: true }.run()
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn from_hex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..s.len()).step_by(2).map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok()).collect()
+}
+
+fn checksum(data: &str) -> u8 {
+    data.bytes().fold(0u8, |sum, b| sum.wrapping_add(b))
+}
+
+// "addr,len" as used by m, M, Z and z
+fn parse_addr_len(s: &str) -> Option<(usize, usize)> {
+    let (addr, len) = s.split_once(',')?;
+    Some((usize::from_str_radix(addr, 16).ok()?, usize::from_str_radix(len, 16).ok()?))
+}
+
+// the addresses an m or M packet covers, if they fit in memory and a packet
+fn memory_range(addr: usize, len: usize, memory_len: usize) -> Option<Range<usize>> {
+    let end = addr.checked_add(len)?;
+    (len <= PACKET_SIZE / 2 && end <= memory_len).then_some(addr..end)
+}
+
+fn target_xml() -> String {
+    let mut xml = String::from("<?xml version=\"1.0\"?>\n<!DOCTYPE target SYSTEM \"gdb-target.dtd\">\n<target version=\"1.0\">\n<feature name=\"org.chip8.core\">\n");
+    for (name, size) in REGISTERS {
+        let kind = match name {
+            "pc" => "code_ptr",
+            "i" => "data_ptr",
+            _ => "int",
+        };
+        xml.push_str(&format!("<reg name=\"{}\" bitsize=\"{}\" type=\"{}\"/>\n", name, size * 8, kind));
+    }
+    xml.push_str("</feature>\n</target>\n");
+    xml
+}
+
+fn stop_reply(reason: StopReason) -> String {
+    match reason {
+        StopReason::Exited => "W00".to_string(),
+        StopReason::Error(EmulatorError::UnknownOpcode { .. }) => "S04".to_string(),
+        StopReason::Error(_) => "S0b".to_string(),
+        StopReason::Watchpoint(hit) => match hit.event {
+            WatchEvent::Memory(access) => {
+                let kind = match access.kind {
+                    AccessKind::Read => "rwatch",
+                    AccessKind::Write => "watch",
+                };
+                format!("T05{}:{:x};", kind, access.addr)
+            },
+            WatchEvent::Register { .. } => "S05".to_string(),
+        },
+        _ => "S05".to_string(),
+    }
+}
+
+struct Session<'a> {
+    dbg: &'a mut Debugger,
+    stream: TcpStream,
+    ack: bool,
+}
+
+impl<'a> Session<'a> {
+    fn run(&mut self) -> io::Result<()> {
+        while let Some(packet) = self.read_packet()? {
+            match self.handle(&packet) {
+                Some(reply) => self.send(&reply)?,
+                None => return Ok(()),
+            }
+            if packet == "QStartNoAckMode" {
+                self.ack = false;
+            }
+        }
+        Ok(())
+    }
+
+    fn read_byte(&mut self) -> io::Result<Option<u8>> {
+        let mut byte = [0];
+        match self.stream.read(&mut byte)? {
+            0 => Ok(None),
+            _ => Ok(Some(byte[0])),
+        }
+    }
+
+    // the contents of the next well formed packet, None once the connection closes
+    fn read_packet(&mut self) -> io::Result<Option<String>> {
+        loop {
+            // skip acks and stray interrupts between packets
+            loop {
+                match self.read_byte()? {
+                    Some(b'$') => break,
+                    Some(_) => continue,
+                    None => return Ok(None),
+                }
+            }
+
+            let mut data = Vec::new();
+            loop {
+                match self.read_byte()? {
+                    Some(b'#') => break,
+                    Some(b) => data.push(b),
+                    None => return Ok(None),
+                }
+            }
+
+            let mut sum = [0; 2];
+            self.stream.read_exact(&mut sum)?;
+            let data = String::from_utf8_lossy(&data).into_owned();
+            let valid = std::str::from_utf8(&sum).ok()
+                .and_then(|s| u8::from_str_radix(s, 16).ok())
+                .is_some_and(|s| s == checksum(&data));
+
+            if self.ack {
+                self.stream.write_all(if valid { b"+" } else { b"-" })?;
+            }
+            if valid {
+                return Ok(Some(data));
+            }
+        }
+    }
+
+    fn send(&mut self, data: &str) -> io::Result<()> {
+        write!(self.stream, "${}#{:02x}", data, checksum(data))?;
+        self.stream.flush()
+    }
+
+    // the reply to a packet, None to end the session
+    fn handle(&mut self, packet: &str) -> Option<String> {
+        let (cmd, args) = packet.split_at(packet.chars().next().map_or(0, char::len_utf8));
+
+        let reply = match cmd {
+            "?" => "S05".to_string(),
+            "g" => self.read_registers(),
+            "G" => self.write_registers(args),
+            "p" => usize::from_str_radix(args, 16).ok()
+                .and_then(|n| self.read_register(n))
+                .unwrap_or_else(|| "E01".to_string()),
+            "P" => self.write_register(args),
+            "m" => self.read_memory(args),
+            "M" => self.write_memory(args),
+            "Z" | "z" => self.breakpoint(cmd == "Z", args),
+            "s" => stop_reply(self.dbg.step()),
+            "c" => self.cont(),
+            "H" => "OK".to_string(),
+            "D" => {
+                let _ = self.send("OK");
+                return None;
+            },
+            "k" => return None,
+            "q" | "Q" | "v" => self.query(packet),
+            _ => String::new(),
+        };
+
+        Some(reply)
+    }
+
+    fn query(&mut self, packet: &str) -> String {
+        if packet.starts_with("qSupported") {
+            return format!("PacketSize={:x};qXfer:features:read+;QStartNoAckMode+", PACKET_SIZE);
+        }
+        if packet == "QStartNoAckMode" {
+            return "OK".to_string();
+        }
+        if let Some(range) = packet.strip_prefix("qXfer:features:read:target.xml:") {
+            let xml = target_xml();
+            let (offset, len) = match parse_addr_len(range) {
+                Some(range) => range,
+                None => return "E01".to_string(),
+            };
+            let end = offset.saturating_add(len);
+            let chunk = xml.get(offset.min(xml.len())..end.min(xml.len())).unwrap_or("");
+            let more = end < xml.len();
+            return format!("{}{}", if more { "m" } else { "l" }, chunk);
+        }
+        if let Some(cmd) = packet.strip_prefix("qRcmd,") {
+            return self.monitor(cmd);
+        }
+
+        match packet {
+            "qAttached" => "1".to_string(),
+            "qC" => "QC1".to_string(),
+            "qfThreadInfo" => "m1".to_string(),
+            "qsThreadInfo" => "l".to_string(),
+            _ => String::new(),
+        }
+    }
+
+    fn monitor(&mut self, hex: &str) -> String {
+        let cmd = match from_hex(hex) {
+            Some(bytes) => String::from_utf8_lossy(&bytes).into_owned(),
+            None => return "E01".to_string(),
+        };
+
+        let words: Vec<&str> = cmd.split_whitespace().collect();
+        let key = words.get(1).and_then(|k| u8::from_str_radix(k, 16).ok()).filter(|k| *k <= 0x0F);
+
+        match (words.first().copied(), key) {
+            (Some("press"), Some(key)) => self.dbg.computer_mut().press(key),
+            (Some("release"), Some(key)) => self.dbg.computer_mut().release(key),
+            _ => {
+                let _ = self.send(&format!("O{}", to_hex(MONITOR_HELP.as_bytes())));
+            },
+        }
+        "OK".to_string()
+    }
+
+    fn cont(&mut self) -> String {
+        let limit = self.dbg.step_limit();
+        self.dbg.set_step_limit(CONTINUE_CHUNK);
+
+        let reason = loop {
+            let reason = self.dbg.cont();
+            if reason != StopReason::StepLimit {
+                break reason;
+            }
+
+            // the next chunk would start on this breakpoint and run past it
+            let pc = self.dbg.computer().cpu().pc;
+            if self.dbg.breakpoints().any(|b| b == pc) {
+                break StopReason::Breakpoint(pc);
+            }
+            if self.interrupted() {
+                break StopReason::Stepped;
+            }
+        };
+
+        self.dbg.set_step_limit(limit);
+        stop_reply(reason)
+    }
+
+    // true if the debugger sent ^C while the program was running
+    fn interrupted(&mut self) -> bool {
+        if self.stream.set_nonblocking(true).is_err() {
+            return false;
+        }
+        let mut byte = [0];
+        let got = matches!(self.stream.read(&mut byte), Ok(1) if byte[0] == 0x03);
+        let _ = self.stream.set_nonblocking(false);
+        got
+    }
+
+    fn read_register(&self, n: usize) -> Option<String> {
+        let cpu = self.dbg.computer().cpu();
+        let bytes = match n {
+            0..=15 => vec![cpu.v[n]],
+            16 => cpu.i.to_be_bytes().to_vec(),
+            17 => cpu.pc.to_be_bytes().to_vec(),
+            18 => vec![cpu.sp],
+            19 => vec![cpu.dt],
+            20 => vec![cpu.st],
+            _ => return None,
+        };
+        Some(to_hex(&bytes))
+    }
+
+    fn set_register(&mut self, n: usize, bytes: &[u8]) -> bool {
+        if REGISTERS.get(n).map(|r| r.1) != Some(bytes.len()) {
+            return false;
+        }
+
+        let cpu = self.dbg.computer_mut().cpu_mut();
+        match n {
+            0..=15 => cpu.v[n] = bytes[0],
+            16 => cpu.i = u16::from_be_bytes([bytes[0], bytes[1]]),
+            17 => cpu.pc = u16::from_be_bytes([bytes[0], bytes[1]]),
+            18 => cpu.sp = bytes[0].min(16),
+            19 => cpu.dt = bytes[0],
+            _ => cpu.st = bytes[0],
+        }
+        true
+    }
+
+    fn read_registers(&self) -> String {
+        (0..REGISTERS.len()).filter_map(|n| self.read_register(n)).collect()
+    }
+
+    fn write_registers(&mut self, args: &str) -> String {
+        let bytes = match from_hex(args) {
+            Some(bytes) if bytes.len() == REGISTERS.iter().map(|r| r.1).sum::<usize>() => bytes,
+            _ => return "E01".to_string(),
+        };
+
+        let mut pos = 0;
+        for (n, (_, size)) in REGISTERS.iter().enumerate() {
+            self.set_register(n, &bytes[pos..pos + size]);
+            pos += size;
+        }
+        "OK".to_string()
+    }
+
+    fn write_register(&mut self, args: &str) -> String {
+        let parsed = args.split_once('=')
+            .and_then(|(n, value)| Some((usize::from_str_radix(n, 16).ok()?, from_hex(value)?)));
+
+        match parsed {
+            Some((n, bytes)) if self.set_register(n, &bytes) => "OK".to_string(),
+            _ => "E01".to_string(),
+        }
+    }
+
+    fn read_memory(&self, args: &str) -> String {
+        let memory = self.dbg.computer().memory();
+        let bytes: Option<Vec<u8>> = parse_addr_len(args)
+            .and_then(|(addr, len)| memory_range(addr, len, memory.len()))
+            .and_then(|range| range.map(|a| memory.read(a).ok()).collect());
+
+        match bytes {
+            Some(bytes) => to_hex(&bytes),
+            None => "E01".to_string(),
+        }
+    }
+
+    fn write_memory(&mut self, args: &str) -> String {
+        let parsed = args.split_once(':')
+            .and_then(|(range, data)| Some((parse_addr_len(range)?, from_hex(data)?)));
+
+        let memory = self.dbg.computer_mut().memory_mut();
+        let (range, data) = match parsed {
+            Some(((addr, len), data)) if len == data.len() => match memory_range(addr, len, memory.len()) {
+                Some(range) => (range, data),
+                None => return "E01".to_string(),
+            },
+            _ => return "E01".to_string(),
+        };
+
+        for (a, byte) in range.zip(data) {
+            if memory.write(a, byte).is_err() {
+                return "E01".to_string();
+            }
+        }
+        "OK".to_string()
+    }
+
+    // Z0/Z1 set code breakpoints, Z2/Z3/Z4 write, read and access watchpoints
+    fn breakpoint(&mut self, insert: bool, args: &str) -> String {
+        let mut parts = args.splitn(3, ',');
+        let kind = parts.next();
+        let addr = parts.next().and_then(|a| usize::from_str_radix(a, 16).ok());
+        let len = parts.next().and_then(|l| usize::from_str_radix(l, 16).ok()).unwrap_or(1).max(1);
+
+        // code breakpoints are on a 16 bit PC, watchpoints anywhere in the address space
+        let (addr, end) = match addr.and_then(|a| Some((a, a.checked_add(len - 1)?))) {
+            Some((addr, end)) if addr <= u16::MAX as usize => (addr, end),
+            _ => return "E01".to_string(),
+        };
+        let watchpoint = |read, write| Watchpoint { start: addr, end, read, write };
+
+        match (kind, insert) {
+            (Some("0" | "1"), true) => {
+                self.dbg.add_breakpoint(addr as u16);
+            },
+            (Some("0" | "1"), false) => {
+                self.dbg.remove_breakpoint(addr as u16);
+            },
+            (Some(kind @ ("2" | "3" | "4")), _) => {
+                let wp = match kind {
+                    "2" => watchpoint(false, true),
+                    "3" => watchpoint(true, false),
+                    _ => watchpoint(true, true),
+                };
+                if insert {
+                    self.dbg.add_watchpoint(wp);
+                }
+                else {
+                    self.dbg.remove_watchpoint(&wp);
+                }
+            },
+            _ => return String::new(),
+        }
+        "OK".to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::computer::Computer;
+    use crate::quirks::Quirks;
+
+    // replies to each packet in turn, from a session over a loopback connection
+    fn replies(dbg: &mut Debugger, packets: &[&str]) -> Vec<String> {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let _client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+
+        let mut session = Session { dbg, stream, ack: true };
+        packets.iter().map(|p| session.handle(p).unwrap()).collect()
+    }
+
+    fn debugger() -> Debugger {
+        Debugger::new(Computer::with_seed(vec![0x12, 0x34], Quirks::default(), 0), 10)
+    }
+
+    #[test]
+    fn reads_and_writes_memory() {
+        let mut dbg = debugger();
+        assert_eq!(replies(&mut dbg, &["m200,2", "M300,2:abcd", "m2ff,3"]), ["1234", "OK", "00abcd"]);
+    }
+
+    #[test]
+    fn rejects_memory_ranges_that_dont_fit() {
+        let mut dbg = debugger();
+        let packets = ["mffffffffffffffff,10", "m0,ffffffff", "mfff,2", "m0,2001", "Mffffffffffffffff,1:00", "Mfff,2:0000"];
+        assert_eq!(replies(&mut dbg, &packets), ["E01"; 6]);
+
+        // the whole of memory, one packet's worth at a time
+        let mut dbg = debugger();
+        assert_eq!(replies(&mut dbg, &["m0,800", "m800,800"]).concat().len(), 4096 * 2);
+    }
+
+    #[test]
+    fn rejects_breakpoints_that_dont_fit() {
+        let mut dbg = debugger();
+        let packets = ["Z0,ffffffffffffffff,2", "Z0,10000,2", "Z2,ffffffffffffffff,2", "Z3,2,ffffffffffffffff"];
+        assert_eq!(replies(&mut dbg, &packets), ["E01"; 4]);
+        assert_eq!(dbg.breakpoints().count(), 0);
+        assert!(dbg.watchpoints().is_empty());
+
+        assert_eq!(replies(&mut dbg, &["Z0,202,2", "Z2,300,4"]), ["OK", "OK"]);
+        assert_eq!(dbg.watchpoints()[0], Watchpoint { start: 0x300, end: 0x303, read: false, write: true });
+    }
+
+    #[test]
+    fn serves_target_xml_in_chunks() {
+        let mut dbg = debugger();
+        let xml = target_xml();
+        let reply = replies(&mut dbg, &["qXfer:features:read:target.xml:0,10", "qXfer:features:read:target.xml:ffffffffffffffff,ffffffffffffffff"]);
+
+        assert_eq!(reply[0], format!("m{}", &xml[..0x10]));
+        assert_eq!(reply[1], "l");
+    }
+}
@@ -0,0 +1,158 @@
+#[cfg(feature = "audio")]
+use crate::sound;
+use chip8_rs::display::Display;
+use chip8_rs::frontend::{Frontend, Input};
+use glutin_window::GlutinWindow;
+use opengl_graphics::{GlGraphics, OpenGL};
+use piston::{PressEvent, ReleaseEvent};
+use piston::window::{Window, WindowSettings};
+
+pub const CYCLES_PER_FRAME: usize = 10;
+
+// window size in pixels per CHIP-8 pixel, the screen is scaled to fit on resize
+const SCALE: u32 = 20;
+// space left between pixels, for the grid look
+const GAP: f64 = 2.0;
+
+const GREEN: [f32; 4] = [0.0, 1.0, 0.0, 1.0];
+const RED: [f32; 4] = [1.0, 0.0, 0.0, 1.0];
+const BLUE: [f32; 4] = [0.0, 0.0, 1.0, 1.0];
+const BLACK: [f32; 4] = [0.0, 0.0, 0.0, 1.0];
+// colour per XO-CHIP plane combination, index 0 is the background
+const PALETTE: [[f32; 4]; 4] = [GREEN, RED, BLUE, BLACK];
+
+// a piston window, plus the default audio device with the audio feature
+pub struct Gui {
+    window: GlutinWindow,
+    gl: GlGraphics, // OpenGL drawing backend.
+    #[cfg(feature = "audio")]
+    speaker: Option<sound::Speaker>,
+}
+
+impl Gui {
+    pub fn open() -> Self {
+        // Change this to OpenGL::V2_1 if not working.
+        let opengl = OpenGL::V3_2;
+
+        let window = WindowSettings::new("chip8-rs", [64 * SCALE, 32 * SCALE])
+            .graphics_api(opengl)
+            .exit_on_esc(true)
+            .build()
+            .unwrap();
+
+        Self {
+            window,
+            gl: GlGraphics::new(opengl),
+            #[cfg(feature = "audio")]
+            speaker: sound::Speaker::open(),
+        }
+    }
+}
+
+impl Frontend for Gui {
+    fn present(&mut self, dsp: &Display) {
+        use graphics::*;
+
+        let size = self.window.size();
+        let draw_size = self.window.draw_size();
+        let viewport = Viewport {
+            rect: [0, 0, draw_size.width as i32, draw_size.height as i32],
+            draw_size: [draw_size.width as u32, draw_size.height as u32],
+            window_size: [size.width, size.height],
+        };
+
+        // same footprint in both resolutions, centred in the window
+        let cell = (size.width / dsp.width() as f64).min(size.height / dsp.height() as f64);
+        let left = (size.width - cell * dsp.width() as f64) / 2.0;
+        let top = (size.height - cell * dsp.height() as f64) / 2.0;
+        let square = rectangle::square(0.0, 0.0, (cell - GAP).max(1.0));
+
+        self.gl.draw(viewport, |c, gl| {
+            clear(PALETTE[0], gl);
+
+            for j in 0..dsp.height() {
+                for i in 0..dsp.width() {
+                    let px = dsp.pixel(i, j);
+                    if px != 0 {
+                        let transform = c.transform.trans(left + i as f64 * cell, top + j as f64 * cell);
+                        rectangle(PALETTE[px as usize], square, transform, gl);
+                    }
+                }
+            }
+        });
+        self.window.swap_buffers();
+    }
+
+    fn poll_input(&mut self) -> Vec<Input> {
+        let mut inputs = Vec::new();
+
+        while let Some(e) = self.window.poll_event() {
+            if let Some(piston::Button::Keyboard(k)) = e.press_args() {
+                let input = match k {
+                    piston::Key::F1 => Some(Input::Slot(0)),
+                    piston::Key::F2 => Some(Input::Slot(1)),
+                    piston::Key::F3 => Some(Input::Slot(2)),
+                    piston::Key::F4 => Some(Input::Slot(3)),
+                    piston::Key::F5 => Some(Input::Save),
+                    piston::Key::F9 => Some(Input::Load),
+                    piston::Key::Backspace => Some(Input::Rewind(true)),
+                    _ => keymap(k).map(Input::Press),
+                };
+                inputs.extend(input);
+            }
+
+            if let Some(piston::Button::Keyboard(k)) = e.release_args() {
+                let input = match k {
+                    piston::Key::Backspace => Some(Input::Rewind(false)),
+                    _ => keymap(k).map(Input::Release),
+                };
+                inputs.extend(input);
+            }
+        }
+
+        if self.window.should_close() {
+            inputs.push(Input::Quit);
+        }
+        inputs
+    }
+
+    #[cfg(feature = "audio")]
+    fn sample_rate(&self) -> Option<u32> {
+        self.speaker.as_ref().map(|s| s.sample_rate())
+    }
+
+    #[cfg(feature = "audio")]
+    fn push_audio(&mut self, samples: &[f32]) {
+        if let Some(speaker) = &self.speaker {
+            speaker.push(samples);
+        }
+    }
+
+    fn message(&mut self, text: &str) {
+        println!("{}", text);
+    }
+}
+
+// maps the left side of a QWERTY keyboard onto the hex keypad:
+// 1 2 3 4 / Q W E R / A S D F / Z X C V
+fn keymap(k: piston::Key) -> Option<u8> {
+    match k {
+        piston::Key::D1 => Some(0x00),
+        piston::Key::D2 => Some(0x01),
+        piston::Key::D3 => Some(0x02),
+        piston::Key::D4 => Some(0x03),
+        piston::Key::Q => Some(0x04),
+        piston::Key::W => Some(0x05),
+        piston::Key::E => Some(0x06),
+        piston::Key::R => Some(0x07),
+        piston::Key::A => Some(0x08),
+        piston::Key::S => Some(0x09),
+        piston::Key::D => Some(0x0A),
+        piston::Key::F => Some(0x0B),
+        piston::Key::Z => Some(0x0C),
+        piston::Key::X => Some(0x0D),
+        piston::Key::C => Some(0x0E),
+        piston::Key::V => Some(0x0F),
+        _ => None
+    }
+}
@@ -0,0 +1,275 @@
+use std::error::Error;
+use std::fmt;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Instruction {
+    Scd(u8),                        // 00Cn (SUPER-CHIP)
+    Scu(u8),                        // 00Dn (XO-CHIP)
+    Cls,                            // 00E0
+    Ret,                            // 00EE
+    Scr,                            // 00FB (SUPER-CHIP)
+    Scl,                            // 00FC (SUPER-CHIP)
+    Exit,                           // 00FD (SUPER-CHIP)
+    Low,                            // 00FE (SUPER-CHIP)
+    High,                           // 00FF (SUPER-CHIP)
+    Jp(u16),                        // 1nnn
+    Call(u16),                      // 2nnn
+    SeVxByte { x: u8, kk: u8 },     // 3xkk
+    SneVxByte { x: u8, kk: u8 },    // 4xkk
+    SeVxVy { x: u8, y: u8 },        // 5xy0
+    SaveVxVy { x: u8, y: u8 },      // 5xy2 (XO-CHIP)
+    LoadVxVy { x: u8, y: u8 },      // 5xy3 (XO-CHIP)
+    LdVxByte { x: u8, kk: u8 },     // 6xkk
+    AddVxByte { x: u8, kk: u8 },    // 7xkk
+    LdVxVy { x: u8, y: u8 },        // 8xy0
+    OrVxVy { x: u8, y: u8 },        // 8xy1
+    AndVxVy { x: u8, y: u8 },       // 8xy2
+    XorVxVy { x: u8, y: u8 },       // 8xy3
+    AddVxVy { x: u8, y: u8 },       // 8xy4
+    SubVxVy { x: u8, y: u8 },       // 8xy5
+    ShrVxVy { x: u8, y: u8 },       // 8xy6
+    SubnVxVy { x: u8, y: u8 },      // 8xy7
+    ShlVxVy { x: u8, y: u8 },       // 8xyE
+    SneVxVy { x: u8, y: u8 },       // 9xy0
+    LdI(u16),                       // Annn
+    JpV0(u16),                      // Bnnn
+    RndVxByte { x: u8, kk: u8 },    // Cxkk
+    Drw { x: u8, y: u8, n: u8 },    // Dxyn, Dxy0 draws 16x16 (SUPER-CHIP)
+    SkpVx { x: u8 },                // Ex9E
+    SknpVx { x: u8 },               // ExA1
+    LdILong,                        // F000 nnnn (XO-CHIP), the address is the following word
+    Plane(u8),                      // Fn01 (XO-CHIP)
+    Audio,                          // F002 (XO-CHIP)
+    LdVxDt { x: u8 },               // Fx07
+    LdVxK { x: u8 },                // Fx0A
+    LdDtVx { x: u8 },               // Fx15
+    LdStVx { x: u8 },               // Fx18
+    AddIVx { x: u8 },               // Fx1E
+    LdFVx { x: u8 },                // Fx29
+    LdHfVx { x: u8 },               // Fx30 (SUPER-CHIP)
+    LdBVx { x: u8 },                // Fx33
+    PitchVx { x: u8 },              // Fx3A (XO-CHIP)
+    LdIVx { x: u8 },                // Fx55
+    LdVxI { x: u8 },                // Fx65
+    LdRVx { x: u8 },                // Fx75 (SUPER-CHIP)
+    LdVxR { x: u8 },                // Fx85 (SUPER-CHIP)
+}
+
+impl Instruction {
+    // inverse of decode(); for LdILong this is just the F000 prefix
+    pub fn encode(&self) -> u16 {
+        let xy = |op: u16, x: u8, y: u8, n: u16| op | (x as u16) << 8 | (y as u16) << 4 | n;
+        let xkk = |op: u16, x: u8, kk: u8| op | (x as u16) << 8 | kk as u16;
+
+        match *self {
+            Instruction::Scd(n) => 0x00C0 | n as u16,
+            Instruction::Scu(n) => 0x00D0 | n as u16,
+            Instruction::Cls => 0x00E0,
+            Instruction::Ret => 0x00EE,
+            Instruction::Scr => 0x00FB,
+            Instruction::Scl => 0x00FC,
+            Instruction::Exit => 0x00FD,
+            Instruction::Low => 0x00FE,
+            Instruction::High => 0x00FF,
+            Instruction::Jp(addr) => 0x1000 | addr,
+            Instruction::Call(addr) => 0x2000 | addr,
+            Instruction::SeVxByte { x, kk } => xkk(0x3000, x, kk),
+            Instruction::SneVxByte { x, kk } => xkk(0x4000, x, kk),
+            Instruction::SeVxVy { x, y } => xy(0x5000, x, y, 0x0),
+            Instruction::SaveVxVy { x, y } => xy(0x5000, x, y, 0x2),
+            Instruction::LoadVxVy { x, y } => xy(0x5000, x, y, 0x3),
+            Instruction::LdVxByte { x, kk } => xkk(0x6000, x, kk),
+            Instruction::AddVxByte { x, kk } => xkk(0x7000, x, kk),
+            Instruction::LdVxVy { x, y } => xy(0x8000, x, y, 0x0),
+            Instruction::OrVxVy { x, y } => xy(0x8000, x, y, 0x1),
+            Instruction::AndVxVy { x, y } => xy(0x8000, x, y, 0x2),
+            Instruction::XorVxVy { x, y } => xy(0x8000, x, y, 0x3),
+            Instruction::AddVxVy { x, y } => xy(0x8000, x, y, 0x4),
+            Instruction::SubVxVy { x, y } => xy(0x8000, x, y, 0x5),
+            Instruction::ShrVxVy { x, y } => xy(0x8000, x, y, 0x6),
+            Instruction::SubnVxVy { x, y } => xy(0x8000, x, y, 0x7),
+            Instruction::ShlVxVy { x, y } => xy(0x8000, x, y, 0xE),
+            Instruction::SneVxVy { x, y } => xy(0x9000, x, y, 0x0),
+            Instruction::LdI(addr) => 0xA000 | addr,
+            Instruction::JpV0(addr) => 0xB000 | addr,
+            Instruction::RndVxByte { x, kk } => xkk(0xC000, x, kk),
+            Instruction::Drw { x, y, n } => xy(0xD000, x, y, n as u16),
+            Instruction::SkpVx { x } => xkk(0xE000, x, 0x9E),
+            Instruction::SknpVx { x } => xkk(0xE000, x, 0xA1),
+            Instruction::LdILong => 0xF000,
+            Instruction::Plane(n) => xkk(0xF000, n, 0x01),
+            Instruction::Audio => 0xF002,
+            Instruction::LdVxDt { x } => xkk(0xF000, x, 0x07),
+            Instruction::LdVxK { x } => xkk(0xF000, x, 0x0A),
+            Instruction::LdDtVx { x } => xkk(0xF000, x, 0x15),
+            Instruction::LdStVx { x } => xkk(0xF000, x, 0x18),
+            Instruction::AddIVx { x } => xkk(0xF000, x, 0x1E),
+            Instruction::LdFVx { x } => xkk(0xF000, x, 0x29),
+            Instruction::LdHfVx { x } => xkk(0xF000, x, 0x30),
+            Instruction::LdBVx { x } => xkk(0xF000, x, 0x33),
+            Instruction::PitchVx { x } => xkk(0xF000, x, 0x3A),
+            Instruction::LdIVx { x } => xkk(0xF000, x, 0x55),
+            Instruction::LdVxI { x } => xkk(0xF000, x, 0x65),
+            Instruction::LdRVx { x } => xkk(0xF000, x, 0x75),
+            Instruction::LdVxR { x } => xkk(0xF000, x, 0x85),
+        }
+    }
+
+    // size in bytes, F000 nnnn is the only four byte instruction
+    pub fn size(&self) -> u16 {
+        match self {
+            Instruction::LdILong => 4,
+            _ => 2,
+        }
+    }
+}
+
+// Cowgod style mnemonics, e.g. "LD V3, 0x1F" or "DRW V0, V1, 5"
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Instruction::Scd(n) => write!(f, "SCD {}", n),
+            Instruction::Scu(n) => write!(f, "SCU {}", n),
+            Instruction::Cls => write!(f, "CLS"),
+            Instruction::Ret => write!(f, "RET"),
+            Instruction::Scr => write!(f, "SCR"),
+            Instruction::Scl => write!(f, "SCL"),
+            Instruction::Exit => write!(f, "EXIT"),
+            Instruction::Low => write!(f, "LOW"),
+            Instruction::High => write!(f, "HIGH"),
+            Instruction::Jp(addr) => write!(f, "JP 0x{:03X}", addr),
+            Instruction::Call(addr) => write!(f, "CALL 0x{:03X}", addr),
+            Instruction::SeVxByte { x, kk } => write!(f, "SE V{:X}, 0x{:02X}", x, kk),
+            Instruction::SneVxByte { x, kk } => write!(f, "SNE V{:X}, 0x{:02X}", x, kk),
+            Instruction::SeVxVy { x, y } => write!(f, "SE V{:X}, V{:X}", x, y),
+            Instruction::SaveVxVy { x, y } => write!(f, "SAVE V{:X}, V{:X}", x, y),
+            Instruction::LoadVxVy { x, y } => write!(f, "LOAD V{:X}, V{:X}", x, y),
+            Instruction::LdVxByte { x, kk } => write!(f, "LD V{:X}, 0x{:02X}", x, kk),
+            Instruction::AddVxByte { x, kk } => write!(f, "ADD V{:X}, 0x{:02X}", x, kk),
+            Instruction::LdVxVy { x, y } => write!(f, "LD V{:X}, V{:X}", x, y),
+            Instruction::OrVxVy { x, y } => write!(f, "OR V{:X}, V{:X}", x, y),
+            Instruction::AndVxVy { x, y } => write!(f, "AND V{:X}, V{:X}", x, y),
+            Instruction::XorVxVy { x, y } => write!(f, "XOR V{:X}, V{:X}", x, y),
+            Instruction::AddVxVy { x, y } => write!(f, "ADD V{:X}, V{:X}", x, y),
+            Instruction::SubVxVy { x, y } => write!(f, "SUB V{:X}, V{:X}", x, y),
+            Instruction::ShrVxVy { x, y } => write!(f, "SHR V{:X}, V{:X}", x, y),
+            Instruction::SubnVxVy { x, y } => write!(f, "SUBN V{:X}, V{:X}", x, y),
+            Instruction::ShlVxVy { x, y } => write!(f, "SHL V{:X}, V{:X}", x, y),
+            Instruction::SneVxVy { x, y } => write!(f, "SNE V{:X}, V{:X}", x, y),
+            Instruction::LdI(addr) => write!(f, "LD I, 0x{:03X}", addr),
+            Instruction::JpV0(addr) => write!(f, "JP V0, 0x{:03X}", addr),
+            Instruction::RndVxByte { x, kk } => write!(f, "RND V{:X}, 0x{:02X}", x, kk),
+            Instruction::Drw { x, y, n } => write!(f, "DRW V{:X}, V{:X}, {}", x, y, n),
+            Instruction::SkpVx { x } => write!(f, "SKP V{:X}", x),
+            Instruction::SknpVx { x } => write!(f, "SKNP V{:X}", x),
+            Instruction::LdILong => write!(f, "LD I, LONG"),
+            Instruction::Plane(n) => write!(f, "PLANE {}", n),
+            Instruction::Audio => write!(f, "AUDIO"),
+            Instruction::LdVxDt { x } => write!(f, "LD V{:X}, DT", x),
+            Instruction::LdVxK { x } => write!(f, "LD V{:X}, K", x),
+            Instruction::LdDtVx { x } => write!(f, "LD DT, V{:X}", x),
+            Instruction::LdStVx { x } => write!(f, "LD ST, V{:X}", x),
+            Instruction::AddIVx { x } => write!(f, "ADD I, V{:X}", x),
+            Instruction::LdFVx { x } => write!(f, "LD F, V{:X}", x),
+            Instruction::LdHfVx { x } => write!(f, "LD HF, V{:X}", x),
+            Instruction::LdBVx { x } => write!(f, "LD B, V{:X}", x),
+            Instruction::PitchVx { x } => write!(f, "PITCH V{:X}", x),
+            Instruction::LdIVx { x } => write!(f, "LD [I], V{:X}", x),
+            Instruction::LdVxI { x } => write!(f, "LD V{:X}, [I]", x),
+            Instruction::LdRVx { x } => write!(f, "LD R, V{:X}", x),
+            Instruction::LdVxR { x } => write!(f, "LD V{:X}, R", x),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DecodeError {
+    pub opcode: u16,
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "unknown opcode {:#06x}", self.opcode)
+    }
+}
+
+impl Error for DecodeError {}
+
+pub fn decode(opcode: u16) -> Result<Instruction, DecodeError> {
+    let x = ((opcode & 0x0F00) >> 8) as u8;
+    let y = ((opcode & 0x00F0) >> 4) as u8;
+    let n = (opcode & 0x000F) as u8;
+    let kk = (opcode & 0x00FF) as u8;
+    let nnn = opcode & 0x0FFF;
+
+    let instruction = match opcode >> 12 {
+        0x0 => match opcode {
+            0x00C0..=0x00CF => Instruction::Scd(n),
+            0x00D0..=0x00DF => Instruction::Scu(n),
+            0x00E0 => Instruction::Cls,
+            0x00EE => Instruction::Ret,
+            0x00FB => Instruction::Scr,
+            0x00FC => Instruction::Scl,
+            0x00FD => Instruction::Exit,
+            0x00FE => Instruction::Low,
+            0x00FF => Instruction::High,
+            _ => return Err(DecodeError { opcode }),
+        },
+        0x1 => Instruction::Jp(nnn),
+        0x2 => Instruction::Call(nnn),
+        0x3 => Instruction::SeVxByte { x, kk },
+        0x4 => Instruction::SneVxByte { x, kk },
+        0x5 => match n {
+            0x0 => Instruction::SeVxVy { x, y },
+            0x2 => Instruction::SaveVxVy { x, y },
+            0x3 => Instruction::LoadVxVy { x, y },
+            _ => return Err(DecodeError { opcode }),
+        },
+        0x6 => Instruction::LdVxByte { x, kk },
+        0x7 => Instruction::AddVxByte { x, kk },
+        0x8 => match n {
+            0x0 => Instruction::LdVxVy { x, y },
+            0x1 => Instruction::OrVxVy { x, y },
+            0x2 => Instruction::AndVxVy { x, y },
+            0x3 => Instruction::XorVxVy { x, y },
+            0x4 => Instruction::AddVxVy { x, y },
+            0x5 => Instruction::SubVxVy { x, y },
+            0x6 => Instruction::ShrVxVy { x, y },
+            0x7 => Instruction::SubnVxVy { x, y },
+            0xE => Instruction::ShlVxVy { x, y },
+            _ => return Err(DecodeError { opcode }),
+        },
+        0x9 if n == 0x0 => Instruction::SneVxVy { x, y },
+        0xA => Instruction::LdI(nnn),
+        0xB => Instruction::JpV0(nnn),
+        0xC => Instruction::RndVxByte { x, kk },
+        0xD => Instruction::Drw { x, y, n },
+        0xE => match kk {
+            0x9E => Instruction::SkpVx { x },
+            0xA1 => Instruction::SknpVx { x },
+            _ => return Err(DecodeError { opcode }),
+        },
+        0xF => match kk {
+            0x00 if x == 0x0 => Instruction::LdILong,
+            0x01 => Instruction::Plane(x),
+            0x02 if x == 0x0 => Instruction::Audio,
+            0x07 => Instruction::LdVxDt { x },
+            0x0A => Instruction::LdVxK { x },
+            0x15 => Instruction::LdDtVx { x },
+            0x18 => Instruction::LdStVx { x },
+            0x1E => Instruction::AddIVx { x },
+            0x29 => Instruction::LdFVx { x },
+            0x30 => Instruction::LdHfVx { x },
+            0x33 => Instruction::LdBVx { x },
+            0x3A => Instruction::PitchVx { x },
+            0x55 => Instruction::LdIVx { x },
+            0x65 => Instruction::LdVxI { x },
+            0x75 => Instruction::LdRVx { x },
+            0x85 => Instruction::LdVxR { x },
+            _ => return Err(DecodeError { opcode }),
+        },
+        _ => return Err(DecodeError { opcode }),
+    };
+
+    Ok(instruction)
+}
@@ -1,3 +1,19 @@
+pub mod asm;
+pub mod audio;
 pub mod computer;
 pub mod cpu;
-pub mod display;
\ No newline at end of file
+pub mod debugger;
+pub mod disasm;
+pub mod display;
+pub mod error;
+pub mod frontend;
+pub mod gdb;
+pub mod instruction;
+pub mod memory;
+pub mod movie;
+pub mod octo;
+pub mod quirks;
+pub mod rewind;
+pub mod rng;
+pub mod state;
+pub mod trace;
@@ -1,120 +1,193 @@
-use std::{env, fs};
+#[cfg(feature = "gui")]
+mod gui;
+mod repl;
+#[cfg(feature = "audio")]
+mod sound;
+
+use std::path::Path;
+use std::{env, fs, process};
+use chip8_rs::asm;
 use chip8_rs::computer::Computer;
-use chip8_rs::display::Display;
-use glutin_window::GlutinWindow as Window;
-use opengl_graphics::{GlGraphics, OpenGL};
-use piston::{PressEvent, ReleaseEvent};
-use piston::event_loop::{EventSettings, Events};
-use piston::input::{RenderArgs, RenderEvent, UpdateArgs, UpdateEvent};
-use piston::window::WindowSettings;
-
-pub struct App {
-    gl: GlGraphics, // OpenGL drawing backend.
-    
+use chip8_rs::debugger::Debugger;
+use chip8_rs::disasm;
+#[cfg(feature = "gui")]
+use chip8_rs::frontend::Runner;
+use chip8_rs::gdb;
+use chip8_rs::movie::{Movie, Replay};
+use chip8_rs::octo;
+use chip8_rs::quirks::Quirks;
+
+fn main() {
+
+    let args: Vec<String> = env::args().collect();
+
+    match args.get(1).map(|s| s.as_str()) {
+        Some("asm") => assemble(&args[2..]),
+        Some("debug") => repl::run(load(&args[2..], "usage: chip8-rs debug <rom> [quirks]")),
+        Some("disasm") => disasm(&args[2..]),
+        Some("gdb") => gdb_server(&args[2..]),
+        _ => run(&args[1..]),
+    }
 }
 
-impl App {
-    fn render(&mut self, args: &RenderArgs, dsp: Display) {
-        use graphics::*;
-
-        const GREEN: [f32; 4] = [0.0, 1.0, 0.0, 1.0];
-        const RED: [f32; 4] = [1.0, 0.0, 0.0, 1.0];
-
-        let square = rectangle::square(0.0, 0.0, 18.0);
-        self.gl.draw(args.viewport(), |c, gl| {
-        
-            // Clear the screen.
-            clear(GREEN, gl);
-
-            let ld = dsp.clone();
-            for j in 0..32 { //0..32 
-                for i in 0..64 { //0..64 
-                    
-                    if ld.get(i, j) {
-                        // let (x, y) = (args.window_size[0] / 2.0, args.window_size[1] / 2.0);
-                        let (x, y) = ((i * 20 + 60) as f64, (j * 20 + 60) as f64);
-
-                        //println!("{} - {}", x, y);
-
-                        let transform = c
-                            .transform
-                            .trans(x, y)
-                            .trans(-25.0, -25.0);
-            
-                        // Draw a box rotating around the middle of the screen.
-                        rectangle(RED, square, transform, gl);
-                    }
-                }
-            }
-        });
+// chip8-rs asm <source> [-o rom], .8o files are compiled as Octo
+fn assemble(args: &[String]) {
+    let source = args.first().expect("usage: chip8-rs asm <source> [-o rom]");
+    let output = match args.get(1).map(|s| s.as_str()) {
+        Some("-o") => args.get(2).expect("usage: chip8-rs asm <source> [-o rom]").clone(),
+        _ => Path::new(source).with_extension("ch8").display().to_string(),
+    };
+
+    let path = Path::new(source);
+    let result = match path.extension().and_then(|e| e.to_str()) {
+        Some("8o") => octo::compile_file(path),
+        _ => asm::assemble_file(path),
+    };
+
+    match result {
+        Ok(rom) => fs::write(&output, rom).expect("could not write rom!"),
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
     }
+}
 
-    fn update(&mut self, _args: &UpdateArgs) {
-        // Rotate 2 radians per second.
+// chip8-rs disasm <rom>
+fn disasm(args: &[String]) {
+    let file = fs::read(args.first().expect("usage: chip8-rs disasm <rom>")).expect("file not found!");
+    if disasm::addressable(&file, 0x200).len() < file.len() {
+        eprintln!("warning: only the first {} bytes fit in memory", 0x10000 - 0x200);
     }
+
+    print!("{}", disasm::disassemble(&file, 0x200));
 }
 
-fn main() {
+// chip8-rs gdb <rom> [quirks] [--port N]
+fn gdb_server(args: &[String]) {
+    let usage = "usage: chip8-rs gdb <rom> [quirks] [--port N]";
+    let mut args = args.to_vec();
+    let mut port = 9000;
 
-    let args: Vec<String> = env::args().collect();
+    if let Some(pos) = args.iter().position(|a| a == "--port") {
+        port = args.get(pos + 1).and_then(|p| p.parse().ok()).expect(usage);
+        args.drain(pos..pos + 2);
+    }
 
-    let file = fs::read(args.get(1).unwrap()).expect("file not found!");
-    println!("{:?}", args);
+    let mut dbg = Debugger::new(load(&args, usage), 10);
+    println!("waiting for gdb on 127.0.0.1:{}", port);
 
-    let mut comp = Computer::new(file);
-   
+    if let Err(e) = gdb::serve(&mut dbg, ("127.0.0.1", port)) {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+}
 
-    // fs::write(args.get(2).unwrap(), c.dump()).expect("could not written!");
+// <rom> [quirks]
+fn read_rom(args: &[String], usage: &str) -> (Vec<u8>, Option<Quirks>) {
+    let file = fs::read(args.first().expect(usage)).expect("file not found!");
+    let quirks = args.get(1).map(|name| name.parse().expect("unknown quirks profile!"));
+
+    (file, quirks)
+}
 
-    // Change this to OpenGL::V2_1 if not working.
-    let opengl = OpenGL::V3_2;
+fn load(args: &[String], usage: &str) -> Computer {
+    let (file, quirks) = read_rom(args, usage);
 
-    // Create a Glutin window.
-    let mut window: Window = WindowSettings::new("spinning-square", [200, 200])
-        .graphics_api(opengl)
-        .exit_on_esc(true)
-        .build()
-        .unwrap();
+    Computer::new(file, quirks.unwrap_or_default())
+}
 
-    // Create a new game and run it.
-    let mut app = App {
-        gl: GlGraphics::new(opengl),
-    };
+// removes `--name VALUE` from args and returns VALUE
+fn take_option(args: &mut Vec<String>, name: &str, usage: &str) -> Option<String> {
+    let pos = args.iter().position(|a| a == name)?;
+    let value = args.get(pos + 1).expect(usage).clone();
+    args.drain(pos..pos + 2);
 
-    let mut events = Events::new(EventSettings::new());
-    while let Some(e) = events.next(&mut window) {
-        if let Some(args) = e.render_args() {
+    Some(value)
+}
 
-            let disp = comp.display();
-            app.render(&args, disp);
-        }
+// removes `--name` from args and returns whether it was there
+fn take_flag(args: &mut Vec<String>, name: &str) -> bool {
+    match args.iter().position(|a| a == name) {
+        Some(pos) => {
+            args.remove(pos);
+            true
+        },
+        None => false,
+    }
+}
 
-        if let Some(args) = e.update_args() {
-            app.update(&args);
+// chip8-rs <rom> [quirks] [--record FILE] [--replay FILE [--override-quirks]]
+fn run(args: &[String]) {
+    println!("{:?}", args);
+    let usage = "usage: chip8-rs <rom> [quirks] [--record FILE] [--replay FILE [--override-quirks]]";
+    let mut args = args.to_vec();
+    let record = take_option(&mut args, "--record", usage);
+    let replay = take_option(&mut args, "--replay", usage);
+    let override_quirks = take_flag(&mut args, "--override-quirks");
+
+    let mut movie = match &replay {
+        Some(path) => match Movie::parse(&fs::read_to_string(path).expect("file not found!")) {
+            Ok(movie) => movie,
+            Err(e) => {
+                eprintln!("{}", e);
+                process::exit(1);
+            }
+        },
+        None => Movie::default(),
+    };
 
-            comp.tick();
+    let (file, quirks) = read_rom(&args, usage);
+    // the movie's input only makes sense on the machine it was recorded on
+    if let (Some(given), Some(recorded)) = (quirks, movie.quirks) {
+        if given != recorded && !override_quirks {
+            let name = recorded.name().unwrap_or("custom");
+            eprintln!("the movie was recorded with the {} quirks; pass --override-quirks to replay it with others", name);
+            process::exit(1);
         }
+    }
+    movie.quirks = quirks.or(movie.quirks);
+    // a movie is only reproducible with a known seed
+    if record.is_some() && movie.seed.is_none() {
+        movie.seed = Some(rand::random());
+    }
 
-        if let Some(args) = e.press_args() {
+    let mut comp = movie.computer(file, Quirks::default());
+    if record.is_some() {
+        comp.start_recording();
+    }
+    let replay = replay.map(|_| Replay::new(&movie));
 
-            match args {
-                piston::Button::Keyboard(k) => {
-                    //println!("{:?}", k);
-                    comp.set_key(k);
-                },
-                _ => {}
-            }
+    // fs::write(args.get(2).unwrap(), c.dump()).expect("could not written!");
+
+    #[cfg(feature = "gui")]
+    {
+        if movie.cycles_per_frame.is_some_and(|cpf| cpf != gui::CYCLES_PER_FRAME) {
+            eprintln!("warning: the movie was recorded at a different speed and may not replay correctly");
         }
-        
-        if let Some(args) = e.release_args() {
-            match args {
-                piston::Button::Keyboard(k) => {
-                    //println!("{:?}", k);
-                    
-                    comp.reset_key(k);
-                },
-                _ => {}
-            }
+
+        let mut runner = Runner::new(comp, gui::CYCLES_PER_FRAME);
+        if let Some(replay) = replay {
+            runner.set_replay(replay);
+        }
+        runner.run(&mut gui::Gui::open());
+
+        let mut comp = runner.into_computer();
+        if let Some(path) = record {
+            let recorded = Movie {
+                seed: movie.seed,
+                cycles_per_frame: Some(gui::CYCLES_PER_FRAME),
+                quirks: Some(comp.quirks()),
+                events: comp.stop_recording(),
+            };
+            fs::write(path, recorded.to_string()).expect("could not write movie!");
         }
     }
-}
\ No newline at end of file
+
+    #[cfg(not(feature = "gui"))]
+    {
+        let _ = (comp, replay, record);
+        eprintln!("built without the gui feature, use chip8-headless to run roms");
+        process::exit(1);
+    }
+}
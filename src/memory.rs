@@ -0,0 +1,191 @@
+use crate::error::EmulatorError;
+use crate::state::{StateError, StateReader, StateWriter};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum AccessPolicy {
+    #[default]
+    Wrap,   // addresses past the end wrap around to 0
+    Trap,   // out of range accesses fail with MemoryOutOfBounds
+    Clamp,  // out of range accesses hit the last byte
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AccessKind {
+    Read,
+    Write,
+}
+
+// a data access made by an instruction, `addr` is after the policy is applied
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Access {
+    pub addr: usize,
+    pub kind: AccessKind,
+    pub value: u8,
+}
+
+#[derive(Clone)]
+pub struct Memory {
+    data: Vec<u8>,
+    policy: AccessPolicy,
+}
+
+impl Default for Memory {
+    fn default() -> Self {
+        Self::new(4096)
+    }
+}
+
+impl Memory {
+    pub fn new(size: usize) -> Self {
+        // Wrap and Clamp need a byte to land on
+        assert!(size > 0, "memory needs at least one byte");
+        Self {
+            data: vec![0; size],
+            policy: AccessPolicy::default(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    pub fn policy(&self) -> AccessPolicy {
+        self.policy
+    }
+
+    pub fn set_policy(&mut self, policy: AccessPolicy) {
+        self.policy = policy;
+    }
+
+    // the cell an address ends up at under the current policy
+    pub fn resolve(&self, addr: usize) -> Result<usize, EmulatorError> {
+        if addr < self.data.len() {
+            return Ok(addr);
+        }
+
+        match self.policy {
+            AccessPolicy::Wrap => Ok(addr % self.data.len()),
+            AccessPolicy::Trap => Err(EmulatorError::MemoryOutOfBounds { addr }),
+            AccessPolicy::Clamp => Ok(self.data.len() - 1),
+        }
+    }
+
+    pub fn read(&self, addr: usize) -> Result<u8, EmulatorError> {
+        let idx = self.resolve(addr)?;
+        Ok(self.data[idx])
+    }
+
+    pub fn write(&mut self, addr: usize, data: u8) -> Result<(), EmulatorError> {
+        let idx = self.resolve(addr)?;
+        self.data[idx] = data;
+        Ok(())
+    }
+
+    // copies as much of `data` as fits starting at `offset`, ignoring the policy
+    pub fn load(&mut self, offset: usize, data: &[u8]) {
+        for (pos, e) in data.iter().enumerate() {
+            match self.data.get_mut(offset + pos) {
+                Some(cell) => *cell = *e,
+                None => break,
+            }
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.data.fill(0);
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub(crate) fn write_state(&self, out: &mut StateWriter) {
+        out.u8(self.policy as u8);
+        out.u32(self.data.len() as u32);
+        out.bytes(&self.data);
+    }
+
+    pub(crate) fn read_state(r: &mut StateReader) -> Result<Self, StateError> {
+        let policy = match r.u8()? {
+            0 => AccessPolicy::Wrap,
+            1 => AccessPolicy::Trap,
+            2 => AccessPolicy::Clamp,
+            _ => return Err(StateError::Invalid("memory policy")),
+        };
+        let len = r.u32()? as usize;
+        if len == 0 || len > 0x10000 {
+            return Err(StateError::Invalid("memory size"));
+        }
+
+        Ok(Self { data: r.bytes(len)?.to_vec(), policy })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn memory(policy: AccessPolicy) -> Memory {
+        let mut memory = Memory::new(16);
+        memory.load(0, &(0..16).collect::<Vec<u8>>());
+        memory.set_policy(policy);
+        memory
+    }
+
+    #[test]
+    fn in_range_accesses_ignore_the_policy() {
+        for policy in [AccessPolicy::Wrap, AccessPolicy::Trap, AccessPolicy::Clamp] {
+            let mut memory = memory(policy);
+            assert_eq!(memory.read(15), Ok(15));
+            assert_eq!(memory.write(3, 0xAA), Ok(()));
+            assert_eq!(memory.read(3), Ok(0xAA));
+        }
+    }
+
+    #[test]
+    fn wrap_goes_around_to_the_start() {
+        let mut memory = memory(AccessPolicy::Wrap);
+        assert_eq!(memory.resolve(16), Ok(0));
+        assert_eq!(memory.resolve(16 * 5 + 7), Ok(7));
+        assert_eq!(memory.read(18), Ok(2));
+
+        memory.write(33, 0xAA).unwrap();
+        assert_eq!(memory.as_slice()[1], 0xAA);
+    }
+
+    #[test]
+    fn trap_fails_and_leaves_memory_alone() {
+        let mut memory = memory(AccessPolicy::Trap);
+        assert_eq!(memory.read(16), Err(EmulatorError::MemoryOutOfBounds { addr: 16 }));
+        assert_eq!(memory.write(usize::MAX, 0xAA), Err(EmulatorError::MemoryOutOfBounds { addr: usize::MAX }));
+        assert_eq!(memory.as_slice(), &(0..16).collect::<Vec<u8>>()[..]);
+    }
+
+    #[test]
+    fn clamp_hits_the_last_byte() {
+        let mut memory = memory(AccessPolicy::Clamp);
+        assert_eq!(memory.read(16), Ok(15));
+        assert_eq!(memory.read(usize::MAX), Ok(15));
+
+        memory.write(1000, 0xAA).unwrap();
+        assert_eq!(memory.as_slice()[15], 0xAA);
+        assert_eq!(memory.as_slice()[0], 0);
+    }
+
+    #[test]
+    fn load_stops_at_the_end() {
+        let mut memory = Memory::new(4);
+        memory.load(2, &[1, 2, 3]);
+        assert_eq!(memory.as_slice(), &[0, 0, 1, 2]);
+    }
+
+    #[test]
+    #[should_panic(expected = "at least one byte")]
+    fn empty_memory_is_refused() {
+        Memory::new(0);
+    }
+}
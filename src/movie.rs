@@ -0,0 +1,252 @@
+use std::fmt;
+use crate::computer::Computer;
+use crate::quirks::Quirks;
+
+// a key going down or up before frame `frame` runs
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct InputEvent {
+    pub frame: u64,
+    pub key: u8,
+    pub pressed: bool,
+}
+
+// a recorded session: the settings needed to start the machine the same way,
+// then every key change. As text, one setting or event per line:
+//
+//   seed 1234
+//   cpf 10
+//   quirks vip
+//   12 press 5
+//   15 release 5
+//
+// `#` starts a comment, and every header line is optional. Quirks that aren't a
+// profile are written one `quirk <field> <value>` line each, on top of the default
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Movie {
+    pub seed: Option<u64>,
+    pub cycles_per_frame: Option<usize>,
+    pub quirks: Option<Quirks>,
+    pub events: Vec<InputEvent>,
+}
+
+impl Movie {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut movie = Movie::default();
+
+        for (n, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let err = |what: &str| format!("movie line {}: {}", n + 1, what);
+            if fields.len() == 2 {
+                match fields[0] {
+                    "seed" => movie.seed = Some(fields[1].parse().map_err(|_| err("invalid seed"))?),
+                    "cpf" => movie.cycles_per_frame = Some(fields[1].parse().map_err(|_| err("invalid cpf"))?),
+                    "quirks" => movie.quirks = Some(fields[1].parse().map_err(|e: String| err(&e))?),
+                    _ => return Err(err("expected seed, cpf or quirks")),
+                }
+                continue;
+            }
+
+            if fields.len() == 3 && fields[0] == "quirk" {
+                let quirks = movie.quirks.get_or_insert_with(Quirks::default);
+                quirks.set(fields[1], fields[2]).map_err(|e| err(&e))?;
+                continue;
+            }
+
+            let usage = || err("expected \"<frame> press|release <key>\"");
+            if fields.len() != 3 {
+                return Err(usage());
+            }
+
+            let frame = fields[0].parse().map_err(|_| usage())?;
+            let pressed = match fields[1] {
+                "press" => true,
+                "release" => false,
+                _ => return Err(usage()),
+            };
+            let key = u8::from_str_radix(fields[2].trim_start_matches("0x"), 16).map_err(|_| usage())?;
+            if key > 0x0F {
+                return Err(usage());
+            }
+
+            movie.events.push(InputEvent { frame, key, pressed });
+        }
+
+        // stable, so events on the same frame keep their order
+        movie.events.sort_by_key(|e| e.frame);
+        Ok(movie)
+    }
+
+    // a computer set up the way the movie was recorded, with its input still to come
+    pub fn computer(&self, data: Vec<u8>, quirks: Quirks) -> Computer {
+        let quirks = self.quirks.unwrap_or(quirks);
+        match self.seed {
+            Some(seed) => Computer::with_seed(data, quirks, seed),
+            None => Computer::new(data, quirks),
+        }
+    }
+}
+
+impl fmt::Display for Movie {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(seed) = self.seed {
+            writeln!(f, "seed {}", seed)?;
+        }
+        if let Some(cpf) = self.cycles_per_frame {
+            writeln!(f, "cpf {}", cpf)?;
+        }
+        if let Some(quirks) = self.quirks {
+            match quirks.name() {
+                Some(name) => writeln!(f, "quirks {}", name)?,
+                None => {
+                    for (field, value) in quirks.fields() {
+                        writeln!(f, "quirk {} {}", field, value)?;
+                    }
+                },
+            }
+        }
+        for e in &self.events {
+            writeln!(f, "{} {} {:X}", e.frame, if e.pressed { "press" } else { "release" }, e.key)?;
+        }
+
+        Ok(())
+    }
+}
+
+// feeds a movie's input to a computer as its frames come up
+pub struct Replay {
+    events: Vec<InputEvent>,
+    next: usize,
+}
+
+impl Replay {
+    pub fn new(movie: &Movie) -> Self {
+        Self { events: movie.events.clone(), next: 0 }
+    }
+
+    // applies everything due by comp.frames(); call before each run_frame()
+    pub fn apply(&mut self, comp: &mut Computer) {
+        while let Some(e) = self.events.get(self.next).filter(|e| e.frame <= comp.frames()) {
+            if e.pressed {
+                comp.press(e.key);
+            }
+            else {
+                comp.release(e.key);
+            }
+            self.next += 1;
+        }
+    }
+
+    // continues from `frame`, e.g. after a save state moved the computer there
+    pub fn seek(&mut self, frame: u64) {
+        self.next = self.events.partition_point(|e| e.frame < frame);
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.next == self.events.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn press(frame: u64, key: u8) -> InputEvent {
+        InputEvent { frame, key, pressed: true }
+    }
+
+    fn release(frame: u64, key: u8) -> InputEvent {
+        InputEvent { frame, key, pressed: false }
+    }
+
+    #[test]
+    fn parses_what_it_writes() {
+        let movie = Movie {
+            seed: Some(1234),
+            cycles_per_frame: Some(15),
+            quirks: Some(Quirks::cosmac_vip()),
+            events: vec![press(0, 0xA), press(3, 1), release(3, 0xA), release(90, 1)],
+        };
+
+        let text = movie.to_string();
+        assert_eq!(text, "seed 1234\ncpf 15\nquirks vip\n0 press A\n3 press 1\n3 release A\n90 release 1\n");
+        assert_eq!(Movie::parse(&text), Ok(movie));
+        assert_eq!(Movie::parse(""), Ok(Movie::default()));
+    }
+
+    #[test]
+    fn writes_out_quirks_that_are_not_a_profile() {
+        let quirks = Quirks { wrap_sprites: true, ..Quirks::super_chip() };
+        let movie = Movie { quirks: Some(quirks), events: vec![press(1, 2)], ..Default::default() };
+
+        let text = movie.to_string();
+        assert!(text.contains("quirk wrap_sprites true\n") && text.contains("quirk jump_uses_vx true\n"));
+        assert_eq!(Movie::parse(&text), Ok(movie));
+
+        // applied on top of a profile when there is one
+        let movie = Movie::parse("quirks vip\nquirk display_wait false").unwrap();
+        assert_eq!(movie.quirks, Some(Quirks { display_wait: false, ..Quirks::cosmac_vip() }));
+        assert_eq!(Movie::parse("quirk turbo true"), Err("movie line 1: unknown quirk: turbo".to_string()));
+    }
+
+    #[test]
+    fn skips_comments_and_sorts_events_by_frame() {
+        let text = "# attract mode\n\n  7 press 0x5  # coin\n2 press F\n7 release 5\n2 release F\n";
+        let movie = Movie::parse(text).unwrap();
+
+        assert_eq!(movie.events, [press(2, 0xF), release(2, 0xF), press(7, 5), release(7, 5)]);
+        assert_eq!((movie.seed, movie.cycles_per_frame, movie.quirks), (None, None, None));
+    }
+
+    #[test]
+    fn reports_the_bad_line() {
+        let cases = [
+            ("seed x", "movie line 1: invalid seed"),
+            ("\ncpf -1", "movie line 2: invalid cpf"),
+            ("speed 3", "movie line 1: expected seed, cpf or quirks"),
+            ("1 press 10", "movie line 1: expected \"<frame> press|release <key>\""),
+            ("1 hold 1", "movie line 1: expected \"<frame> press|release <key>\""),
+            ("1 press 1 now", "movie line 1: expected \"<frame> press|release <key>\""),
+        ];
+        for (text, error) in cases {
+            assert_eq!(Movie::parse(text), Err(error.to_string()), "{:?}", text);
+        }
+        assert!(Movie::parse("quirks nope").unwrap_err().starts_with("movie line 1: "));
+    }
+
+    #[test]
+    fn computers_use_the_recorded_settings() {
+        let movie = Movie { seed: Some(9), quirks: Some(Quirks::xo_chip()), ..Default::default() };
+        let comp = movie.computer(Vec::new(), Quirks::cosmac_vip());
+        assert_eq!(comp.quirks(), Quirks::xo_chip());
+        assert_eq!(comp.save_state(), Computer::with_seed(Vec::new(), Quirks::xo_chip(), 9).save_state());
+
+        assert_eq!(Movie::default().computer(Vec::new(), Quirks::cosmac_vip()).quirks(), Quirks::cosmac_vip());
+    }
+
+    #[test]
+    fn replays_events_as_their_frames_come_up() {
+        let movie = Movie { events: vec![press(0, 3), press(2, 5), release(2, 3), release(3, 5), press(3, 1)], ..Default::default() };
+        let mut replay = Replay::new(&movie);
+        // 200 LD V0 K, 202 JP 200: V0 is the lowest held key
+        let mut comp = Computer::with_seed(vec![0xF0, 0x0A, 0x12, 0x00], Quirks::default(), 0);
+
+        let mut keys = Vec::new();
+        while !replay.is_finished() {
+            replay.apply(&mut comp);
+            comp.run_frame(10).unwrap();
+            keys.push(comp.cpu().v[0]);
+        }
+        assert_eq!(keys, [3, 3, 5, 1]);
+
+        // going back replays the events from there on
+        replay.seek(2);
+        assert!(!replay.is_finished());
+        replay.seek(4);
+        assert!(replay.is_finished());
+    }
+}
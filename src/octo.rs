@@ -0,0 +1,999 @@
+use std::collections::{HashMap, VecDeque};
+use std::f64::consts;
+use std::fs;
+use std::path::Path;
+use crate::asm::AsmError;
+use crate::instruction::Instruction;
+
+const ORIGIN: u16 = 0x200;
+const MAX_SIZE: usize = 0x10000 - ORIGIN as usize;
+const MAX_EXPANSIONS: usize = 100_000;
+
+const KEYWORDS: &[&str] = &[
+    ":=", "+=", "-=", "=-", "|=", "&=", "^=", ">>=", "<<=", "==", "!=", "<", ">", "<=", ">=",
+    "if", "then", "begin", "else", "end", "loop", "again", "while", "key", "-key",
+    "random", "delay", "buzzer", "pitch", "hex", "bighex", "long", "i", ";",
+    "clear", "return", "bcd", "save", "load", "sprite", "jump", "jump0", "native",
+    "scroll-down", "scroll-up", "scroll-left", "scroll-right", "exit", "lores", "hires",
+    "saveflags", "loadflags", "plane", "audio",
+];
+
+#[derive(Clone, Debug)]
+struct Token {
+    text: String,
+    line: usize,
+}
+
+struct Macro {
+    params: Vec<String>,
+    body: Vec<Token>,
+}
+
+#[derive(Clone, Copy)]
+enum FixupKind {
+    // low 12 bits of an instruction
+    Addr,
+    // a full 16 bit word
+    Long,
+    // the byte operands of the two loads made by :unpack
+    UnpackHigh(Option<u8>),
+    UnpackLow,
+}
+
+struct Fixup {
+    pos: usize,
+    name: String,
+    line: usize,
+    kind: FixupKind,
+}
+
+enum Block {
+    If { jump: usize, line: usize },
+    Else { jump: usize, line: usize },
+    Loop { start: u16, breaks: Vec<usize>, line: usize },
+}
+
+#[derive(Clone, Copy)]
+enum Operand {
+    Reg(u8),
+    Byte(u8),
+}
+
+#[derive(Clone, Copy)]
+enum Cond {
+    Eq(u8, Operand),
+    Ne(u8, Operand),
+    Key(u8),
+    NotKey(u8),
+}
+
+impl Cond {
+    fn negate(self) -> Self {
+        match self {
+            Cond::Eq(x, rhs) => Cond::Ne(x, rhs),
+            Cond::Ne(x, rhs) => Cond::Eq(x, rhs),
+            Cond::Key(x) => Cond::NotKey(x),
+            Cond::NotKey(x) => Cond::Key(x),
+        }
+    }
+
+    // the instruction that skips the next one when the condition holds
+    fn skip(self) -> Instruction {
+        match self {
+            Cond::Eq(x, Operand::Reg(y)) => Instruction::SeVxVy { x, y },
+            Cond::Eq(x, Operand::Byte(kk)) => Instruction::SeVxByte { x, kk },
+            Cond::Ne(x, Operand::Reg(y)) => Instruction::SneVxVy { x, y },
+            Cond::Ne(x, Operand::Byte(kk)) => Instruction::SneVxByte { x, kk },
+            Cond::Key(x) => Instruction::SkpVx { x },
+            Cond::NotKey(x) => Instruction::SknpVx { x },
+        }
+    }
+}
+
+// compiles Octo source into a ROM image that loads at 0x200
+pub fn compile(source: &str) -> Result<Vec<u8>, AsmError> {
+    Compiler::new("<input>", source)?.run()
+}
+
+pub fn compile_file(path: &Path) -> Result<Vec<u8>, AsmError> {
+    let name = path.display().to_string();
+    let source = fs::read_to_string(path)
+        .map_err(|e| AsmError { file: name.clone(), line: 0, message: e.to_string() })?;
+
+    Compiler::new(&name, &source)?.run()
+}
+
+fn tokenize(file: &str, source: &str) -> Result<VecDeque<Token>, AsmError> {
+    let mut tokens = VecDeque::new();
+
+    for (n, text) in source.lines().enumerate() {
+        let mut rest = text.trim_start();
+
+        while !rest.is_empty() && !rest.starts_with('#') {
+            let len = if let Some(quoted) = rest.strip_prefix('"') {
+                match quoted.find('"') {
+                    Some(end) => end + 2,
+                    None => return Err(AsmError { file: file.to_string(), line: n + 1, message: "unterminated string".to_string() }),
+                }
+            }
+            else {
+                rest.find(char::is_whitespace).unwrap_or(rest.len())
+            };
+
+            tokens.push_back(Token { text: rest[..len].to_string(), line: n + 1 });
+            rest = rest[len..].trim_start();
+        }
+    }
+
+    Ok(tokens)
+}
+
+fn parse_number(s: &str) -> Option<f64> {
+    let (negative, digits) = match s.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, s),
+    };
+
+    let value = if let Some(hex) = digits.strip_prefix("0x").or_else(|| digits.strip_prefix("0X")) {
+        i64::from_str_radix(hex, 16).ok()? as f64
+    }
+    else if let Some(bin) = digits.strip_prefix("0b").or_else(|| digits.strip_prefix("0B")) {
+        i64::from_str_radix(bin, 2).ok()? as f64
+    }
+    else if digits.starts_with(|c: char| c.is_ascii_digit()) {
+        digits.parse().ok()?
+    }
+    else {
+        return None;
+    };
+
+    Some(if negative { -value } else { value })
+}
+
+fn register(s: &str) -> Option<u8> {
+    let s = s.strip_prefix('v').or_else(|| s.strip_prefix('V'))?;
+    if s.len() == 1 {
+        u8::from_str_radix(s, 16).ok()
+    }
+    else {
+        None
+    }
+}
+
+fn is_name(s: &str) -> bool {
+    !s.is_empty()
+        && !s.starts_with(|c: char| c.is_ascii_digit() || c == '-' || c == ':' || c == '"')
+        && s.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+        && register(s).is_none()
+        && !KEYWORDS.contains(&s)
+}
+
+struct Compiler {
+    file: String,
+    tokens: VecDeque<Token>,
+    line: usize,
+    rom: Vec<u8>,
+    here: usize,
+    symbols: HashMap<String, f64>,
+    aliases: HashMap<String, u8>,
+    macros: HashMap<String, Macro>,
+    fixups: Vec<Fixup>,
+    blocks: Vec<Block>,
+    expansions: usize,
+}
+
+impl Compiler {
+    fn new(file: &str, source: &str) -> Result<Self, AsmError> {
+        Ok(Self {
+            file: file.to_string(),
+            tokens: tokenize(file, source)?,
+            line: 0,
+            rom: Vec::new(),
+            here: 0,
+            symbols: HashMap::new(),
+            aliases: HashMap::new(),
+            macros: HashMap::new(),
+            fixups: Vec::new(),
+            blocks: Vec::new(),
+            expansions: 0,
+        })
+    }
+
+    fn run(mut self) -> Result<Vec<u8>, AsmError> {
+        // programs start with a jump to main, unless main comes first anyway
+        let main_first = self.tokens.len() >= 2 && self.tokens[0].text == ":" && self.tokens[1].text == "main";
+        if !main_first {
+            self.emit_jump(0x1, "main")?;
+        }
+
+        while let Some(tok) = self.tokens.pop_front() {
+            self.line = tok.line;
+            self.statement(&tok.text)?;
+        }
+
+        if let Some(block) = self.blocks.last() {
+            let (what, line) = match block {
+                Block::If { line, .. } | Block::Else { line, .. } => ("if without end", *line),
+                Block::Loop { line, .. } => ("loop without again", *line),
+            };
+            self.line = line;
+            return Err(self.error(what));
+        }
+
+        for fixup in std::mem::take(&mut self.fixups) {
+            self.line = fixup.line;
+            let value = match self.symbols.get(&fixup.name) {
+                Some(value) => *value as i64,
+                None => return Err(self.error(format!("undefined name: {}", fixup.name))),
+            };
+            self.patch(fixup.pos, fixup.kind, value)?;
+        }
+
+        Ok(self.rom)
+    }
+
+    fn error(&self, message: impl Into<String>) -> AsmError {
+        AsmError { file: self.file.clone(), line: self.line, message: message.into() }
+    }
+
+    fn next(&mut self) -> Result<String, AsmError> {
+        match self.tokens.pop_front() {
+            Some(tok) => {
+                self.line = tok.line;
+                Ok(tok.text)
+            },
+            None => Err(self.error("unexpected end of input")),
+        }
+    }
+
+    fn expect(&mut self, text: &str) -> Result<(), AsmError> {
+        let tok = self.next()?;
+        if tok != text {
+            return Err(self.error(format!("expected {}, found {}", text, tok)));
+        }
+        Ok(())
+    }
+
+    fn addr(&self) -> u16 {
+        ORIGIN + self.here as u16
+    }
+
+    fn emit(&mut self, bytes: &[u8]) -> Result<(), AsmError> {
+        let end = self.here + bytes.len();
+        if end > MAX_SIZE {
+            return Err(self.error("program does not fit in memory"));
+        }
+        if self.rom.len() < end {
+            self.rom.resize(end, 0);
+        }
+        self.rom[self.here..end].copy_from_slice(bytes);
+        self.here = end;
+        Ok(())
+    }
+
+    fn emit_ins(&mut self, ins: Instruction) -> Result<(), AsmError> {
+        self.emit(&ins.encode().to_be_bytes())
+    }
+
+    // an instruction with a 12 bit address operand, which may be a forward reference
+    fn emit_jump(&mut self, op: u8, target: &str) -> Result<(), AsmError> {
+        let pos = self.here;
+        self.emit(&[op << 4, 0x00])?;
+        self.reference(pos, FixupKind::Addr, target)
+    }
+
+    fn reference(&mut self, pos: usize, kind: FixupKind, target: &str) -> Result<(), AsmError> {
+        match self.value(target)? {
+            Some(value) => self.patch(pos, kind, value as i64),
+            None if is_name(target) => {
+                self.fixups.push(Fixup { pos, name: target.to_string(), line: self.line, kind });
+                Ok(())
+            },
+            None => Err(self.error(format!("expected an address, found {}", target))),
+        }
+    }
+
+    fn patch(&mut self, pos: usize, kind: FixupKind, value: i64) -> Result<(), AsmError> {
+        let max = match kind {
+            FixupKind::Long | FixupKind::UnpackHigh(None) | FixupKind::UnpackLow => 0xFFFF,
+            FixupKind::Addr | FixupKind::UnpackHigh(Some(_)) => 0xFFF,
+        };
+        if !(0..=max).contains(&value) {
+            return Err(self.error(format!("address out of range: {:#X}", value)));
+        }
+
+        let value = value as u16;
+        match kind {
+            FixupKind::Addr => {
+                self.rom[pos] = (self.rom[pos] & 0xF0) | (value >> 8) as u8;
+                self.rom[pos + 1] = value as u8;
+            },
+            FixupKind::Long => self.rom[pos..pos + 2].copy_from_slice(&value.to_be_bytes()),
+            FixupKind::UnpackHigh(nibble) => self.rom[pos + 1] = (nibble.unwrap_or(0) << 4) | (value >> 8) as u8,
+            FixupKind::UnpackLow => self.rom[pos + 1] = value as u8,
+        }
+        Ok(())
+    }
+
+    fn define(&mut self, name: &str, value: f64) -> Result<(), AsmError> {
+        if !is_name(name) {
+            return Err(self.error(format!("invalid name: {}", name)));
+        }
+        if self.symbols.contains_key(name) || self.macros.contains_key(name) || self.aliases.contains_key(name) {
+            return Err(self.error(format!("duplicate name: {}", name)));
+        }
+        self.symbols.insert(name.to_string(), value);
+        Ok(())
+    }
+
+    // a number or an already defined name
+    fn value(&self, tok: &str) -> Result<Option<f64>, AsmError> {
+        if let Some(n) = parse_number(tok) {
+            return Ok(Some(n));
+        }
+        if tok.starts_with(|c: char| c.is_ascii_digit()) {
+            return Err(self.error(format!("invalid number: {}", tok)));
+        }
+        Ok(self.symbols.get(tok).copied())
+    }
+
+    fn number(&self, tok: &str, min: i64, max: i64) -> Result<i64, AsmError> {
+        let value = match self.value(tok)? {
+            Some(value) => value as i64,
+            None => return Err(self.error(format!("undefined name: {}", tok))),
+        };
+        if value < min || value > max {
+            return Err(self.error(format!("value out of range: {}", tok)));
+        }
+        Ok(value)
+    }
+
+    fn byte(&self, tok: &str) -> Result<u8, AsmError> {
+        Ok(self.number(tok, -128, 0xFF)? as u8)
+    }
+
+    fn reg(&self, tok: &str) -> Result<u8, AsmError> {
+        self.aliases.get(tok).copied()
+            .or_else(|| register(tok))
+            .ok_or_else(|| self.error(format!("expected a register, found {}", tok)))
+    }
+
+    fn next_reg(&mut self) -> Result<u8, AsmError> {
+        let tok = self.next()?;
+        self.reg(&tok)
+    }
+
+    fn next_number(&mut self, min: i64, max: i64) -> Result<i64, AsmError> {
+        let tok = self.next()?;
+        self.number(&tok, min, max)
+    }
+
+    fn operand(&self, tok: &str) -> Result<Operand, AsmError> {
+        match self.reg(tok) {
+            Ok(y) => Ok(Operand::Reg(y)),
+            Err(_) => Ok(Operand::Byte(self.byte(tok)?)),
+        }
+    }
+
+    fn statement(&mut self, tok: &str) -> Result<(), AsmError> {
+        match tok {
+            ":" => {
+                let name = self.next()?;
+                self.define(&name, self.addr() as f64)
+            },
+            ":const" => {
+                let name = self.next()?;
+                let value = self.next_number(i64::MIN, i64::MAX)?;
+                self.define(&name, value as f64)
+            },
+            ":alias" => {
+                let name = self.next()?;
+                let x = self.next_reg()?;
+                if !is_name(&name) || self.symbols.contains_key(&name) || self.macros.contains_key(&name) {
+                    return Err(self.error(format!("invalid alias name: {}", name)));
+                }
+                self.aliases.insert(name, x);
+                Ok(())
+            },
+            ":unpack" => self.unpack(),
+            ":next" => {
+                let name = self.next()?;
+                self.define(&name, (self.addr() + 1) as f64)
+            },
+            ":org" => {
+                self.here = (self.next_number(ORIGIN as i64, 0xFFFF)? - ORIGIN as i64) as usize;
+                Ok(())
+            },
+            ":byte" => {
+                let value = if self.tokens.front().is_some_and(|t| t.text == "{") {
+                    self.calc()? as i64
+                }
+                else {
+                    self.next_number(-128, 0xFF)?
+                };
+                if !(-128..=0xFF).contains(&value) {
+                    return Err(self.error("byte out of range"));
+                }
+                self.emit(&[value as u8])
+            },
+            ":pointer" => {
+                let target = self.next()?;
+                let pos = self.here;
+                self.emit(&[0, 0])?;
+                self.reference(pos, FixupKind::Long, &target)
+            },
+            ":call" => {
+                let target = self.next()?;
+                self.emit_jump(0x2, &target)
+            },
+            ":calc" => {
+                let name = self.next()?;
+                let value = self.calc()?;
+                self.define(&name, value)
+            },
+            ":macro" => self.define_macro(),
+            ":assert" => {
+                let message = match self.tokens.front() {
+                    Some(t) if t.text.starts_with('"') => self.next()?.trim_matches('"').to_string(),
+                    _ => "assertion failed".to_string(),
+                };
+                if self.calc()? == 0.0 {
+                    return Err(self.error(message));
+                }
+                Ok(())
+            },
+            // debugger annotations, nothing to emit
+            ":breakpoint" => self.next().map(|_| ()),
+            ":monitor" => self.next().and_then(|_| self.next()).map(|_| ()),
+
+            "if" => self.conditional(),
+            "else" => {
+                let (jump, line) = match self.blocks.pop() {
+                    Some(Block::If { jump, line }) => (jump, line),
+                    _ => return Err(self.error("else without if")),
+                };
+                let end = self.here;
+                self.emit_ins(Instruction::Jp(0))?;
+                self.patch(jump, FixupKind::Addr, self.addr() as i64)?;
+                self.blocks.push(Block::Else { jump: end, line });
+                Ok(())
+            },
+            "end" => match self.blocks.pop() {
+                Some(Block::If { jump, .. }) | Some(Block::Else { jump, .. }) => {
+                    self.patch(jump, FixupKind::Addr, self.addr() as i64)
+                },
+                _ => Err(self.error("end without if")),
+            },
+            "loop" => {
+                self.blocks.push(Block::Loop { start: self.addr(), breaks: Vec::new(), line: self.line });
+                Ok(())
+            },
+            "while" => {
+                let cond = self.condition()?;
+                self.emit_ins(cond.skip())?;
+                let jump = self.here;
+                self.emit_ins(Instruction::Jp(0))?;
+
+                let line = self.line;
+                match self.blocks.iter_mut().rev().find_map(|b| match b {
+                    Block::Loop { breaks, .. } => Some(breaks),
+                    _ => None,
+                }) {
+                    Some(breaks) => breaks.push(jump),
+                    None => return Err(AsmError { file: self.file.clone(), line, message: "while outside of a loop".to_string() }),
+                }
+                Ok(())
+            },
+            "again" => match self.blocks.pop() {
+                Some(Block::Loop { start, breaks, .. }) => {
+                    self.emit_ins(Instruction::Jp(0))?;
+                    self.patch(self.here - 2, FixupKind::Addr, start as i64)?;
+                    for jump in breaks {
+                        self.patch(jump, FixupKind::Addr, self.addr() as i64)?;
+                    }
+                    Ok(())
+                },
+                _ => Err(self.error("again without loop")),
+            },
+
+            "clear" => self.emit_ins(Instruction::Cls),
+            "return" | ";" => self.emit_ins(Instruction::Ret),
+            "exit" => self.emit_ins(Instruction::Exit),
+            "lores" => self.emit_ins(Instruction::Low),
+            "hires" => self.emit_ins(Instruction::High),
+            "scroll-left" => self.emit_ins(Instruction::Scl),
+            "scroll-right" => self.emit_ins(Instruction::Scr),
+            "audio" => self.emit_ins(Instruction::Audio),
+            "scroll-down" => {
+                let n = self.next_number(0, 0xF)? as u8;
+                self.emit_ins(Instruction::Scd(n))
+            },
+            "scroll-up" => {
+                let n = self.next_number(0, 0xF)? as u8;
+                self.emit_ins(Instruction::Scu(n))
+            },
+            "plane" => {
+                let n = self.next_number(0, 3)? as u8;
+                self.emit_ins(Instruction::Plane(n))
+            },
+            "bcd" => {
+                let x = self.next_reg()?;
+                self.emit_ins(Instruction::LdBVx { x })
+            },
+            "save" | "load" => {
+                let x = self.next_reg()?;
+                let range = self.tokens.front().is_some_and(|t| t.text == "-");
+                let ins = match (tok, range) {
+                    ("save", false) => Instruction::LdIVx { x },
+                    (_, false) => Instruction::LdVxI { x },
+                    (_, true) => {
+                        self.next()?;
+                        let y = self.next_reg()?;
+                        if tok == "save" { Instruction::SaveVxVy { x, y } } else { Instruction::LoadVxVy { x, y } }
+                    },
+                };
+                self.emit_ins(ins)
+            },
+            "saveflags" => {
+                let x = self.next_reg()?;
+                self.emit_ins(Instruction::LdRVx { x })
+            },
+            "loadflags" => {
+                let x = self.next_reg()?;
+                self.emit_ins(Instruction::LdVxR { x })
+            },
+            "sprite" => {
+                let x = self.next_reg()?;
+                let y = self.next_reg()?;
+                let n = self.next_number(0, 0xF)? as u8;
+                self.emit_ins(Instruction::Drw { x, y, n })
+            },
+            "jump" => {
+                let target = self.next()?;
+                self.emit_jump(0x1, &target)
+            },
+            "jump0" => {
+                let target = self.next()?;
+                self.emit_jump(0xB, &target)
+            },
+            "native" => Err(self.error("native machine code calls are not supported")),
+            "i" => self.index(),
+            "delay" | "buzzer" | "pitch" => {
+                self.expect(":=")?;
+                let x = self.next_reg()?;
+                let ins = match tok {
+                    "delay" => Instruction::LdDtVx { x },
+                    "buzzer" => Instruction::LdStVx { x },
+                    _ => Instruction::PitchVx { x },
+                };
+                self.emit_ins(ins)
+            },
+
+            _ if self.macros.contains_key(tok) => self.expand(tok),
+            _ if self.reg(tok).is_ok() => self.assignment(tok),
+            _ if parse_number(tok).is_some() => {
+                let byte = self.byte(tok)?;
+                self.emit(&[byte])
+            },
+            _ if is_name(tok) => self.emit_jump(0x2, tok),
+            _ => Err(self.error(format!("unexpected {}", tok))),
+        }
+    }
+
+    fn index(&mut self) -> Result<(), AsmError> {
+        let op = self.next()?;
+        if op == "+=" {
+            let x = self.next_reg()?;
+            return self.emit_ins(Instruction::AddIVx { x });
+        }
+        if op != ":=" {
+            return Err(self.error(format!("expected := or +=, found {}", op)));
+        }
+
+        let rhs = self.next()?;
+        match rhs.as_str() {
+            "hex" => {
+                let x = self.next_reg()?;
+                self.emit_ins(Instruction::LdFVx { x })
+            },
+            "bighex" => {
+                let x = self.next_reg()?;
+                self.emit_ins(Instruction::LdHfVx { x })
+            },
+            "long" => {
+                let target = self.next()?;
+                self.emit_ins(Instruction::LdILong)?;
+                let pos = self.here;
+                self.emit(&[0, 0])?;
+                self.reference(pos, FixupKind::Long, &target)
+            },
+            _ => self.emit_jump(0xA, &rhs),
+        }
+    }
+
+    fn assignment(&mut self, dst: &str) -> Result<(), AsmError> {
+        let x = self.reg(dst)?;
+        let op = self.next()?;
+        let rhs = self.next()?;
+
+        let ins = match (op.as_str(), rhs.as_str()) {
+            (":=", "random") => {
+                let kk = self.next_number(0, 0xFF)? as u8;
+                Instruction::RndVxByte { x, kk }
+            },
+            (":=", "key") => Instruction::LdVxK { x },
+            (":=", "delay") => Instruction::LdVxDt { x },
+            (":=", _) => match self.operand(&rhs)? {
+                Operand::Reg(y) => Instruction::LdVxVy { x, y },
+                Operand::Byte(kk) => Instruction::LdVxByte { x, kk },
+            },
+            ("+=", _) => match self.operand(&rhs)? {
+                Operand::Reg(y) => Instruction::AddVxVy { x, y },
+                Operand::Byte(kk) => Instruction::AddVxByte { x, kk },
+            },
+            ("-=", _) => match self.operand(&rhs)? {
+                Operand::Reg(y) => Instruction::SubVxVy { x, y },
+                Operand::Byte(kk) => Instruction::AddVxByte { x, kk: kk.wrapping_neg() },
+            },
+            ("=-", _) => Instruction::SubnVxVy { x, y: self.reg(&rhs)? },
+            ("|=", _) => Instruction::OrVxVy { x, y: self.reg(&rhs)? },
+            ("&=", _) => Instruction::AndVxVy { x, y: self.reg(&rhs)? },
+            ("^=", _) => Instruction::XorVxVy { x, y: self.reg(&rhs)? },
+            (">>=", _) => Instruction::ShrVxVy { x, y: self.reg(&rhs)? },
+            ("<<=", _) => Instruction::ShlVxVy { x, y: self.reg(&rhs)? },
+            _ => return Err(self.error(format!("unknown operator: {}", op))),
+        };
+
+        self.emit_ins(ins)
+    }
+
+    // parses `vx op rhs`; the ordering comparisons compute into vF first
+    fn condition(&mut self) -> Result<Cond, AsmError> {
+        let x = self.next_reg()?;
+        let op = self.next()?;
+
+        match op.as_str() {
+            "key" => return Ok(Cond::Key(x)),
+            "-key" => return Ok(Cond::NotKey(x)),
+            _ => {}
+        }
+
+        let rhs = self.next()?;
+        let rhs = self.operand(&rhs)?;
+        let f = 0xF;
+
+        match op.as_str() {
+            "==" => Ok(Cond::Eq(x, rhs)),
+            "!=" => Ok(Cond::Ne(x, rhs)),
+            "<" | ">=" | ">" | "<=" => {
+                // vF ends up as the no-borrow flag of x - rhs (< and >=) or rhs - x (> and <=)
+                let ins = match (op.as_str(), rhs) {
+                    ("<" | ">=", Operand::Reg(y)) => [Instruction::LdVxVy { x: f, y: x }, Instruction::SubVxVy { x: f, y }],
+                    ("<" | ">=", Operand::Byte(kk)) => [Instruction::LdVxByte { x: f, kk }, Instruction::SubnVxVy { x: f, y: x }],
+                    (_, Operand::Reg(y)) => [Instruction::LdVxVy { x: f, y }, Instruction::SubVxVy { x: f, y: x }],
+                    (_, Operand::Byte(kk)) => [Instruction::LdVxByte { x: f, kk }, Instruction::SubVxVy { x: f, y: x }],
+                };
+                for ins in ins {
+                    self.emit_ins(ins)?;
+                }
+
+                match op.as_str() {
+                    "<" | ">" => Ok(Cond::Eq(f, Operand::Byte(0))),
+                    _ => Ok(Cond::Ne(f, Operand::Byte(0))),
+                }
+            },
+            _ => Err(self.error(format!("unknown comparison: {}", op))),
+        }
+    }
+
+    fn conditional(&mut self) -> Result<(), AsmError> {
+        let cond = self.condition()?;
+
+        match self.next()?.as_str() {
+            "then" => self.emit_ins(cond.negate().skip()),
+            "begin" => {
+                self.emit_ins(cond.skip())?;
+                self.blocks.push(Block::If { jump: self.here, line: self.line });
+                self.emit_ins(Instruction::Jp(0))
+            },
+            tok => Err(self.error(format!("expected then or begin, found {}", tok))),
+        }
+    }
+
+    // :unpack nibble label, or :unpack long label, loads the address into v0 and v1
+    fn unpack(&mut self) -> Result<(), AsmError> {
+        let high = self.next()?;
+        let nibble = match high.as_str() {
+            "long" => None,
+            _ => Some(self.number(&high, 0, 0xF)? as u8),
+        };
+        let target = self.next()?;
+
+        let pos = self.here;
+        self.emit_ins(Instruction::LdVxByte { x: 0, kk: 0 })?;
+        self.emit_ins(Instruction::LdVxByte { x: 1, kk: 0 })?;
+        self.reference(pos, FixupKind::UnpackHigh(nibble), &target)?;
+        self.reference(pos + 2, FixupKind::UnpackLow, &target)
+    }
+
+    fn define_macro(&mut self) -> Result<(), AsmError> {
+        let name = self.next()?;
+        if !is_name(&name) || self.symbols.contains_key(&name) || self.macros.contains_key(&name) {
+            return Err(self.error(format!("invalid macro name: {}", name)));
+        }
+
+        let mut params = Vec::new();
+        loop {
+            let tok = self.next()?;
+            if tok == "{" {
+                break;
+            }
+            params.push(tok);
+        }
+
+        let body = self.block_body()?;
+        self.macros.insert(name, Macro { params, body });
+        Ok(())
+    }
+
+    // tokens up to the matching }, after the opening { has been read
+    fn block_body(&mut self) -> Result<Vec<Token>, AsmError> {
+        let mut body = Vec::new();
+        let mut depth = 1;
+
+        loop {
+            let tok = self.tokens.pop_front().ok_or_else(|| self.error("missing }"))?;
+            match tok.text.as_str() {
+                "{" => depth += 1,
+                "}" => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return Ok(body);
+                    }
+                },
+                _ => {}
+            }
+            body.push(tok);
+        }
+    }
+
+    fn expand(&mut self, name: &str) -> Result<(), AsmError> {
+        self.expansions += 1;
+        if self.expansions > MAX_EXPANSIONS {
+            return Err(self.error("too many macro expansions"));
+        }
+
+        let count = self.macros[name].params.len();
+        let mut args = HashMap::new();
+        for i in 0..count {
+            let arg = self.next()?;
+            args.insert(self.macros[name].params[i].clone(), arg);
+        }
+
+        for tok in self.macros[name].body.iter().rev() {
+            let text = args.get(&tok.text).cloned().unwrap_or_else(|| tok.text.clone());
+            self.tokens.push_front(Token { text, line: tok.line });
+        }
+        Ok(())
+    }
+
+    // { expression }: operators have equal precedence and evaluate right to left
+    fn calc(&mut self) -> Result<f64, AsmError> {
+        self.expect("{")?;
+        let body: Vec<String> = self.block_body()?.into_iter().map(|t| t.text).collect();
+
+        let mut pos = 0;
+        let value = self.expression(&body, &mut pos)?;
+        if pos != body.len() {
+            return Err(self.error(format!("unexpected {} in expression", body[pos])));
+        }
+        Ok(value)
+    }
+
+    fn expression(&self, toks: &[String], pos: &mut usize) -> Result<f64, AsmError> {
+        let lhs = self.term(toks, pos)?;
+
+        let op = match toks.get(*pos) {
+            Some(op) if op != ")" => op.as_str(),
+            _ => return Ok(lhs),
+        };
+        *pos += 1;
+        let rhs = self.expression(toks, pos)?;
+
+        let (a, b) = (lhs as i64, rhs as i64);
+        let bool = |v: bool| if v { 1.0 } else { 0.0 };
+        Ok(match op {
+            "+" => lhs + rhs,
+            "-" => lhs - rhs,
+            "*" => lhs * rhs,
+            "/" if rhs == 0.0 => return Err(self.error("division by zero")),
+            "/" => lhs / rhs,
+            "%" if b == 0 => return Err(self.error("division by zero")),
+            "%" => (a % b) as f64,
+            "&" => (a & b) as f64,
+            "|" => (a | b) as f64,
+            "^" => (a ^ b) as f64,
+            "<<" => a.checked_shl(b as u32).unwrap_or(0) as f64,
+            ">>" => a.checked_shr(b as u32).unwrap_or(0) as f64,
+            "pow" => lhs.powf(rhs),
+            "min" => lhs.min(rhs),
+            "max" => lhs.max(rhs),
+            "<" => bool(lhs < rhs),
+            "<=" => bool(lhs <= rhs),
+            "==" => bool(lhs == rhs),
+            "!=" => bool(lhs != rhs),
+            ">=" => bool(lhs >= rhs),
+            ">" => bool(lhs > rhs),
+            _ => return Err(self.error(format!("unknown operator: {}", op))),
+        })
+    }
+
+    fn term(&self, toks: &[String], pos: &mut usize) -> Result<f64, AsmError> {
+        let tok = toks.get(*pos).ok_or_else(|| self.error("incomplete expression"))?;
+        *pos += 1;
+
+        let unary = |f: fn(f64) -> f64, pos: &mut usize| self.term(toks, pos).map(f);
+        match tok.as_str() {
+            "(" => {
+                let value = self.expression(toks, pos)?;
+                if toks.get(*pos).map(String::as_str) != Some(")") {
+                    return Err(self.error("missing )"));
+                }
+                *pos += 1;
+                Ok(value)
+            },
+            "-" => unary(|v| -v, pos),
+            "~" => unary(|v| !(v as i64) as f64, pos),
+            "!" => unary(|v| if v == 0.0 { 1.0 } else { 0.0 }, pos),
+            "sin" => unary(f64::sin, pos),
+            "cos" => unary(f64::cos, pos),
+            "tan" => unary(f64::tan, pos),
+            "exp" => unary(f64::exp, pos),
+            "log" => unary(f64::ln, pos),
+            "abs" => unary(f64::abs, pos),
+            "sqrt" => unary(f64::sqrt, pos),
+            "sign" => unary(f64::signum, pos),
+            "ceil" => unary(f64::ceil, pos),
+            "floor" => unary(f64::floor, pos),
+            "@" => {
+                let addr = self.term(toks, pos)? as i64 - ORIGIN as i64;
+                Ok(usize::try_from(addr).ok().and_then(|a| self.rom.get(a)).copied().unwrap_or(0) as f64)
+            },
+            "HERE" => Ok(self.addr() as f64),
+            "PI" => Ok(consts::PI),
+            "E" => Ok(consts::E),
+            _ => match self.value(tok)? {
+                Some(value) => Ok(value),
+                None => Err(self.error(format!("undefined name: {}", tok))),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::computer::Computer;
+    use crate::quirks::Quirks;
+
+    #[test]
+    fn compiles_control_flow() {
+        let source = "
+            : main
+                v0 := 0
+                loop
+                    v0 += 1
+                    if v0 == 5 then v1 := 2
+                    while v0 != 10
+                again
+                sub
+            : sub
+                return
+            ";
+        let expected = vec![
+            0x60, 0x00,     // 200 v0 := 0
+            0x70, 0x01,     // 202 v0 += 1
+            0x40, 0x05,     // 204 skip unless v0 == 5
+            0x61, 0x02,     // 206 v1 := 2
+            0x40, 0x0A,     // 208 leave the loop unless v0 != 10
+            0x12, 0x0E,     // 20A
+            0x12, 0x02,     // 20C again
+            0x22, 0x10,     // 20E sub
+            0x00, 0xEE,     // 210
+        ];
+        assert_eq!(compile(source), Ok(expected));
+    }
+
+    #[test]
+    fn evaluates_constants_and_data() {
+        // main isn't first, so the program starts with a jump to it
+        let source = ":const SIZE 4\n: main i := data sprite v0 v0 SIZE\n: data 0x81 0x42 :calc twice { SIZE * 2 } :byte twice";
+        assert_eq!(compile(source), Ok(vec![0x12, 0x02, 0xA2, 0x06, 0xD0, 0x04, 0x81, 0x42, 0x08]));
+    }
+
+    #[test]
+    fn expands_macros_and_aliases() {
+        let source = ": main\n:alias counter v3\n:macro bump REG AMOUNT { REG += AMOUNT }\nbump counter 2\nbump v1 counter";
+        assert_eq!(compile(source), Ok(vec![0x73, 0x02, 0x81, 0x34]));
+    }
+
+    #[test]
+    fn calc_evaluates_right_to_left() {
+        let source = ": main\n:calc A { 2 * 3 + 4 }\n:calc B { ( 2 * 3 ) + 4 }\n:byte A :byte B :byte { A - B }";
+        assert_eq!(compile(source), Ok(vec![14, 10, 4]));
+    }
+
+    #[test]
+    fn compiles_if_blocks() {
+        let source = "
+            : main
+                if v0 == 1 begin
+                    v1 := 1
+                else
+                    v1 := 2
+                end
+                v2 := 3
+                if v0 != v1 begin
+                    v2 := 1
+                end
+            ";
+        let expected = vec![
+            0x30, 0x01,     // 200 skip into the block if v0 == 1
+            0x12, 0x08,     // 202 to else
+            0x61, 0x01,     // 204
+            0x12, 0x0A,     // 206 past end
+            0x61, 0x02,     // 208 else
+            0x62, 0x03,     // 20A end
+            0x90, 0x10,     // 20C
+            0x12, 0x12,     // 20E
+            0x62, 0x01,     // 210
+        ];
+        assert_eq!(compile(source), Ok(expected));
+    }
+
+    #[test]
+    fn expands_ordering_comparisons_through_vf() {
+        let cases: [(&str, [u8; 6]); 4] = [
+            ("v1 < v2", [0x8F, 0x10, 0x8F, 0x25, 0x4F, 0x00]),
+            ("v1 >= v2", [0x8F, 0x10, 0x8F, 0x25, 0x3F, 0x00]),
+            ("v1 > 5", [0x6F, 0x05, 0x8F, 0x15, 0x4F, 0x00]),
+            ("v1 <= 5", [0x6F, 0x05, 0x8F, 0x15, 0x3F, 0x00]),
+        ];
+        for (cond, expected) in cases {
+            let rom = compile(&format!(": main if {} then v3 := 1", cond)).unwrap();
+            assert_eq!(rom[..6], expected, "{}", cond);
+        }
+
+        // and they pick the right side when run
+        let source = "
+            : main
+                v1 := 3 v2 := 5 v3 := 0
+                if v1 < v2 then v3 += 1
+                if v1 > v2 then v3 += 2
+                if v2 >= 5 then v3 += 4
+                if v1 <= 2 then v3 += 8
+                if v1 < 3 then v3 += 16
+                if v2 <= 5 then v3 += 32
+                loop again
+            ";
+        let mut comp = Computer::with_seed(compile(source).unwrap(), Quirks::default(), 0);
+        for _ in 0..40 {
+            comp.tick().unwrap();
+        }
+        assert_eq!(comp.cpu().v[3], 1 + 4 + 32);
+    }
+
+    #[test]
+    fn loads_long_addresses() {
+        let rom = compile(": main\ni := long data\n:org 0x1000\n: data 0xAB").unwrap();
+        assert_eq!(rom[..4], [0xF0, 0x00, 0x10, 0x00]);
+        assert_eq!(rom.len(), 0x1000 - 0x200 + 1);
+        assert_eq!(rom[0x1000 - 0x200], 0xAB);
+    }
+
+    #[test]
+    fn reports_undefined_names() {
+        let err = compile(": main\n  jump nowhere").unwrap_err();
+        assert_eq!(err.line, 2);
+    }
+}
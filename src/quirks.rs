@@ -0,0 +1,239 @@
+use std::str::FromStr;
+use crate::state::{StateError, StateReader, StateWriter};
+
+// what Fx55/Fx65 leave in I
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IndexIncrement {
+    Unchanged,      // SUPER-CHIP 1.1 and later
+    ByX,            // CHIP-48, one short of the last register
+    ByXPlusOne,     // COSMAC VIP, pointing past the last register
+}
+
+// behaviours that differ between CHIP-8 interpreters; the default matches
+// what this emulator has always done
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Quirks {
+    pub shift_uses_vy: bool,            // 8xy6/8xyE shift Vy into Vx instead of shifting Vx
+    pub load_store_i: IndexIncrement,   // how Fx55/Fx65 move I
+    pub jump_uses_vx: bool,             // Bxnn jumps to xnn + Vx instead of nnn + V0
+    pub logic_resets_vf: bool,          // 8xy1/8xy2/8xy3 clear VF
+    pub wrap_sprites: bool,             // sprites wrap around the screen edges instead of clipping
+    pub display_wait: bool,             // Dxyn waits for the next 60 Hz frame
+    pub long_skip: bool,                // skips step over F000 nnnn as one 4-byte instruction
+    pub memory_size: usize,             // 4 KiB, or 64 KiB for XO-CHIP
+}
+
+impl Default for Quirks {
+    fn default() -> Self {
+        Self {
+            shift_uses_vy: false,
+            load_store_i: IndexIncrement::Unchanged,
+            jump_uses_vx: false,
+            logic_resets_vf: false,
+            wrap_sprites: false,
+            display_wait: false,
+            long_skip: false,
+            memory_size: 4096,
+        }
+    }
+}
+
+impl Quirks {
+    pub fn cosmac_vip() -> Self {
+        Self {
+            shift_uses_vy: true,
+            load_store_i: IndexIncrement::ByXPlusOne,
+            jump_uses_vx: false,
+            logic_resets_vf: true,
+            wrap_sprites: false,
+            display_wait: true,
+            long_skip: false,
+            memory_size: 4096,
+        }
+    }
+
+    pub fn chip48() -> Self {
+        Self {
+            shift_uses_vy: false,
+            load_store_i: IndexIncrement::ByX,
+            jump_uses_vx: true,
+            logic_resets_vf: false,
+            wrap_sprites: false,
+            display_wait: false,
+            long_skip: false,
+            memory_size: 4096,
+        }
+    }
+
+    pub fn super_chip() -> Self {
+        Self {
+            shift_uses_vy: false,
+            load_store_i: IndexIncrement::Unchanged,
+            jump_uses_vx: true,
+            logic_resets_vf: false,
+            wrap_sprites: false,
+            display_wait: false,
+            long_skip: false,
+            memory_size: 4096,
+        }
+    }
+
+    pub fn xo_chip() -> Self {
+        Self {
+            shift_uses_vy: true,
+            load_store_i: IndexIncrement::ByXPlusOne,
+            jump_uses_vx: false,
+            logic_resets_vf: false,
+            wrap_sprites: true,
+            display_wait: false,
+            long_skip: true,
+            memory_size: 65536,
+        }
+    }
+
+    // the profile name FromStr takes for these quirks, if they are one
+    pub fn name(&self) -> Option<&'static str> {
+        [("default", Quirks::default()), ("vip", Quirks::cosmac_vip()), ("chip48", Quirks::chip48()), ("schip", Quirks::super_chip()), ("xo", Quirks::xo_chip())]
+            .into_iter()
+            .find(|(_, quirks)| quirks == self)
+            .map(|(name, _)| name)
+    }
+
+    // every field by name, in the form set() takes, for writing quirks down
+    // that aren't one of the profiles
+    pub fn fields(&self) -> Vec<(&'static str, String)> {
+        let load_store_i = match self.load_store_i {
+            IndexIncrement::Unchanged => "unchanged",
+            IndexIncrement::ByX => "x",
+            IndexIncrement::ByXPlusOne => "x+1",
+        };
+
+        vec![
+            ("shift_uses_vy", self.shift_uses_vy.to_string()),
+            ("load_store_i", load_store_i.to_string()),
+            ("jump_uses_vx", self.jump_uses_vx.to_string()),
+            ("logic_resets_vf", self.logic_resets_vf.to_string()),
+            ("wrap_sprites", self.wrap_sprites.to_string()),
+            ("display_wait", self.display_wait.to_string()),
+            ("long_skip", self.long_skip.to_string()),
+            ("memory_size", self.memory_size.to_string()),
+        ]
+    }
+
+    pub fn set(&mut self, field: &str, value: &str) -> Result<(), String> {
+        let invalid = || format!("invalid value for {}: {}", field, value);
+        let flag = || value.parse::<bool>().map_err(|_| invalid());
+
+        match field {
+            "shift_uses_vy" => self.shift_uses_vy = flag()?,
+            "load_store_i" => self.load_store_i = match value {
+                "unchanged" => IndexIncrement::Unchanged,
+                "x" => IndexIncrement::ByX,
+                "x+1" => IndexIncrement::ByXPlusOne,
+                _ => return Err(invalid()),
+            },
+            "jump_uses_vx" => self.jump_uses_vx = flag()?,
+            "logic_resets_vf" => self.logic_resets_vf = flag()?,
+            "wrap_sprites" => self.wrap_sprites = flag()?,
+            "display_wait" => self.display_wait = flag()?,
+            "long_skip" => self.long_skip = flag()?,
+            "memory_size" => self.memory_size = match value {
+                "4096" => 4096,
+                "65536" => 65536,
+                _ => return Err(invalid()),
+            },
+            _ => return Err(format!("unknown quirk: {}", field)),
+        }
+        Ok(())
+    }
+
+    pub(crate) fn write_state(&self, out: &mut StateWriter) {
+        out.bool(self.shift_uses_vy);
+        out.u8(self.load_store_i as u8);
+        out.bool(self.jump_uses_vx);
+        out.bool(self.logic_resets_vf);
+        out.bool(self.wrap_sprites);
+        out.bool(self.display_wait);
+        out.bool(self.long_skip);
+        out.u32(self.memory_size as u32);
+    }
+
+    pub(crate) fn read_state(r: &mut StateReader) -> Result<Self, StateError> {
+        let quirks = Self {
+            shift_uses_vy: r.bool()?,
+            load_store_i: match r.u8()? {
+                0 => IndexIncrement::Unchanged,
+                1 => IndexIncrement::ByX,
+                2 => IndexIncrement::ByXPlusOne,
+                _ => return Err(StateError::Invalid("Fx55/Fx65 quirk")),
+            },
+            jump_uses_vx: r.bool()?,
+            logic_resets_vf: r.bool()?,
+            wrap_sprites: r.bool()?,
+            display_wait: r.bool()?,
+            long_skip: r.bool()?,
+            memory_size: r.u32()? as usize,
+        };
+        if quirks.memory_size != 4096 && quirks.memory_size != 65536 {
+            return Err(StateError::Invalid("memory size"));
+        }
+
+        Ok(quirks)
+    }
+}
+
+impl FromStr for Quirks {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "default" => Ok(Quirks::default()),
+            "vip" | "cosmac-vip" => Ok(Quirks::cosmac_vip()),
+            "chip48" | "chip-48" => Ok(Quirks::chip48()),
+            "schip" | "super-chip" => Ok(Quirks::super_chip()),
+            "xo" | "xo-chip" => Ok(Quirks::xo_chip()),
+            _ => Err(format!("unknown quirks profile: {}", s)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_profile_has_a_name_that_parses_back() {
+        for name in ["default", "vip", "chip48", "schip", "xo"] {
+            let quirks: Quirks = name.parse().unwrap();
+            assert_eq!(quirks.name(), Some(name));
+        }
+    }
+
+    #[test]
+    fn fields_can_be_set_back_by_name() {
+        let quirks = Quirks { load_store_i: IndexIncrement::ByX, memory_size: 65536, ..Quirks::cosmac_vip() };
+        let mut copy = Quirks::default();
+        for (field, value) in quirks.fields() {
+            copy.set(field, &value).unwrap();
+        }
+        assert_eq!(copy, quirks);
+
+        assert_eq!(copy.set("wrap_sprites", "yes"), Err("invalid value for wrap_sprites: yes".to_string()));
+        assert_eq!(copy.set("memory_size", "0"), Err("invalid value for memory_size: 0".to_string()));
+        assert_eq!(copy.set("turbo", "true"), Err("unknown quirk: turbo".to_string()));
+    }
+
+    fn read(quirks: Quirks) -> Result<Quirks, StateError> {
+        let mut out = StateWriter::new();
+        quirks.write_state(&mut out);
+        Quirks::read_state(&mut StateReader::new(&out.into_inner()))
+    }
+
+    #[test]
+    fn states_only_take_real_memory_sizes() {
+        assert_eq!(read(Quirks::xo_chip()), Ok(Quirks::xo_chip()));
+        for memory_size in [0, 1, 4095, 0x10001] {
+            assert_eq!(read(Quirks { memory_size, ..Quirks::default() }), Err(StateError::Invalid("memory size")));
+        }
+    }
+}
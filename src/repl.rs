@@ -0,0 +1,279 @@
+use std::io::{self, BufRead, Write};
+use chip8_rs::computer::Computer;
+use chip8_rs::debugger::{Debugger, RegisterWatch, StopReason, WatchEvent, Watchpoint};
+use chip8_rs::instruction::decode;
+use chip8_rs::memory::AccessKind;
+
+const CYCLES_PER_FRAME: usize = 10;
+
+const HELP: &str = "commands:
+  s, step [N]        execute N instructions (default 1)
+  rs, rstep [N]      step N instructions backwards (default 1)
+  n, next            step over CALL
+  finish             run until the current subroutine returns
+  c, continue        run until a breakpoint, exit or error
+  b, break ADDR      set a breakpoint
+  d, delete [ADDR]   remove one breakpoint, or all of them
+  watch ADDR[-END]   stop when memory in the range is written
+  rwatch ADDR[-END]  stop when it is read
+  awatch ADDR[-END]  stop when it is read or written
+  wreg REG [VALUE]   stop when a register changes, or changes to VALUE
+  unwatch            remove all watchpoints
+  info               list breakpoints and watchpoints
+  r, regs            show registers
+  stack              show the call stack
+  x ADDR [LEN]       dump memory (LEN defaults to 16)
+  l, list [N]        disassemble N instructions from PC (default 8)
+  press K, release K hold or release hex key K
+  fb                 show the screen
+  q, quit            exit the debugger
+an empty line repeats the last command";
+
+fn parse_hex(s: &str) -> Option<u16> {
+    u16::from_str_radix(s.trim_start_matches("0x").trim_start_matches('$'), 16).ok()
+}
+
+fn show_location(dbg: &Debugger) {
+    let pc = dbg.computer().cpu().pc;
+    match dbg.current_instruction() {
+        Some(ins) => println!("{:#05x}: {}", pc, ins),
+        None => println!("{:#05x}: ???", pc),
+    }
+}
+
+fn show_stop(dbg: &Debugger, reason: StopReason) {
+    match reason {
+        StopReason::Stepped => {},
+        StopReason::Breakpoint(addr) => println!("breakpoint at {:#05x}", addr),
+        StopReason::Watchpoint(hit) => {
+            match hit.event {
+                WatchEvent::Memory(access) => {
+                    let kind = match access.kind {
+                        AccessKind::Read => "read",
+                        AccessKind::Write => "write",
+                    };
+                    print!("watchpoint: {} {:#04x} at {:#05x}", kind, access.value, access.addr);
+                },
+                WatchEvent::Register { reg, old, new } => print!("watchpoint: {} {:#x} -> {:#x}", reg, old, new),
+            }
+            println!(" by {:#05x}: {}", hit.pc, hit.instruction);
+        },
+        StopReason::Exited => println!("program exited"),
+        StopReason::WaitingForKey => println!("waiting for a key, use press"),
+        StopReason::NotInSubroutine => println!("not in a subroutine"),
+        StopReason::StepLimit => println!("still running after the step limit, stopped"),
+        StopReason::Error(e) => println!("error: {}", e),
+    }
+    show_location(dbg);
+}
+
+fn parse_range(s: &str) -> Option<(usize, usize)> {
+    match s.split_once('-') {
+        Some((start, end)) => Some((parse_hex(start)? as usize, parse_hex(end)? as usize)),
+        None => parse_hex(s).map(|a| (a as usize, a as usize)),
+    }
+}
+
+fn show_regs(dbg: &Debugger) {
+    let cpu = dbg.computer().cpu();
+    for (row, regs) in cpu.v.chunks(8).enumerate() {
+        let regs: Vec<String> = regs.iter().enumerate().map(|(n, v)| format!("V{:X}={:02X}", row * 8 + n, v)).collect();
+        println!("{}", regs.join(" "));
+    }
+    println!("I={:#05x} PC={:#05x} SP={} DT={} ST={} cycles={}", cpu.i, cpu.pc, cpu.sp, cpu.dt, cpu.st, dbg.cycles());
+}
+
+fn show_memory(dbg: &Debugger, addr: u16, len: usize) {
+    let memory = dbg.computer().memory();
+
+    for row in (0..len).step_by(16) {
+        let start = addr as usize + row;
+        let bytes: Vec<String> = (start..start + (len - row).min(16))
+            .map(|a| match memory.read(a) {
+                Ok(b) => format!("{:02X}", b),
+                Err(_) => "--".to_string(),
+            })
+            .collect();
+        println!("{:#05x}: {}", start, bytes.join(" "));
+    }
+}
+
+fn list(dbg: &Debugger, count: usize) {
+    let memory = dbg.computer().memory();
+    let mut pc = dbg.computer().cpu().pc as usize;
+
+    for _ in 0..count {
+        let word = |a: usize| Some(((memory.read(a).ok()? as u16) << 8) | memory.read(a + 1).ok()? as u16);
+        let op = match word(pc) {
+            Some(op) => op,
+            None => break,
+        };
+
+        let marker = if dbg.breakpoints().any(|b| b as usize == pc) { "*" } else { " " };
+        match decode(op) {
+            Ok(ins) if ins.size() == 4 => {
+                println!("{} {:#05x}: {} {:#06x}", marker, pc, ins, word(pc + 2).unwrap_or(0));
+            },
+            Ok(ins) => println!("{} {:#05x}: {}", marker, pc, ins),
+            Err(_) => println!("{} {:#05x}: DW {:#06x}", marker, pc, op),
+        }
+        pc += match decode(op) {
+            Ok(ins) => ins.size() as usize,
+            Err(_) => 2,
+        };
+    }
+}
+
+fn show_screen(dbg: &Debugger) {
+    let display = dbg.computer().display();
+    for y in 0..display.height() {
+        let row: String = (0..display.width()).map(|x| if display.get(x, y) { '#' } else { '.' }).collect();
+        println!("{}", row);
+    }
+}
+
+// returns false when the user wants to quit
+fn command(dbg: &mut Debugger, line: &str) -> bool {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    let arg = |n: usize| words.get(n).copied();
+
+    match words[0] {
+        "s" | "step" => {
+            let count = arg(1).and_then(|n| n.parse().ok()).unwrap_or(1);
+            let mut reason = StopReason::Stepped;
+            for _ in 0..count {
+                reason = dbg.step();
+                if reason != StopReason::Stepped {
+                    break;
+                }
+            }
+            show_stop(dbg, reason);
+        },
+        "rs" | "rstep" => {
+            let count = arg(1).and_then(|n| n.parse().ok()).unwrap_or(1);
+            for _ in 0..count {
+                if !dbg.reverse_step() {
+                    println!("no history to step back into");
+                    break;
+                }
+            }
+            show_location(dbg);
+        },
+        "n" | "next" => {
+            let reason = dbg.step_over();
+            show_stop(dbg, reason);
+        },
+        "finish" => {
+            let reason = dbg.step_out();
+            show_stop(dbg, reason);
+        },
+        "c" | "continue" => {
+            let reason = dbg.cont();
+            show_stop(dbg, reason);
+        },
+        "b" | "break" => match arg(1).and_then(parse_hex) {
+            Some(addr) => {
+                dbg.add_breakpoint(addr);
+                println!("breakpoint at {:#05x}", addr);
+            },
+            None => println!("usage: break ADDR"),
+        },
+        "d" | "delete" => match arg(1) {
+            Some(addr) => match parse_hex(addr) {
+                Some(addr) if dbg.remove_breakpoint(addr) => {},
+                _ => println!("no breakpoint at {}", addr),
+            },
+            None => dbg.clear_breakpoints(),
+        },
+        "watch" | "rwatch" | "awatch" => match arg(1).and_then(parse_range) {
+            Some((start, end)) => {
+                let read = words[0] != "watch";
+                let write = words[0] != "rwatch";
+                dbg.add_watchpoint(Watchpoint { start, end, read, write });
+            },
+            None => println!("usage: {} ADDR[-END]", words[0]),
+        },
+        "wreg" => match arg(1).map(|r| r.parse()) {
+            Some(Ok(reg)) => {
+                let value = arg(2).and_then(parse_hex);
+                dbg.add_register_watch(RegisterWatch { reg, value });
+            },
+            Some(Err(e)) => println!("{}", e),
+            None => println!("usage: wreg REG [VALUE]"),
+        },
+        "unwatch" => dbg.clear_watchpoints(),
+        "info" => {
+            for addr in dbg.breakpoints() {
+                println!("breakpoint at {:#05x}", addr);
+            }
+            for w in dbg.watchpoints() {
+                let kind = match (w.read, w.write) {
+                    (true, true) => "access",
+                    (true, false) => "read",
+                    _ => "write",
+                };
+                println!("{} watchpoint on {:#05x}-{:#05x}", kind, w.start, w.end);
+            }
+            for w in dbg.register_watches() {
+                match w.value {
+                    Some(value) => println!("watch {} == {:#x}", w.reg, value),
+                    None => println!("watch {} changes", w.reg),
+                }
+            }
+        },
+        "r" | "regs" => show_regs(dbg),
+        "stack" => {
+            let cpu = dbg.computer().cpu();
+            for (depth, addr) in cpu.stack().iter().rev().enumerate() {
+                println!("#{} {:#05x}", depth, addr);
+            }
+        },
+        "x" => match arg(1).and_then(parse_hex) {
+            Some(addr) => show_memory(dbg, addr, arg(2).and_then(|n| n.parse().ok()).unwrap_or(16)),
+            None => println!("usage: x ADDR [LEN]"),
+        },
+        "l" | "list" => list(dbg, arg(1).and_then(|n| n.parse().ok()).unwrap_or(8)),
+        "press" | "release" => match arg(1).and_then(parse_hex) {
+            Some(key) if key <= 0x0F && words[0] == "press" => dbg.computer_mut().press(key as u8),
+            Some(key) if key <= 0x0F => dbg.computer_mut().release(key as u8),
+            _ => println!("usage: {} K", words[0]),
+        },
+        "fb" => show_screen(dbg),
+        "h" | "help" => println!("{}", HELP),
+        "q" | "quit" => return false,
+        other => println!("unknown command {}, try help", other),
+    }
+
+    true
+}
+
+pub fn run(comp: Computer) {
+    let mut dbg = Debugger::new(comp, CYCLES_PER_FRAME);
+    let stdin = io::stdin();
+    let mut last = String::new();
+
+    show_location(&dbg);
+
+    loop {
+        print!("(chip8) ");
+        io::stdout().flush().unwrap();
+
+        let mut line = String::new();
+        if stdin.lock().read_line(&mut line).unwrap_or(0) == 0 {
+            break;
+        }
+
+        let line = match line.trim() {
+            "" => last.clone(),
+            line => line.to_string(),
+        };
+        if line.is_empty() {
+            continue;
+        }
+
+        if !command(&mut dbg, &line) {
+            break;
+        }
+        last = line;
+    }
+}
@@ -0,0 +1,187 @@
+use std::collections::VecDeque;
+
+// shortest run of unchanged bytes worth ending a literal for
+const MIN_RUN: usize = 4;
+
+fn xor_at(a: &[u8], b: &[u8], i: usize) -> u8 {
+    a.get(i).copied().unwrap_or(0) ^ b.get(i).copied().unwrap_or(0)
+}
+
+// XOR of `base` and `target`, stored as (skip u16, len u16, bytes) chunks after the target length,
+// so the mostly unchanged parts of consecutive snapshots take up next to nothing
+fn encode(base: &[u8], target: &[u8]) -> Vec<u8> {
+    let len = base.len().max(target.len());
+    let mut out = (target.len() as u32).to_le_bytes().to_vec();
+    let mut i = 0;
+
+    while i < len {
+        let start = i;
+        while i < len && i - start < 0xFFFF && xor_at(base, target, i) == 0 {
+            i += 1;
+        }
+        let skip = i - start;
+
+        let literal = i;
+        while i < len && i - literal < 0xFFFF && !(i..(i + MIN_RUN).min(len)).all(|j| xor_at(base, target, j) == 0) {
+            i += 1;
+        }
+
+        out.extend((skip as u16).to_le_bytes());
+        out.extend(((i - literal) as u16).to_le_bytes());
+        out.extend((literal..i).map(|j| xor_at(base, target, j)));
+    }
+
+    out
+}
+
+fn decode(base: &[u8], delta: &[u8]) -> Vec<u8> {
+    let target_len = u32::from_le_bytes(delta[..4].try_into().unwrap()) as usize;
+    let mut out = base.to_vec();
+    out.resize(base.len().max(target_len), 0);
+
+    let mut pos = 4;
+    let mut i = 0;
+    while pos < delta.len() {
+        let skip = u16::from_le_bytes([delta[pos], delta[pos + 1]]) as usize;
+        let len = u16::from_le_bytes([delta[pos + 2], delta[pos + 3]]) as usize;
+        pos += 4;
+        i += skip;
+
+        for (byte, x) in out[i..i + len].iter_mut().zip(&delta[pos..pos + len]) {
+            *byte ^= x;
+        }
+        i += len;
+        pos += len;
+    }
+
+    out.truncate(target_len);
+    out
+}
+
+// a bounded history of save states, newest last; each snapshot is tagged with a
+// position (a frame or instruction count) chosen by the caller
+pub struct Rewind {
+    capacity: usize,
+    latest: Option<(u64, Vec<u8>)>,
+    // deltas from the snapshot after each entry back to it
+    older: VecDeque<(u64, Vec<u8>)>,
+}
+
+impl Rewind {
+    pub fn new(capacity: usize) -> Self {
+        Self { capacity: capacity.max(1), latest: None, older: VecDeque::new() }
+    }
+
+    pub fn len(&self) -> usize {
+        self.older.len() + self.latest.is_some() as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.latest.is_none()
+    }
+
+    pub fn clear(&mut self) {
+        self.latest = None;
+        self.older.clear();
+    }
+
+    // bytes used by the stored snapshots
+    pub fn size(&self) -> usize {
+        self.latest.as_ref().map_or(0, |(_, s)| s.len()) + self.older.iter().map(|(_, d)| d.len()).sum::<usize>()
+    }
+
+    // adds a snapshot, dropping the oldest one once the buffer is full
+    pub fn push(&mut self, position: u64, state: Vec<u8>) {
+        if let Some((prev_position, prev)) = self.latest.take() {
+            self.older.push_back((prev_position, encode(&state, &prev)));
+            if self.older.len() >= self.capacity {
+                self.older.pop_front();
+            }
+        }
+        self.latest = Some((position, state));
+    }
+
+    pub fn latest(&self) -> Option<(u64, &[u8])> {
+        self.latest.as_ref().map(|(position, state)| (*position, &state[..]))
+    }
+
+    // removes and returns the newest snapshot
+    pub fn pop(&mut self) -> Option<(u64, Vec<u8>)> {
+        let (position, state) = self.latest.take()?;
+        if let Some((prev_position, delta)) = self.older.pop_back() {
+            self.latest = Some((prev_position, decode(&state, &delta)));
+        }
+        Some((position, state))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // snapshots that differ a little from one to the next, with a length change now and then
+    fn snapshot(n: usize) -> Vec<u8> {
+        let mut state: Vec<u8> = (0..300 + n % 3 * 50).map(|i| (i / 7) as u8).collect();
+        state[n * 13 % 300] = n as u8;
+        state[(n * 31 + 5) % 300] ^= 0xFF;
+        state
+    }
+
+    #[test]
+    fn deltas_rebuild_the_exact_target() {
+        let cases = [
+            (Vec::new(), vec![1, 2, 3]),
+            (vec![1, 2, 3], Vec::new()),
+            (vec![0; 100], vec![0; 100]),
+            (vec![0; 10], (0..200_000).map(|i| i as u8).collect()),
+            (snapshot(1), snapshot(2)),
+        ];
+        for (base, target) in cases {
+            assert_eq!(decode(&base, &encode(&base, &target)), target);
+        }
+    }
+
+    #[test]
+    fn pops_newest_first() {
+        let mut history = Rewind::new(10);
+        assert!(history.is_empty());
+        assert_eq!(history.pop(), None);
+
+        for n in 0..5 {
+            history.push(n as u64, snapshot(n));
+        }
+        assert_eq!(history.len(), 5);
+        assert_eq!(history.latest(), Some((4, &snapshot(4)[..])));
+
+        for n in (0..5).rev() {
+            assert_eq!(history.pop(), Some((n as u64, snapshot(n))));
+        }
+        assert!(history.is_empty());
+        assert_eq!(history.size(), 0);
+    }
+
+    #[test]
+    fn drops_the_oldest_snapshots_when_full() {
+        let mut history = Rewind::new(3);
+        for n in 0..8 {
+            history.push(n as u64, snapshot(n));
+            assert_eq!(history.len(), (n + 1).min(3));
+        }
+
+        let positions: Vec<u64> = std::iter::from_fn(|| history.pop()).map(|(position, _)| position).collect();
+        assert_eq!(positions, [7, 6, 5]);
+    }
+
+    #[test]
+    fn stores_deltas_instead_of_copies() {
+        let mut history = Rewind::new(100);
+        for n in 0..50 {
+            history.push(n, vec![0xAA; 4096]);
+        }
+        assert!(history.size() < 4096 + 50 * 16);
+
+        history.clear();
+        assert!(history.is_empty());
+        assert_eq!((history.len(), history.size()), (0, 0));
+    }
+}
@@ -0,0 +1,159 @@
+use crate::state::StateError;
+
+// where Cxkk gets its random bytes from; the state goes into save states so a
+// loaded state keeps producing the same numbers
+pub trait RandomSource {
+    fn next_byte(&mut self) -> u8;
+
+    // the current state, in the form restore() takes
+    fn save(&self) -> Vec<u8>;
+
+    fn restore(&mut self, state: &[u8]) -> Result<(), StateError>;
+}
+
+// xorshift64*, the default source
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Xorshift {
+    state: u64,
+}
+
+impl Xorshift {
+    pub fn new(seed: u64) -> Self {
+        // an all zero state would only ever produce zeros
+        let state = if seed == 0 { 0x9E37_79B9_7F4A_7C15 } else { seed };
+        Self { state }
+    }
+
+    // seeded from the OS, different every run
+    pub fn from_entropy() -> Self {
+        Self::new(rand::random())
+    }
+}
+
+impl RandomSource for Xorshift {
+    fn next_byte(&mut self) -> u8 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+
+        (self.state.wrapping_mul(0x2545_F491_4F6C_DD1D) >> 56) as u8
+    }
+
+    fn save(&self) -> Vec<u8> {
+        self.state.to_le_bytes().to_vec()
+    }
+
+    fn restore(&mut self, state: &[u8]) -> Result<(), StateError> {
+        let state: [u8; 8] = state.try_into().map_err(|_| StateError::Invalid("random state"))?;
+        match u64::from_le_bytes(state) {
+            0 => Err(StateError::Invalid("random state")),
+            state => {
+                self.state = state;
+                Ok(())
+            },
+        }
+    }
+}
+
+// hands out the given bytes in order and starts over at the end, for tests
+// that need to know exactly what RND returns
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Sequence {
+    bytes: Vec<u8>,
+    pos: usize,
+}
+
+impl Sequence {
+    pub fn new(bytes: Vec<u8>) -> Self {
+        Self { bytes, pos: 0 }
+    }
+}
+
+impl RandomSource for Sequence {
+    fn next_byte(&mut self) -> u8 {
+        let byte = self.bytes.get(self.pos).copied().unwrap_or(0);
+        self.pos = (self.pos + 1) % self.bytes.len().max(1);
+        byte
+    }
+
+    fn save(&self) -> Vec<u8> {
+        (self.pos as u32).to_le_bytes().to_vec()
+    }
+
+    fn restore(&mut self, state: &[u8]) -> Result<(), StateError> {
+        let state: [u8; 4] = state.try_into().map_err(|_| StateError::Invalid("random state"))?;
+        match u32::from_le_bytes(state) as usize {
+            pos if pos < self.bytes.len().max(1) => {
+                self.pos = pos;
+                Ok(())
+            },
+            _ => Err(StateError::Invalid("random state")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::computer::Computer;
+    use crate::quirks::Quirks;
+
+    fn bytes(rng: &mut dyn RandomSource, n: usize) -> Vec<u8> {
+        (0..n).map(|_| rng.next_byte()).collect()
+    }
+
+    #[test]
+    fn the_same_seed_gives_the_same_bytes() {
+        assert_eq!(bytes(&mut Xorshift::new(42), 100), bytes(&mut Xorshift::new(42), 100));
+        assert_ne!(bytes(&mut Xorshift::new(42), 100), bytes(&mut Xorshift::new(43), 100));
+
+        // zero is swapped for a seed that doesn't get stuck
+        assert_eq!(Xorshift::new(0), Xorshift::new(0x9E37_79B9_7F4A_7C15));
+        assert!(bytes(&mut Xorshift::new(0), 100).iter().any(|b| *b != 0));
+    }
+
+    #[test]
+    fn restoring_continues_the_sequence() {
+        let mut rng = Xorshift::new(7);
+        bytes(&mut rng, 10);
+        let saved = rng.save();
+        let expected = bytes(&mut rng, 10);
+
+        let mut other = Xorshift::new(1);
+        other.restore(&saved).unwrap();
+        assert_eq!(bytes(&mut other, 10), expected);
+
+        assert_eq!(other.restore(&[0; 8]), Err(StateError::Invalid("random state")));
+        assert_eq!(other.restore(&saved[..7]), Err(StateError::Invalid("random state")));
+    }
+
+    #[test]
+    fn sequences_start_over_at_the_end() {
+        let mut rng = Sequence::new(vec![1, 2, 3]);
+        assert_eq!(bytes(&mut rng, 7), [1, 2, 3, 1, 2, 3, 1]);
+
+        let saved = rng.save();
+        let mut other = Sequence::new(vec![1, 2, 3]);
+        other.restore(&saved).unwrap();
+        assert_eq!(bytes(&mut other, 3), [2, 3, 1]);
+        assert_eq!(other.restore(&3u32.to_le_bytes()), Err(StateError::Invalid("random state")));
+
+        assert_eq!(bytes(&mut Sequence::new(Vec::new()), 3), [0, 0, 0]);
+    }
+
+    #[test]
+    fn seeded_computers_roll_the_same_numbers() {
+        // 200 RND V0 FF, 202 LD I 300, 204 ADD I V1, 206 LD [I] V0, 208 ADD V1 1, 20A JP 200
+        let rom = vec![0xC0, 0xFF, 0xA3, 0x00, 0xF1, 0x1E, 0xF0, 0x55, 0x71, 0x01, 0x12, 0x00];
+        let rolls = |seed| {
+            let mut comp = Computer::with_seed(rom.clone(), Quirks::default(), seed);
+            for _ in 0..20 {
+                comp.run_frame(12).unwrap();
+            }
+            comp.memory().as_slice()[0x300..0x300 + 40].to_vec()
+        };
+
+        assert_eq!(rolls(5), rolls(5));
+        assert_ne!(rolls(5), rolls(6));
+    }
+}
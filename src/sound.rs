@@ -0,0 +1,51 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+
+// plays samples pushed from the emulator loop on the default output device
+pub struct Speaker {
+    _stream: cpal::Stream,
+    queue: Arc<Mutex<VecDeque<f32>>>,
+    sample_rate: u32,
+}
+
+impl Speaker {
+    pub fn open() -> Option<Self> {
+        let device = cpal::default_host().default_output_device()?;
+        let config: cpal::StreamConfig = device.default_output_config().ok()?.into();
+        let channels = config.channels as usize;
+        let sample_rate = config.sample_rate.0;
+
+        let queue = Arc::new(Mutex::new(VecDeque::new()));
+        let output = queue.clone();
+
+        let stream = device.build_output_stream(
+            &config,
+            move |data: &mut [f32], _: &cpal::OutputCallbackInfo| {
+                let mut queue = output.lock().unwrap();
+                for frame in data.chunks_mut(channels) {
+                    let sample = queue.pop_front().unwrap_or(0.0);
+                    frame.fill(sample);
+                }
+            },
+            |e| eprintln!("audio error: {}", e),
+            None,
+        ).ok()?;
+        stream.play().ok()?;
+
+        Some(Self { _stream: stream, queue, sample_rate })
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    pub fn push(&self, samples: &[f32]) {
+        let mut queue = self.queue.lock().unwrap();
+
+        // don't let latency build up if the device falls behind
+        if queue.len() < self.sample_rate as usize / 10 {
+            queue.extend(samples);
+        }
+    }
+}
@@ -0,0 +1,249 @@
+use std::io::{self, Write};
+use crate::cpu::Cpu;
+use crate::instruction::Instruction;
+
+// one executed instruction; `cycle` counts from 1
+#[derive(Clone, Debug)]
+pub struct TraceRecord {
+    pub cycle: u64,
+    pub pc: u16,
+    pub opcode: u16,
+    pub instruction: Instruction,
+    pub before: Cpu,
+    pub after: Cpu,
+    pub writes: Vec<(usize, u8)>,
+}
+
+impl TraceRecord {
+    pub fn mnemonic(&self) -> String {
+        match self.instruction {
+            Instruction::LdILong => format!("LD I, LONG 0x{:04X}", self.after.i),
+            ins => ins.to_string(),
+        }
+    }
+}
+
+// receives a record for every instruction a Computer completes
+pub trait TraceSink {
+    fn record(&mut self, record: &TraceRecord);
+
+    // flushes the output and reports the first error hit while writing, if any
+    fn finish(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// collects records in memory
+impl TraceSink for Vec<TraceRecord> {
+    fn record(&mut self, record: &TraceRecord) {
+        self.push(record.clone());
+    }
+}
+
+fn registers_json(cpu: &Cpu) -> String {
+    let v: Vec<String> = cpu.v.iter().map(|v| v.to_string()).collect();
+    format!("{{\"v\":[{}],\"i\":{},\"pc\":{},\"sp\":{},\"dt\":{},\"st\":{}}}", v.join(","), cpu.i, cpu.pc, cpu.sp, cpu.dt, cpu.st)
+}
+
+// one JSON object per line:
+// {"cycle":1,"pc":512,"opcode":224,"mnemonic":"CLS","before":{...},"after":{...},"writes":[[addr,value],...]}
+pub struct JsonTraceWriter<W: Write> {
+    out: W,
+    error: Option<io::Error>,
+}
+
+impl<W: Write> JsonTraceWriter<W> {
+    pub fn new(out: W) -> Self {
+        Self { out, error: None }
+    }
+
+    fn write(&mut self, record: &TraceRecord) -> io::Result<()> {
+        let writes: Vec<String> = record.writes.iter().map(|(addr, value)| format!("[{},{}]", addr, value)).collect();
+
+        writeln!(self.out, "{{\"cycle\":{},\"pc\":{},\"opcode\":{},\"mnemonic\":\"{}\",\"before\":{},\"after\":{},\"writes\":[{}]}}",
+            record.cycle, record.pc, record.opcode, record.mnemonic(),
+            registers_json(&record.before), registers_json(&record.after), writes.join(","))
+    }
+}
+
+impl<W: Write> TraceSink for JsonTraceWriter<W> {
+    fn record(&mut self, record: &TraceRecord) {
+        if self.error.is_none() {
+            self.error = self.write(record).err();
+        }
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        match self.error.take() {
+            Some(e) => Err(e),
+            None => self.out.flush(),
+        }
+    }
+}
+
+pub const BINARY_MAGIC: &[u8; 4] = b"C8TR";
+pub const BINARY_VERSION: u8 = 1;
+
+fn registers_binary(out: &mut Vec<u8>, cpu: &Cpu) {
+    out.extend(cpu.v);
+    out.extend(cpu.i.to_le_bytes());
+    out.extend(cpu.pc.to_le_bytes());
+    out.extend([cpu.sp, cpu.dt, cpu.st]);
+}
+
+// "C8TR", a version byte, then per record, little endian:
+// cycle u64, pc u16, opcode u16, registers before and after
+// (V0-VF, I u16, PC u16, SP, DT, ST; 23 bytes each), write count u16, then (addr u16, value u8) per write
+pub struct BinaryTraceWriter<W: Write> {
+    out: W,
+    header: bool,
+    error: Option<io::Error>,
+}
+
+impl<W: Write> BinaryTraceWriter<W> {
+    pub fn new(out: W) -> Self {
+        Self { out, header: false, error: None }
+    }
+
+    fn write(&mut self, record: &TraceRecord) -> io::Result<()> {
+        let mut buf = Vec::with_capacity(64);
+        if !self.header {
+            buf.extend(BINARY_MAGIC);
+            buf.push(BINARY_VERSION);
+            self.header = true;
+        }
+
+        buf.extend(record.cycle.to_le_bytes());
+        buf.extend(record.pc.to_le_bytes());
+        buf.extend(record.opcode.to_le_bytes());
+        registers_binary(&mut buf, &record.before);
+        registers_binary(&mut buf, &record.after);
+
+        buf.extend((record.writes.len() as u16).to_le_bytes());
+        for (addr, value) in &record.writes {
+            buf.extend((*addr as u16).to_le_bytes());
+            buf.push(*value);
+        }
+
+        self.out.write_all(&buf)
+    }
+}
+
+impl<W: Write> TraceSink for BinaryTraceWriter<W> {
+    fn record(&mut self, record: &TraceRecord) {
+        if self.error.is_none() {
+            self.error = self.write(record).err();
+        }
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        match self.error.take() {
+            Some(e) => Err(e),
+            None => self.out.flush(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use super::*;
+    use crate::computer::Computer;
+    use crate::quirks::Quirks;
+
+    struct Shared(Rc<RefCell<Vec<TraceRecord>>>);
+
+    impl TraceSink for Shared {
+        fn record(&mut self, record: &TraceRecord) {
+            self.0.borrow_mut().push(record.clone());
+        }
+    }
+
+    // 200 LD V0 7, 202 LD I 300, 204 LD [I] V0
+    fn traced() -> Vec<TraceRecord> {
+        let records = Rc::new(RefCell::new(Vec::new()));
+        let mut comp = Computer::with_seed(vec![0x60, 0x07, 0xA3, 0x00, 0xF0, 0x55], Quirks::default(), 0);
+        comp.set_trace_sink(Box::new(Shared(records.clone())));
+        for _ in 0..3 {
+            comp.tick().unwrap();
+        }
+
+        records.take()
+    }
+
+    #[test]
+    fn records_every_instruction_and_its_writes() {
+        let records = traced();
+
+        assert_eq!(records.iter().map(|r| (r.cycle, r.pc, r.opcode)).collect::<Vec<_>>(), [(1, 0x200, 0x6007), (2, 0x202, 0xA300), (3, 0x204, 0xF055)]);
+        assert_eq!((records[0].before.v[0], records[0].after.v[0]), (0, 7));
+        assert_eq!(records[2].writes, [(0x300, 7)]);
+        assert_eq!(records[2].mnemonic(), "LD [I], V0");
+    }
+
+    #[test]
+    fn writes_json_lines() {
+        let mut out = Vec::new();
+        let mut writer = JsonTraceWriter::new(&mut out);
+        for record in &traced() {
+            writer.record(record);
+        }
+        writer.finish().unwrap();
+
+        let text = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[2], concat!(
+            "{\"cycle\":3,\"pc\":516,\"opcode\":61525,\"mnemonic\":\"LD [I], V0\",",
+            "\"before\":{\"v\":[7,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0],\"i\":768,\"pc\":516,\"sp\":0,\"dt\":0,\"st\":0},",
+            "\"after\":{\"v\":[7,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0],\"i\":768,\"pc\":518,\"sp\":0,\"dt\":0,\"st\":0},",
+            "\"writes\":[[768,7]]}"));
+    }
+
+    #[test]
+    fn writes_the_binary_format() {
+        let mut out = Vec::new();
+        let mut writer = BinaryTraceWriter::new(&mut out);
+        for record in &traced() {
+            writer.record(record);
+        }
+        writer.finish().unwrap();
+
+        // header, two records without writes, one with a single write
+        assert_eq!(out.len(), 5 + 60 * 3 + 3);
+        assert_eq!(&out[..5], b"C8TR\x01");
+
+        let last = &out[5 + 60 * 2..];
+        assert_eq!(&last[..12], &[3, 0, 0, 0, 0, 0, 0, 0, 0x04, 0x02, 0x55, 0xF0]);
+        // V0 before, then I and PC after
+        assert_eq!(last[12], 7);
+        assert_eq!(&last[35 + 16..35 + 20], &[0x00, 0x03, 0x06, 0x02]);
+        assert_eq!(&last[58..], &[1, 0, 0x00, 0x03, 7]);
+    }
+
+    struct Failing;
+
+    impl Write for Failing {
+        fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+            Err(io::Error::other("disk full"))
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn finish_reports_write_errors() {
+        let records = traced();
+
+        let mut json = JsonTraceWriter::new(Failing);
+        json.record(&records[0]);
+        assert_eq!(json.finish().unwrap_err().to_string(), "disk full");
+
+        let mut binary = BinaryTraceWriter::new(Failing);
+        binary.record(&records[0]);
+        assert_eq!(binary.finish().unwrap_err().to_string(), "disk full");
+    }
+}
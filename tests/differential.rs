@@ -0,0 +1,190 @@
+// property tests over random roms and key presses: Computer must never panic
+// or run away with PC/SP under any quirks, and on standard CHIP-8 code it must
+// agree with the reference interpreter in tests/reference after every step
+mod reference;
+
+use chip8_rs::computer::{Computer, StepOutcome};
+use chip8_rs::memory::AccessPolicy;
+use chip8_rs::quirks::Quirks;
+use proptest::prelude::*;
+use reference::{Reference, Step, Stop};
+
+const STEPS: usize = 2000;
+const CYCLES_PER_FRAME: usize = 10;
+
+// (step, key, pressed)
+type Input = Vec<(usize, u8, bool)>;
+
+// a standard instruction, with jumps and calls kept inside the 3 KiB rom and
+// returns made rare, so programs run for a while instead of falling off into
+// empty memory or underflowing the stack
+fn standard_opcode(pick: u8, r: u16, sub: u8) -> u16 {
+    let xy = r & 0x0FF0;
+    let target = 0x200 + (r % 0x600) * 2;
+
+    match pick % 40 {
+        0 => 0x00E0,
+        1 if sub < 16 => 0x00EE,
+        1 => 0x00E0,
+        2 | 3 => 0x1000 | target,
+        4 | 5 => 0x2000 | target,
+        6 | 7 => 0x3000 | (r & 0x0FFF),
+        8 | 9 => 0x4000 | (r & 0x0FFF),
+        10 => 0x5000 | xy,
+        11..=13 => 0x6000 | (r & 0x0FFF),
+        14 | 15 => 0x7000 | (r & 0x0FFF),
+        16..=22 => 0x8000 | xy | [0x0, 0x1, 0x2, 0x3, 0x4, 0x5, 0x6, 0x7, 0xE][sub as usize % 9],
+        23 => 0x9000 | xy,
+        // mostly pointing outside the rom, so Fx55 doesn't overwrite the program with zeros
+        24 | 25 if sub < 192 => 0xA000 | [0x000, 0xE00][sub as usize % 2] | (r & 0x01FF),
+        24 | 25 => 0xA000 | (r & 0x0FFF),
+        26 => 0xB000 | target,
+        27 | 28 => 0xC000 | (r & 0x0FFF),
+        29..=31 => 0xD000 | xy | (sub as u16 % 15 + 1),
+        32 => 0xE09E | (r & 0x0F00),
+        33 => 0xE0A1 | (r & 0x0F00),
+        _ => 0xF000 | (r & 0x0F00) | [0x07, 0x0A, 0x15, 0x18, 0x1E, 0x29, 0x33, 0x55, 0x65][sub as usize % 9],
+    }
+}
+
+fn rom() -> impl Strategy<Value = Vec<u16>> {
+    let opcode = prop_oneof![
+        49 => (any::<u8>(), any::<u16>(), any::<u8>()).prop_map(|(pick, r, sub)| standard_opcode(pick, r, sub)),
+        1 => any::<u16>(),
+    ];
+    prop::collection::vec(opcode, 0x600)
+}
+
+fn input() -> impl Strategy<Value = Input> {
+    prop::collection::vec((0..STEPS, 0u8..16, any::<bool>()), 0..64).prop_map(|mut events| {
+        events.sort_by_key(|e| e.0);
+        events
+    })
+}
+
+fn quirks() -> impl Strategy<Value = Quirks> {
+    prop_oneof![Just(Quirks::default()), Just(Quirks::cosmac_vip()), Just(Quirks::chip48()), Just(Quirks::xo_chip())]
+}
+
+fn policy() -> impl Strategy<Value = AccessPolicy> {
+    prop_oneof![Just(AccessPolicy::Wrap), Just(AccessPolicy::Trap), Just(AccessPolicy::Clamp)]
+}
+
+fn bytes(rom: &[u16]) -> Vec<u8> {
+    rom.iter().flat_map(|op| op.to_be_bytes()).collect()
+}
+
+fn check_invariants(rom: Vec<u8>, quirks: Quirks, policy: AccessPolicy, seed: u64, input: &Input) -> Result<(), TestCaseError> {
+    let mut comp = Computer::with_seed(rom, quirks, seed);
+    comp.set_memory_policy(policy);
+    let len = comp.memory().len();
+    let mut events = input.iter().peekable();
+
+    for step in 0..STEPS {
+        while let Some((_, key, pressed)) = events.next_if(|e| e.0 <= step) {
+            if *pressed { comp.press(*key) } else { comp.release(*key) }
+        }
+
+        let pc = comp.cpu().pc;
+        let result = comp.tick();
+        let cpu = comp.cpu();
+        prop_assert!(cpu.sp <= 16, "SP {} after {:#06x}", cpu.sp, pc);
+
+        match result {
+            Ok(StepOutcome::Exited) => return Ok(()),
+            Ok(_) => prop_assert!((pc as usize) < len, "executed at {:#06x} with {} bytes of memory", pc, len),
+            Err(e) => {
+                prop_assert_eq!(cpu.pc, pc, "PC moved off the instruction that failed with {}", e);
+                return Ok(());
+            },
+        }
+
+        if (step + 1) % CYCLES_PER_FRAME == 0 {
+            comp.tick_timers();
+        }
+    }
+
+    Ok(())
+}
+
+fn compare(comp: &Computer, model: &Reference, drew: bool, at: u16, opcode: u16) -> Result<(), TestCaseError> {
+    let cpu = comp.cpu();
+    let context = format!("after {:04X} at {:#05x}", opcode, at);
+
+    prop_assert_eq!(cpu.v, model.v, "V registers {}", context);
+    prop_assert_eq!(cpu.i, model.i, "I {}", context);
+    prop_assert_eq!(cpu.pc, model.pc, "PC {}", context);
+    prop_assert_eq!(cpu.stack(), &model.stack[..], "stack {}", context);
+    prop_assert_eq!((cpu.dt, cpu.st), (model.dt, model.st), "timers {}", context);
+    prop_assert!(comp.memory().as_slice() == &model.memory[..], "memory {}", context);
+
+    if drew {
+        let display = comp.display();
+        for (y, row) in model.screen.iter().enumerate() {
+            for (x, pixel) in row.iter().enumerate() {
+                prop_assert_eq!(display.get(x, y), *pixel, "pixel ({}, {}) {}", x, y, context);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn check_against_reference(rom: Vec<u8>, seed: u64, input: &Input) -> Result<(), TestCaseError> {
+    let mut comp = Computer::with_seed(rom, Quirks::default(), seed);
+    let mut model = Reference::new(comp.dump(), seed);
+    let mut events = input.iter().peekable();
+
+    for step in 0..STEPS {
+        while let Some((_, key, pressed)) = events.next_if(|e| e.0 <= step) {
+            if *pressed { comp.press(*key) } else { comp.release(*key) }
+            model.keys[*key as usize] = *pressed;
+        }
+
+        let at = model.pc;
+        let opcode = (model.memory[at as usize % 4096] as u16) << 8 | model.memory[(at as usize + 1) % 4096] as u16;
+        let expected = model.step();
+        let actual = comp.tick();
+
+        match (expected, actual) {
+            // past this point the reference can't say what should happen
+            (Err(Stop::Unsupported), _) => return Ok(()),
+            (Err(Stop::Error), Err(_)) => {
+                prop_assert_eq!(comp.cpu().pc, model.pc, "PC after {:04X} failed at {:#05x}", opcode, at);
+                return Ok(());
+            },
+            (Ok(step), Ok(outcome)) => {
+                let waiting = outcome == StepOutcome::WaitingForKey;
+                prop_assert_eq!(waiting, step == Step::WaitingForKey, "{:04X} at {:#05x}: {:?}", opcode, at, outcome);
+                compare(&comp, &model, step == Step::Drew, at, opcode)?;
+            },
+            (expected, actual) => {
+                return Err(TestCaseError::fail(format!("{:04X} at {:#05x}: reference {:?}, computer {:?}", opcode, at, expected, actual)));
+            },
+        }
+
+        if (step + 1) % CYCLES_PER_FRAME == 0 {
+            comp.tick_timers();
+            model.tick_timers();
+        }
+    }
+
+    Ok(())
+}
+
+proptest! {
+    #[test]
+    fn random_bytes_keep_invariants(rom in prop::collection::vec(any::<u8>(), 0..1024), quirks in quirks(), policy in policy(), seed in any::<u64>(), input in input()) {
+        check_invariants(rom, quirks, policy, seed, &input)?;
+    }
+
+    #[test]
+    fn random_programs_keep_invariants(rom in rom(), quirks in quirks(), policy in policy(), seed in any::<u64>(), input in input()) {
+        check_invariants(bytes(&rom), quirks, policy, seed, &input)?;
+    }
+
+    #[test]
+    fn matches_reference(rom in rom(), seed in any::<u64>(), input in input()) {
+        check_against_reference(bytes(&rom), seed, &input)?;
+    }
+}
@@ -0,0 +1,194 @@
+// drives Runner through a scripted frontend: each poll hands out the next
+// frame's input, and it quits once the script runs out
+use std::collections::VecDeque;
+use chip8_rs::computer::Computer;
+use chip8_rs::display::Display;
+use chip8_rs::frontend::{Frontend, Input, Runner};
+use chip8_rs::movie::{InputEvent, Movie, Replay};
+use chip8_rs::quirks::Quirks;
+
+// V0 = key, then count up in V1 forever
+const COUNTER: &[u8] = &[0xF0, 0x0A, 0x71, 0x01, 0x12, 0x02];
+
+#[derive(Default)]
+struct Script {
+    frames: VecDeque<Vec<Input>>,
+    sample_rate: Option<u32>,
+    presented: usize,
+    samples: Vec<usize>,
+    messages: Vec<String>,
+}
+
+impl Script {
+    fn new(frames: Vec<Vec<Input>>) -> Self {
+        Self { frames: frames.into(), ..Default::default() }
+    }
+}
+
+impl Frontend for Script {
+    fn present(&mut self, _display: &Display) {
+        self.presented += 1;
+    }
+
+    fn poll_input(&mut self) -> Vec<Input> {
+        self.frames.pop_front().unwrap_or(vec![Input::Quit])
+    }
+
+    fn sample_rate(&self) -> Option<u32> {
+        self.sample_rate
+    }
+
+    fn push_audio(&mut self, samples: &[f32]) {
+        self.samples.push(samples.len());
+    }
+
+    fn message(&mut self, text: &str) {
+        self.messages.push(text.to_string());
+    }
+}
+
+fn runner(rom: &[u8]) -> Runner {
+    let mut runner = Runner::new(Computer::with_seed(rom.to_vec(), Quirks::default(), 0), 10);
+    runner.set_frame_rate(None);
+    runner
+}
+
+#[test]
+fn runs_a_frame_per_poll_until_quit() {
+    let mut runner = runner(COUNTER);
+    let mut script = Script::new(vec![vec![]; 5]);
+    script.sample_rate = Some(48000);
+
+    runner.run(&mut script);
+
+    assert_eq!(runner.computer().frames(), 5);
+    assert_eq!(script.presented, 5);
+    assert_eq!(script.samples, vec![800; 5]);
+}
+
+#[test]
+fn passes_keys_to_the_computer() {
+    let mut runner = runner(COUNTER);
+    let mut script = Script::new(vec![vec![], vec![Input::Press(7)], vec![Input::Release(7)], vec![]]);
+
+    runner.run(&mut script);
+
+    let cpu = runner.computer().cpu();
+    assert_eq!(cpu.v[0], 7);
+    assert!(cpu.v[1] > 0);
+}
+
+#[test]
+fn applies_a_replay() {
+    let movie = Movie { events: vec![InputEvent { frame: 2, key: 0xA, pressed: true }], ..Default::default() };
+    let mut runner = runner(COUNTER);
+    runner.set_replay(Replay::new(&movie));
+
+    runner.run(&mut Script::new(vec![vec![]; 4]));
+
+    assert_eq!(runner.computer().cpu().v[0], 0xA);
+}
+
+#[test]
+fn saves_and_loads_slots() {
+    let mut runner = runner(COUNTER);
+    let mut script = Script::new(vec![
+        vec![Input::Press(1)],
+        vec![Input::Slot(2), Input::Save],
+        vec![],
+        vec![],
+        vec![Input::Load, Input::Quit],
+        vec![],
+    ]);
+
+    runner.run(&mut script);
+
+    // saved before the second frame ran
+    assert_eq!(runner.computer().frames(), 1);
+    assert_eq!(script.messages, ["save slot 3", "saved to slot 3", "loaded slot 3"]);
+}
+
+#[test]
+fn loading_an_empty_slot_changes_nothing() {
+    let mut runner = runner(COUNTER);
+    let mut script = Script::new(vec![vec![], vec![Input::Load]]);
+
+    runner.run(&mut script);
+
+    assert_eq!(runner.computer().frames(), 2);
+    assert_eq!(script.messages, ["slot 1 is empty"]);
+}
+
+#[test]
+fn rewinds_while_held() {
+    let mut runner = runner(COUNTER);
+    let mut frames = vec![vec![Input::Press(1)]];
+    frames.extend(vec![vec![]; 9]);
+    frames.push(vec![Input::Rewind(true)]);
+    frames.extend(vec![vec![]; 3]);
+    frames.push(vec![Input::Rewind(false)]);
+
+    runner.run(&mut Script::new(frames));
+
+    // 10 frames, back 4 while held, then one more
+    assert_eq!(runner.computer().frames(), 7);
+}
+
+#[test]
+fn stops_running_after_an_error() {
+    // 5001 isn't an instruction
+    let mut runner = runner(&[0x60, 0x01, 0x50, 0x01]);
+    let mut script = Script::new(vec![vec![]; 3]);
+
+    runner.run(&mut script);
+
+    assert!(runner.is_halted());
+    assert_eq!(runner.computer().frames(), 0);
+    assert_eq!(script.messages.len(), 1);
+    // still presented, so the screen stays up
+    assert_eq!(script.presented, 3);
+}
+
+// logs the lowest held key to 300 onwards, one byte per loop
+const KEY_LOG: &[u8] = &[0xA3, 0x00, 0x61, 0x01, 0xF0, 0x0A, 0xF0, 0x55, 0xF1, 0x1E, 0x12, 0x04];
+
+fn key_movie() -> Movie {
+    let events = (0..12).flat_map(|n| [
+        InputEvent { frame: 2 * n + 1, key: n as u8, pressed: true },
+        InputEvent { frame: 2 * n + 2, key: n as u8, pressed: false },
+    ]);
+    Movie { events: events.collect(), ..Default::default() }
+}
+
+fn replayed(frames: Vec<Vec<Input>>) -> Runner {
+    let mut runner = runner(KEY_LOG);
+    runner.set_replay(Replay::new(&key_movie()));
+    runner.run(&mut Script::new(frames));
+    runner
+}
+
+#[test]
+fn loading_a_slot_rewinds_the_replay_too() {
+    let expected = replayed(vec![vec![]; 30]);
+
+    let mut frames = vec![vec![]; 40];
+    frames[5] = vec![Input::Save];
+    frames[15] = vec![Input::Load];
+    let runner = replayed(frames);
+
+    assert_eq!(runner.computer().frames(), 30);
+    assert_eq!(runner.computer().dump(), expected.computer().dump());
+}
+
+#[test]
+fn rewinding_rewinds_the_replay_too() {
+    let expected = replayed(vec![vec![]; 30]);
+
+    let mut frames = vec![vec![]; 40];
+    frames[15] = vec![Input::Rewind(true)];
+    frames[20] = vec![Input::Rewind(false)];
+    let runner = replayed(frames);
+
+    assert_eq!(runner.computer().frames(), 30);
+    assert_eq!(runner.computer().dump(), expected.computer().dump());
+}
@@ -0,0 +1,132 @@
+// runs each bundled rom for a fixed number of frames and compares the screen
+// with the reference image in tests/golden/<rom>.pbm
+//
+//   cargo test --test golden                 check every rom
+//   cargo test --test golden -- maze         only roms whose name contains "maze"
+//   cargo test --test golden -- --bless      rewrite the references from the current output
+//
+// input for a rom, along with its seed, cpf and quirks, comes from
+// tests/golden/<rom>.movie when there is one
+use std::path::{Path, PathBuf};
+use std::{env, fs, process};
+use chip8_rs::computer::Computer;
+use chip8_rs::movie::{Movie, Replay};
+
+// rom, quirks profile, frames to run
+const CASES: &[(&str, &str, u64)] = &[
+    ("ibm", "default", 100),
+    ("maze", "default", 300),
+    ("picture", "default", 300),
+    ("life", "default", 3000),
+    ("sirpinski", "default", 3000),
+    ("particle", "default", 600),
+    ("bmp", "default", 300),
+    ("trip8", "default", 600),
+    ("stars", "xo", 600),
+    ("jumpxo", "xo", 300),
+];
+
+fn root() -> &'static Path {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+}
+
+fn run(rom: &str, quirks: &str, frames: u64) -> Result<Computer, String> {
+    let data = fs::read(root().join(format!("{}.ch8", rom))).map_err(|e| format!("{}.ch8: {}", rom, e))?;
+
+    let movie_path = root().join("tests/golden").join(format!("{}.movie", rom));
+    let mut movie = match fs::read_to_string(&movie_path) {
+        Ok(text) => Movie::parse(&text)?,
+        Err(_) => Movie::default(),
+    };
+    movie.seed = movie.seed.or(Some(0));
+
+    let mut comp = movie.computer(data, quirks.parse()?);
+    let mut replay = Replay::new(&movie);
+    let cycles_per_frame = movie.cycles_per_frame.unwrap_or(10);
+
+    while comp.frames() < frames && !comp.is_halted() {
+        replay.apply(&mut comp);
+        comp.run_frame(cycles_per_frame).map_err(|e| format!("frame {}: {}", comp.frames(), e))?;
+    }
+
+    Ok(comp)
+}
+
+fn first_difference(expected: &str, actual: &str) -> String {
+    let expected: Vec<&str> = expected.lines().collect();
+    let actual: Vec<&str> = actual.lines().collect();
+    if expected.get(1) != actual.get(1) {
+        return format!("size changed from {} to {}", expected.get(1).unwrap_or(&"?"), actual.get(1).unwrap_or(&"?"));
+    }
+
+    let pixels = expected.iter().zip(&actual).skip(2)
+        .map(|(e, a)| e.chars().zip(a.chars()).filter(|(e, a)| e != a).count())
+        .sum::<usize>();
+    let row = expected.iter().zip(&actual).skip(2).position(|(e, a)| e != a).unwrap_or(0);
+
+    format!("{} pixels differ, first on row {}", pixels, row)
+}
+
+fn check(rom: &str, quirks: &str, frames: u64, bless: bool) -> Result<(), String> {
+    let actual = run(rom, quirks, frames)?.display().to_pbm();
+    let reference = root().join("tests/golden").join(format!("{}.pbm", rom));
+
+    if bless {
+        return fs::write(&reference, &actual).map_err(|e| format!("{}: {}", reference.display(), e));
+    }
+
+    let expected = fs::read_to_string(&reference).map_err(|e| format!("{}: {}, bless to create it", reference.display(), e))?;
+    if expected == actual {
+        return Ok(());
+    }
+
+    let out = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join(format!("{}.pbm", rom));
+    fs::write(&out, &actual).map_err(|e| format!("{}: {}", out.display(), e))?;
+    Err(format!("{}, output written to {}", first_difference(&expected, &actual), out.display()))
+}
+
+// fb.dat is an older dump of the IBM logo, one byte per pixel
+fn check_fb_dat() -> Result<(), String> {
+    let expected = fs::read(root().join("fb.dat")).map_err(|e| format!("fb.dat: {}", e))?;
+    let actual: Vec<u8> = run("ibm", "default", 100)?.display().dump().into_iter().map(|p| p as u8).collect();
+
+    match expected == actual {
+        true => Ok(()),
+        false => Err("screen differs from fb.dat".to_string()),
+    }
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let bless = args.iter().any(|a| a == "--bless");
+    let filters: Vec<&String> = args.iter().filter(|a| !a.starts_with('-')).collect();
+    let selected = |name: &str| filters.is_empty() || filters.iter().any(|f| name.contains(f.as_str()));
+
+    let mut results = Vec::new();
+    for (rom, quirks, frames) in CASES {
+        if selected(rom) {
+            results.push((rom.to_string(), check(rom, quirks, *frames, bless)));
+        }
+    }
+    if !bless && selected("fb.dat") {
+        results.push(("ibm (fb.dat)".to_string(), check_fb_dat()));
+    }
+
+    println!("\nrunning {} golden tests", results.len());
+    let mut failed = 0;
+    for (name, result) in &results {
+        match result {
+            Ok(()) if bless => println!("golden {} ... blessed", name),
+            Ok(()) => println!("golden {} ... ok", name),
+            Err(e) => {
+                println!("golden {} ... FAILED\n    {}", name, e);
+                failed += 1;
+            },
+        }
+    }
+
+    println!("\ngolden result: {}. {} passed; {} failed\n", if failed == 0 { "ok" } else { "FAILED" }, results.len() - failed, failed);
+    if failed > 0 {
+        process::exit(1);
+    }
+}
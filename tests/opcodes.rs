@@ -0,0 +1,344 @@
+// one case per behaviour of each standard CHIP-8 instruction: load a program at
+// 0x200, set up registers and memory, tick a few times and check the machine
+use std::collections::BTreeSet;
+use chip8_rs::computer::{Computer, StepOutcome};
+use chip8_rs::error::EmulatorError;
+use chip8_rs::quirks::Quirks;
+use chip8_rs::rng::Sequence;
+
+// what RND draws from, in order
+const RANDOM: &[u8] = &[0xA5, 0x3C];
+
+enum Set {
+    V(usize, u8),
+    Regs(&'static [u8]),    // V0 onwards
+    I(u16),
+    Dt(u8),
+    Key(u8),
+    Mem(usize, &'static [u8]),
+    Calls(usize),           // fills the stack with this many return addresses
+}
+
+enum Expect {
+    V(usize, u8),
+    Regs(&'static [u8]),
+    I(u16),
+    Pc(u16),
+    Sp(u8),
+    Stack(&'static [u16]),
+    Dt(u8),
+    St(u8),
+    Mem(usize, &'static [u8]),
+    Pixel(usize, usize, bool),
+    Lit(usize),             // number of pixels switched on
+    Outcome(StepOutcome),   // of the last tick
+    Error(EmulatorError),
+}
+
+struct Case {
+    name: &'static str,
+    quirks: fn() -> Quirks,
+    program: &'static [u16],
+    setup: &'static [Set],
+    steps: usize,
+    expect: &'static [Expect],
+}
+
+const CASE: Case = Case { name: "", quirks: Quirks::default, program: &[], setup: &[], steps: 1, expect: &[] };
+
+// font sprite for 0 is F0 90 90 90 F0, 14 pixels
+const CASES: &[Case] = &[
+    // 0nnn, 00E0, 00EE
+    Case { name: "0nnn machine code routines are not supported", program: &[0x0123],
+        expect: &[Expect::Error(EmulatorError::UnknownOpcode { pc: 0x200, opcode: 0x0123 }), Expect::Pc(0x200)], ..CASE },
+    Case { name: "00E0 clears the screen", program: &[0xD015, 0x00E0], steps: 2,
+        expect: &[Expect::Lit(0), Expect::Pc(0x204)], ..CASE },
+    Case { name: "00EE returns past the call", program: &[0x2206, 0x0000, 0x0000, 0x00EE], steps: 2,
+        expect: &[Expect::Pc(0x202), Expect::Sp(0), Expect::Stack(&[])], ..CASE },
+    Case { name: "00EE with an empty stack underflows", program: &[0x00EE],
+        expect: &[Expect::Error(EmulatorError::StackUnderflow), Expect::Pc(0x200)], ..CASE },
+
+    // 1nnn, 2nnn
+    Case { name: "1nnn jumps", program: &[0x1ABC], expect: &[Expect::Pc(0xABC), Expect::Sp(0)], ..CASE },
+    Case { name: "2nnn pushes the return address", program: &[0x2ABC],
+        expect: &[Expect::Pc(0xABC), Expect::Sp(1), Expect::Stack(&[0x202])], ..CASE },
+    Case { name: "2nnn nests", program: &[0x2204, 0x0000, 0x2208], steps: 2,
+        expect: &[Expect::Pc(0x208), Expect::Stack(&[0x202, 0x206])], ..CASE },
+    Case { name: "2nnn with a full stack overflows", program: &[0x2ABC], setup: &[Set::Calls(16)],
+        expect: &[Expect::Error(EmulatorError::StackOverflow), Expect::Pc(0x200), Expect::Sp(16)], ..CASE },
+
+    // 3xkk, 4xkk, 5xy0, 9xy0
+    Case { name: "3xkk skips when equal", program: &[0x3342], setup: &[Set::V(3, 0x42)], expect: &[Expect::Pc(0x204)], ..CASE },
+    Case { name: "3xkk doesn't skip when different", program: &[0x3342], setup: &[Set::V(3, 0x41)], expect: &[Expect::Pc(0x202)], ..CASE },
+    Case { name: "3xkk skips all of a long load on XO-CHIP", quirks: Quirks::xo_chip, program: &[0x3000, 0xF000, 0x1234], expect: &[Expect::Pc(0x206)], ..CASE },
+    Case { name: "3xkk skips a single word elsewhere", program: &[0x3000, 0xF000, 0x1234], expect: &[Expect::Pc(0x204)], ..CASE },
+    Case { name: "4xkk skips when different", program: &[0x4342], setup: &[Set::V(3, 0x41)], expect: &[Expect::Pc(0x204)], ..CASE },
+    Case { name: "4xkk doesn't skip when equal", program: &[0x4342], setup: &[Set::V(3, 0x42)], expect: &[Expect::Pc(0x202)], ..CASE },
+    Case { name: "5xy0 skips when equal", program: &[0x5120], setup: &[Set::V(1, 7), Set::V(2, 7)], expect: &[Expect::Pc(0x204)], ..CASE },
+    Case { name: "5xy0 doesn't skip when different", program: &[0x5120], setup: &[Set::V(1, 7), Set::V(2, 8)], expect: &[Expect::Pc(0x202)], ..CASE },
+    Case { name: "9xy0 skips when different", program: &[0x9120], setup: &[Set::V(1, 7), Set::V(2, 8)], expect: &[Expect::Pc(0x204)], ..CASE },
+    Case { name: "9xy0 doesn't skip when equal", program: &[0x9120], setup: &[Set::V(1, 7), Set::V(2, 7)], expect: &[Expect::Pc(0x202)], ..CASE },
+
+    // 6xkk, 7xkk
+    Case { name: "6xkk loads", program: &[0x6A7F], expect: &[Expect::V(0xA, 0x7F)], ..CASE },
+    Case { name: "7xkk adds", program: &[0x7A02], setup: &[Set::V(0xA, 0x10)], expect: &[Expect::V(0xA, 0x12)], ..CASE },
+    Case { name: "7xkk wraps without touching VF", program: &[0x7A02], setup: &[Set::V(0xA, 0xFF), Set::V(0xF, 0x55)],
+        expect: &[Expect::V(0xA, 0x01), Expect::V(0xF, 0x55)], ..CASE },
+
+    // 8xy0-8xy3
+    Case { name: "8xy0 copies", program: &[0x8120], setup: &[Set::V(2, 0x99)], expect: &[Expect::V(1, 0x99), Expect::V(2, 0x99)], ..CASE },
+    Case { name: "8xy1 ors", program: &[0x8121], setup: &[Set::V(1, 0xF0), Set::V(2, 0x0F), Set::V(0xF, 5)],
+        expect: &[Expect::V(1, 0xFF), Expect::V(0xF, 5)], ..CASE },
+    Case { name: "8xy1 clears VF on the VIP", quirks: Quirks::cosmac_vip, program: &[0x8121], setup: &[Set::V(1, 0xF0), Set::V(2, 0x0F), Set::V(0xF, 5)],
+        expect: &[Expect::V(1, 0xFF), Expect::V(0xF, 0)], ..CASE },
+    Case { name: "8xy2 ands", program: &[0x8122], setup: &[Set::V(1, 0xF3), Set::V(2, 0x3F), Set::V(0xF, 5)],
+        expect: &[Expect::V(1, 0x33), Expect::V(0xF, 5)], ..CASE },
+    Case { name: "8xy2 clears VF on the VIP", quirks: Quirks::cosmac_vip, program: &[0x8122], setup: &[Set::V(1, 0xF3), Set::V(2, 0x3F), Set::V(0xF, 5)],
+        expect: &[Expect::V(1, 0x33), Expect::V(0xF, 0)], ..CASE },
+    Case { name: "8xy3 xors", program: &[0x8123], setup: &[Set::V(1, 0xFF), Set::V(2, 0x0F), Set::V(0xF, 5)],
+        expect: &[Expect::V(1, 0xF0), Expect::V(0xF, 5)], ..CASE },
+    Case { name: "8xy3 clears VF on the VIP", quirks: Quirks::cosmac_vip, program: &[0x8123], setup: &[Set::V(1, 0xFF), Set::V(2, 0x0F), Set::V(0xF, 5)],
+        expect: &[Expect::V(1, 0xF0), Expect::V(0xF, 0)], ..CASE },
+
+    // 8xy4, 8xy5, 8xy7
+    Case { name: "8xy4 adds without carry", program: &[0x8124], setup: &[Set::V(1, 0x10), Set::V(2, 0x20), Set::V(0xF, 1)],
+        expect: &[Expect::V(1, 0x30), Expect::V(0xF, 0)], ..CASE },
+    Case { name: "8xy4 carries", program: &[0x8124], setup: &[Set::V(1, 0xFF), Set::V(2, 0x02)],
+        expect: &[Expect::V(1, 0x01), Expect::V(0xF, 1)], ..CASE },
+    Case { name: "8xy4 carries from exactly 0x100", program: &[0x8124], setup: &[Set::V(1, 0x80), Set::V(2, 0x80)],
+        expect: &[Expect::V(1, 0x00), Expect::V(0xF, 1)], ..CASE },
+    Case { name: "8xy4 into VF keeps the carry, not the sum", program: &[0x8F14], setup: &[Set::V(0xF, 0xFF), Set::V(1, 0x02)],
+        expect: &[Expect::V(0xF, 1)], ..CASE },
+    Case { name: "8xy4 reads VF as an operand", program: &[0x81F4], setup: &[Set::V(1, 0x10), Set::V(0xF, 0x20)],
+        expect: &[Expect::V(1, 0x30), Expect::V(0xF, 0)], ..CASE },
+    Case { name: "8xy5 subtracts", program: &[0x8125], setup: &[Set::V(1, 0x30), Set::V(2, 0x10)],
+        expect: &[Expect::V(1, 0x20), Expect::V(0xF, 1)], ..CASE },
+    Case { name: "8xy5 borrows", program: &[0x8125], setup: &[Set::V(1, 0x10), Set::V(2, 0x30), Set::V(0xF, 1)],
+        expect: &[Expect::V(1, 0xE0), Expect::V(0xF, 0)], ..CASE },
+    Case { name: "8xy5 of equal values doesn't borrow", program: &[0x8125], setup: &[Set::V(1, 5), Set::V(2, 5)],
+        expect: &[Expect::V(1, 0), Expect::V(0xF, 1)], ..CASE },
+    Case { name: "8xy5 into VF keeps the flag", program: &[0x8F15], setup: &[Set::V(0xF, 0x10), Set::V(1, 0x30)],
+        expect: &[Expect::V(0xF, 0)], ..CASE },
+    Case { name: "8xy7 subtracts Vx from Vy", program: &[0x8127], setup: &[Set::V(1, 0x10), Set::V(2, 0x30)],
+        expect: &[Expect::V(1, 0x20), Expect::V(0xF, 1)], ..CASE },
+    Case { name: "8xy7 borrows", program: &[0x8127], setup: &[Set::V(1, 0x30), Set::V(2, 0x10), Set::V(0xF, 1)],
+        expect: &[Expect::V(1, 0xE0), Expect::V(0xF, 0)], ..CASE },
+
+    // 8xy6, 8xyE
+    Case { name: "8xy6 shifts Vx right", program: &[0x8126], setup: &[Set::V(1, 0x05), Set::V(2, 0xF0)],
+        expect: &[Expect::V(1, 0x02), Expect::V(2, 0xF0), Expect::V(0xF, 1)], ..CASE },
+    Case { name: "8xy6 shifts Vy into Vx on the VIP", quirks: Quirks::cosmac_vip, program: &[0x8126], setup: &[Set::V(1, 0x05), Set::V(2, 0x04)],
+        expect: &[Expect::V(1, 0x02), Expect::V(2, 0x04), Expect::V(0xF, 0)], ..CASE },
+    Case { name: "8xy6 into VF keeps the flag", program: &[0x8F06], setup: &[Set::V(0xF, 0x03)], expect: &[Expect::V(0xF, 1)], ..CASE },
+    Case { name: "8xyE shifts Vx left", program: &[0x812E], setup: &[Set::V(1, 0x81), Set::V(2, 0x01)],
+        expect: &[Expect::V(1, 0x02), Expect::V(2, 0x01), Expect::V(0xF, 1)], ..CASE },
+    Case { name: "8xyE shifts Vy into Vx on the VIP", quirks: Quirks::cosmac_vip, program: &[0x812E], setup: &[Set::V(1, 0x81), Set::V(2, 0x40)],
+        expect: &[Expect::V(1, 0x80), Expect::V(0xF, 0)], ..CASE },
+    Case { name: "8xy6 shifts Vy into Vx on XO-CHIP", quirks: Quirks::xo_chip, program: &[0x8126], setup: &[Set::V(1, 0x05), Set::V(2, 0x04)],
+        expect: &[Expect::V(1, 0x02), Expect::V(2, 0x04), Expect::V(0xF, 0)], ..CASE },
+    Case { name: "8xyE shifts Vy into Vx on XO-CHIP", quirks: Quirks::xo_chip, program: &[0x812E], setup: &[Set::V(1, 0x81), Set::V(2, 0x40)],
+        expect: &[Expect::V(1, 0x80), Expect::V(2, 0x40), Expect::V(0xF, 0)], ..CASE },
+    Case { name: "8xyE into VF keeps the flag", program: &[0x8F0E], setup: &[Set::V(0xF, 0x40)], expect: &[Expect::V(0xF, 0)], ..CASE },
+
+    // Annn, Bnnn, Cxkk
+    Case { name: "Annn loads I", program: &[0xA123], expect: &[Expect::I(0x123)], ..CASE },
+    Case { name: "Bnnn jumps to nnn + V0", program: &[0xB300], setup: &[Set::V(0, 0x10), Set::V(3, 0x20)], expect: &[Expect::Pc(0x310)], ..CASE },
+    Case { name: "Bxnn jumps to xnn + Vx on the CHIP-48", quirks: Quirks::chip48, program: &[0xB300], setup: &[Set::V(0, 0x10), Set::V(3, 0x20)],
+        expect: &[Expect::Pc(0x320)], ..CASE },
+    Case { name: "Cxkk masks a random byte", program: &[0xC30F, 0xC4F0], steps: 2, expect: &[Expect::V(3, 0x05), Expect::V(4, 0x30)], ..CASE },
+    Case { name: "Cxkk with a zero mask", program: &[0xC300], setup: &[Set::V(3, 0x77)], expect: &[Expect::V(3, 0)], ..CASE },
+
+    // Dxyn
+    Case { name: "Dxyn draws a sprite", program: &[0xD015],
+        expect: &[Expect::Lit(14), Expect::Pixel(0, 0, true), Expect::Pixel(1, 1, false), Expect::Pixel(3, 4, true), Expect::V(0xF, 0)], ..CASE },
+    Case { name: "Dxyn sets VF when it erases pixels", program: &[0xD015, 0xD015], steps: 2, expect: &[Expect::Lit(0), Expect::V(0xF, 1)], ..CASE },
+    Case { name: "Dxyn sets VF on a partial overlap", program: &[0xD015, 0xA005, 0xD015], steps: 3, expect: &[Expect::V(0xF, 1)], ..CASE },
+    Case { name: "Dxyn clears VF without a collision", program: &[0xD015, 0x6108, 0xD015], setup: &[Set::V(0xF, 1)], steps: 3,
+        expect: &[Expect::Lit(28), Expect::V(0xF, 0)], ..CASE },
+    Case { name: "Dxyn reads VF as a coordinate", program: &[0xDF15], setup: &[Set::V(0xF, 8)],
+        expect: &[Expect::Pixel(8, 0, true), Expect::Pixel(0, 0, false), Expect::V(0xF, 0)], ..CASE },
+    Case { name: "Dxyn clips at the right edge", program: &[0xD015], setup: &[Set::V(0, 62)],
+        expect: &[Expect::Lit(7), Expect::Pixel(62, 0, true), Expect::Pixel(0, 0, false)], ..CASE },
+    Case { name: "Dxyn clips at the bottom edge", program: &[0xD015], setup: &[Set::V(1, 30)],
+        expect: &[Expect::Lit(6), Expect::Pixel(0, 30, true), Expect::Pixel(0, 0, false)], ..CASE },
+    Case { name: "Dxyn wraps the starting position", program: &[0xD015], setup: &[Set::V(0, 66), Set::V(1, 33)],
+        expect: &[Expect::Lit(14), Expect::Pixel(2, 1, true)], ..CASE },
+    Case { name: "Dxyn wraps sprites on XO-CHIP", quirks: Quirks::xo_chip, program: &[0xD015], setup: &[Set::V(0, 62)],
+        expect: &[Expect::Lit(14), Expect::Pixel(62, 0, true), Expect::Pixel(1, 0, true)], ..CASE },
+    Case { name: "Dxyn waits for the next frame on the VIP", quirks: Quirks::cosmac_vip, program: &[0xD015, 0xD015], steps: 2,
+        expect: &[Expect::Outcome(StepOutcome::WaitingForVblank), Expect::Pc(0x202), Expect::Lit(14)], ..CASE },
+
+    // Ex9E, ExA1
+    Case { name: "Ex9E skips when the key is down", program: &[0xE19E], setup: &[Set::V(1, 5), Set::Key(5)], expect: &[Expect::Pc(0x204)], ..CASE },
+    Case { name: "Ex9E doesn't skip when the key is up", program: &[0xE19E], setup: &[Set::V(1, 5), Set::Key(6)], expect: &[Expect::Pc(0x202)], ..CASE },
+    Case { name: "Ex9E only looks at the low nibble", program: &[0xE19E], setup: &[Set::V(1, 0x15), Set::Key(5)], expect: &[Expect::Pc(0x204)], ..CASE },
+    Case { name: "ExA1 skips when the key is up", program: &[0xE1A1], setup: &[Set::V(1, 5)], expect: &[Expect::Pc(0x204)], ..CASE },
+    Case { name: "ExA1 doesn't skip when the key is down", program: &[0xE1A1], setup: &[Set::V(1, 5), Set::Key(5)], expect: &[Expect::Pc(0x202)], ..CASE },
+
+    // Fx07, Fx0A, Fx15, Fx18
+    Case { name: "Fx07 reads the delay timer", program: &[0xF207], setup: &[Set::Dt(0x33)], expect: &[Expect::V(2, 0x33)], ..CASE },
+    Case { name: "Fx0A waits for a key", program: &[0xF20A], setup: &[Set::V(2, 0x99)],
+        expect: &[Expect::Outcome(StepOutcome::WaitingForKey), Expect::Pc(0x200), Expect::V(2, 0x99)], ..CASE },
+    Case { name: "Fx0A takes a held key", program: &[0xF20A], setup: &[Set::Key(7)], expect: &[Expect::V(2, 7), Expect::Pc(0x202)], ..CASE },
+    Case { name: "Fx15 sets the delay timer", program: &[0xF215], setup: &[Set::V(2, 0x40)], expect: &[Expect::Dt(0x40), Expect::St(0)], ..CASE },
+    Case { name: "Fx18 sets the sound timer", program: &[0xF218], setup: &[Set::V(2, 0x40)], expect: &[Expect::St(0x40), Expect::Dt(0)], ..CASE },
+
+    // Fx1E, Fx29
+    Case { name: "Fx1E adds to I", program: &[0xF11E], setup: &[Set::I(0x100), Set::V(1, 0x20)], expect: &[Expect::I(0x120)], ..CASE },
+    Case { name: "Fx1E past 0xFFF doesn't touch VF", program: &[0xF11E], setup: &[Set::I(0x0FFF), Set::V(1, 1), Set::V(0xF, 0x55)],
+        expect: &[Expect::I(0x1000), Expect::V(0xF, 0x55)], ..CASE },
+    Case { name: "Fx29 points I at a font sprite", program: &[0xF129], setup: &[Set::V(1, 0x0A)], expect: &[Expect::I(50)], ..CASE },
+    Case { name: "Fx29 only looks at the low nibble", program: &[0xF129], setup: &[Set::V(1, 0x1A)], expect: &[Expect::I(50)], ..CASE },
+
+    // Fx33
+    Case { name: "Fx33 of 255", program: &[0xF133], setup: &[Set::V(1, 255), Set::I(0x300)], expect: &[Expect::Mem(0x300, &[2, 5, 5]), Expect::I(0x300)], ..CASE },
+    Case { name: "Fx33 of 107", program: &[0xF133], setup: &[Set::V(1, 107), Set::I(0x300)], expect: &[Expect::Mem(0x300, &[1, 0, 7])], ..CASE },
+    Case { name: "Fx33 of 0", program: &[0xF133], setup: &[Set::I(0x300), Set::Mem(0x300, &[9, 9, 9])], expect: &[Expect::Mem(0x300, &[0, 0, 0])], ..CASE },
+
+    // Fx55, Fx65
+    Case { name: "Fx55 with x=F stores every register", program: &[0xFF55],
+        setup: &[Set::Regs(&[0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15]), Set::I(0x300)],
+        expect: &[Expect::Mem(0x300, &[0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15]), Expect::I(0x300)], ..CASE },
+    Case { name: "Fx55 stops at Vx", program: &[0xF155], setup: &[Set::Regs(&[1, 2, 3]), Set::I(0x300), Set::Mem(0x300, &[9, 9, 9])],
+        expect: &[Expect::Mem(0x300, &[1, 2, 9])], ..CASE },
+    Case { name: "Fx55 increments I on the VIP", quirks: Quirks::cosmac_vip, program: &[0xFF55], setup: &[Set::I(0x300)], expect: &[Expect::I(0x310)], ..CASE },
+    Case { name: "Fx55 increments I by x on CHIP-48", quirks: Quirks::chip48, program: &[0xFF55], setup: &[Set::I(0x300)], expect: &[Expect::I(0x30F)], ..CASE },
+    Case { name: "Fx55 leaves I alone on SUPER-CHIP", quirks: Quirks::super_chip, program: &[0xFF55], setup: &[Set::I(0x300)], expect: &[Expect::I(0x300)], ..CASE },
+    Case { name: "Fx55 wraps around the end of memory", program: &[0xF155], setup: &[Set::Regs(&[0xAA, 0xBB]), Set::I(0x0FFF)],
+        expect: &[Expect::Mem(0x0FFF, &[0xAA]), Expect::Mem(0x000, &[0xBB])], ..CASE },
+    Case { name: "Fx65 with x=F loads every register", program: &[0xFF65],
+        setup: &[Set::I(0x300), Set::Mem(0x300, &[15, 14, 13, 12, 11, 10, 9, 8, 7, 6, 5, 4, 3, 2, 1, 0])],
+        expect: &[Expect::Regs(&[15, 14, 13, 12, 11, 10, 9, 8, 7, 6, 5, 4, 3, 2, 1, 0]), Expect::I(0x300)], ..CASE },
+    Case { name: "Fx65 stops at Vx", program: &[0xF065], setup: &[Set::Regs(&[0, 0x77]), Set::I(0x300), Set::Mem(0x300, &[1, 2])],
+        expect: &[Expect::Regs(&[1, 0x77])], ..CASE },
+    Case { name: "Fx65 increments I on the VIP", quirks: Quirks::cosmac_vip, program: &[0xF265], setup: &[Set::I(0x300)], expect: &[Expect::I(0x303)], ..CASE },
+    Case { name: "Fx65 increments I by x on CHIP-48", quirks: Quirks::chip48, program: &[0xF265], setup: &[Set::I(0x300)], expect: &[Expect::I(0x302)], ..CASE },
+];
+
+fn setup(comp: &mut Computer, set: &Set) {
+    match set {
+        Set::V(x, value) => comp.cpu_mut().v[*x] = *value,
+        Set::Regs(values) => comp.cpu_mut().v[..values.len()].copy_from_slice(values),
+        Set::I(i) => comp.cpu_mut().i = *i,
+        Set::Dt(dt) => comp.cpu_mut().dt = *dt,
+        Set::Key(key) => comp.press(*key),
+        Set::Mem(addr, bytes) => {
+            for (n, byte) in bytes.iter().enumerate() {
+                comp.memory_mut().write(addr + n, *byte).unwrap();
+            }
+        },
+        Set::Calls(n) => {
+            for d in 0..*n {
+                comp.cpu_mut().stack_push(0x300 + 2 * d as u16).unwrap();
+            }
+        },
+    }
+}
+
+// describes the first expectation that doesn't hold
+fn check(comp: &Computer, last: &Result<StepOutcome, EmulatorError>, expect: &Expect) -> Option<String> {
+    let cpu = comp.cpu();
+    let display = comp.display();
+    let differs = |what: String, expected: String, actual: String| (expected != actual).then(|| format!("{}: expected {}, got {}", what, expected, actual));
+
+    match expect {
+        Expect::V(x, value) => differs(format!("V{:X}", x), format!("{:#04x}", value), format!("{:#04x}", cpu.v[*x])),
+        Expect::Regs(values) => differs("registers".to_string(), format!("{:?}", values), format!("{:?}", &cpu.v[..values.len()])),
+        Expect::I(i) => differs("I".to_string(), format!("{:#06x}", i), format!("{:#06x}", cpu.i)),
+        Expect::Pc(pc) => differs("PC".to_string(), format!("{:#06x}", pc), format!("{:#06x}", cpu.pc)),
+        Expect::Sp(sp) => differs("SP".to_string(), sp.to_string(), cpu.sp.to_string()),
+        Expect::Stack(stack) => differs("stack".to_string(), format!("{:x?}", stack), format!("{:x?}", cpu.stack())),
+        Expect::Dt(dt) => differs("DT".to_string(), dt.to_string(), cpu.dt.to_string()),
+        Expect::St(st) => differs("ST".to_string(), st.to_string(), cpu.st.to_string()),
+        Expect::Mem(addr, bytes) => {
+            let actual: Vec<u8> = (0..bytes.len()).map(|n| comp.memory().read(addr + n).unwrap()).collect();
+            differs(format!("memory at {:#05x}", addr), format!("{:?}", bytes), format!("{:?}", actual))
+        },
+        Expect::Pixel(x, y, lit) => differs(format!("pixel ({}, {})", x, y), lit.to_string(), display.get(*x, *y).to_string()),
+        Expect::Lit(count) => {
+            let lit = display.dump().iter().filter(|p| **p).count();
+            differs("lit pixels".to_string(), count.to_string(), lit.to_string())
+        },
+        Expect::Outcome(outcome) => differs("outcome".to_string(), format!("{:?}", Ok::<_, EmulatorError>(*outcome)), format!("{:?}", last)),
+        Expect::Error(e) => differs("result".to_string(), format!("{:?}", Err::<StepOutcome, _>(*e)), format!("{:?}", last)),
+    }
+}
+
+fn run(case: &Case) -> Vec<String> {
+    let program: Vec<u8> = case.program.iter().flat_map(|op| op.to_be_bytes()).collect();
+    let mut comp = Computer::with_random_source(program, (case.quirks)(), Box::new(Sequence::new(RANDOM.to_vec())));
+    for set in case.setup {
+        setup(&mut comp, set);
+    }
+
+    let mut last = Ok(StepOutcome::Exited);
+    for _ in 0..case.steps {
+        last = comp.tick();
+        if last.is_err() {
+            break;
+        }
+    }
+
+    let mut failures: Vec<String> = case.expect.iter().filter_map(|e| check(&comp, &last, e)).collect();
+    let expects_error = case.expect.iter().any(|e| matches!(e, Expect::Error(_)));
+    if let (Err(e), false) = (&last, expects_error) {
+        failures.push(format!("unexpected error: {}", e));
+    }
+
+    failures.into_iter().map(|f| format!("{}: {}", case.name, f)).collect()
+}
+
+#[test]
+fn opcodes() {
+    let failures: Vec<String> = CASES.iter().flat_map(run).collect();
+    assert!(failures.is_empty(), "{} failures:\n{}", failures.len(), failures.join("\n"));
+}
+
+// the 35 instructions of the original interpreter
+fn standard_opcode(op: u16) -> Option<&'static str> {
+    let name = match (op >> 12, op & 0x00FF, op & 0x000F) {
+        (0x0, _, _) if op == 0x00E0 => "00E0",
+        (0x0, _, _) if op == 0x00EE => "00EE",
+        (0x0, _, _) => "0nnn",
+        (0x1, _, _) => "1nnn",
+        (0x2, _, _) => "2nnn",
+        (0x3, _, _) => "3xkk",
+        (0x4, _, _) => "4xkk",
+        (0x5, _, 0x0) => "5xy0",
+        (0x6, _, _) => "6xkk",
+        (0x7, _, _) => "7xkk",
+        (0x8, _, 0x0) => "8xy0",
+        (0x8, _, 0x1) => "8xy1",
+        (0x8, _, 0x2) => "8xy2",
+        (0x8, _, 0x3) => "8xy3",
+        (0x8, _, 0x4) => "8xy4",
+        (0x8, _, 0x5) => "8xy5",
+        (0x8, _, 0x6) => "8xy6",
+        (0x8, _, 0x7) => "8xy7",
+        (0x8, _, 0xE) => "8xyE",
+        (0x9, _, 0x0) => "9xy0",
+        (0xA, _, _) => "Annn",
+        (0xB, _, _) => "Bnnn",
+        (0xC, _, _) => "Cxkk",
+        (0xD, _, _) => "Dxyn",
+        (0xE, 0x9E, _) => "Ex9E",
+        (0xE, 0xA1, _) => "ExA1",
+        (0xF, 0x07, _) => "Fx07",
+        (0xF, 0x0A, _) => "Fx0A",
+        (0xF, 0x15, _) => "Fx15",
+        (0xF, 0x18, _) => "Fx18",
+        (0xF, 0x1E, _) => "Fx1E",
+        (0xF, 0x29, _) => "Fx29",
+        (0xF, 0x33, _) => "Fx33",
+        (0xF, 0x55, _) => "Fx55",
+        (0xF, 0x65, _) => "Fx65",
+        _ => return None,
+    };
+    Some(name)
+}
+
+#[test]
+fn every_standard_opcode_has_a_case() {
+    let covered: BTreeSet<&str> = CASES.iter().flat_map(|case| case.program).filter_map(|op| standard_opcode(*op)).collect();
+    assert_eq!(covered.len(), 35, "covered: {:?}", covered);
+}
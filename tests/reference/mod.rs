@@ -0,0 +1,220 @@
+// a small CHIP-8 interpreter written straight from the original instruction
+// set, independent of Computer, for differential testing. It models the
+// default quirks, 4 KiB of memory and the 64x32 screen only; anything from
+// SCHIP or XO-CHIP stops it with Stop::Unsupported
+use chip8_rs::rng::{RandomSource, Xorshift};
+
+pub const WIDTH: usize = 64;
+pub const HEIGHT: usize = 32;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Step {
+    Ran,
+    Drew,
+    WaitingForKey,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stop {
+    // an invalid opcode, stack overflow/underflow or PC past the end of memory
+    Error,
+    Unsupported,
+}
+
+pub struct Reference {
+    pub v: [u8; 16],
+    pub i: u16,
+    pub pc: u16,
+    pub stack: Vec<u16>,
+    pub dt: u8,
+    pub st: u8,
+    pub memory: Vec<u8>,
+    pub screen: [[bool; WIDTH]; HEIGHT],
+    pub keys: [bool; 16],
+    rng: Xorshift,
+}
+
+impl Reference {
+    // `memory` is the initial contents, font included; RND uses the same
+    // generator as Computer::with_seed
+    pub fn new(memory: Vec<u8>, seed: u64) -> Self {
+        Self {
+            v: [0; 16],
+            i: 0,
+            pc: 0x200,
+            stack: Vec::new(),
+            dt: 0,
+            st: 0,
+            memory,
+            screen: [[false; WIDTH]; HEIGHT],
+            keys: [false; 16],
+            rng: Xorshift::new(seed),
+        }
+    }
+
+    pub fn tick_timers(&mut self) {
+        self.dt = self.dt.saturating_sub(1);
+        self.st = self.st.saturating_sub(1);
+    }
+
+    fn read(&self, addr: usize) -> u8 {
+        self.memory[addr % self.memory.len()]
+    }
+
+    fn write(&mut self, addr: usize, value: u8) {
+        let len = self.memory.len();
+        self.memory[addr % len] = value;
+    }
+
+    fn word(&self, addr: usize) -> u16 {
+        (self.read(addr) as u16) << 8 | self.read(addr + 1) as u16
+    }
+
+    // skips the next instruction; only XO-CHIP treats F000 nnnn as one
+    fn skip_if(&mut self, condition: bool) {
+        if condition {
+            self.pc += 2;
+        }
+    }
+
+    // runs one instruction; on a Stop, PC stays on it
+    pub fn step(&mut self) -> Result<Step, Stop> {
+        let pc = self.pc;
+        if pc as usize >= self.memory.len() {
+            return Err(Stop::Error);
+        }
+
+        let result = self.execute(self.word(pc as usize));
+        if result.is_err() {
+            self.pc = pc;
+        }
+        result
+    }
+
+    fn execute(&mut self, op: u16) -> Result<Step, Stop> {
+        let x = (op >> 8 & 0xF) as usize;
+        let y = (op >> 4 & 0xF) as usize;
+        let n = op & 0xF;
+        let kk = (op & 0xFF) as u8;
+        let nnn = op & 0xFFF;
+        let (vx, vy) = (self.v[x], self.v[y]);
+        self.pc += 2;
+
+        match op >> 12 {
+            0x0 => match op {
+                0x00E0 => {
+                    self.screen = [[false; WIDTH]; HEIGHT];
+                    return Ok(Step::Drew);
+                },
+                0x00EE => self.pc = self.stack.pop().ok_or(Stop::Error)?,
+                0x00C0..=0x00DF | 0x00FB..=0x00FF => return Err(Stop::Unsupported),
+                _ => return Err(Stop::Error),
+            },
+            0x1 => self.pc = nnn,
+            0x2 => {
+                if self.stack.len() == 16 {
+                    return Err(Stop::Error);
+                }
+                self.stack.push(self.pc);
+                self.pc = nnn;
+            },
+            0x3 => self.skip_if(vx == kk),
+            0x4 => self.skip_if(vx != kk),
+            0x5 => match n {
+                0x0 => self.skip_if(vx == vy),
+                0x2 | 0x3 => return Err(Stop::Unsupported),
+                _ => return Err(Stop::Error),
+            },
+            0x6 => self.v[x] = kk,
+            0x7 => self.v[x] = vx.wrapping_add(kk),
+            0x8 => {
+                // the flag is written last, so it wins when x is F
+                let (result, flag) = match n {
+                    0x0 => (vy, None),
+                    0x1 => (vx | vy, None),
+                    0x2 => (vx & vy, None),
+                    0x3 => (vx ^ vy, None),
+                    0x4 => (vx.wrapping_add(vy), Some((vx as u16 + vy as u16 > 0xFF) as u8)),
+                    0x5 => (vx.wrapping_sub(vy), Some((vx >= vy) as u8)),
+                    0x6 => (vx >> 1, Some(vx & 1)),
+                    0x7 => (vy.wrapping_sub(vx), Some((vy >= vx) as u8)),
+                    0xE => (vx << 1, Some(vx >> 7)),
+                    _ => return Err(Stop::Error),
+                };
+                self.v[x] = result;
+                if let Some(flag) = flag {
+                    self.v[0xF] = flag;
+                }
+            },
+            0x9 if n == 0 => self.skip_if(vx != vy),
+            0x9 => return Err(Stop::Error),
+            0xA => self.i = nnn,
+            0xB => self.pc = nnn + self.v[0] as u16,
+            0xC => self.v[x] = self.rng.next_byte() & kk,
+            0xD => {
+                if n == 0 {
+                    return Err(Stop::Unsupported);
+                }
+
+                let (left, top) = (vx as usize % WIDTH, vy as usize % HEIGHT);
+                self.v[0xF] = 0;
+                for row in 0..n as usize {
+                    let sprite = self.read(self.i as usize + row);
+                    for col in 0..8 {
+                        let (px, py) = (left + col, top + row);
+                        if sprite & (0x80 >> col) == 0 || px >= WIDTH || py >= HEIGHT {
+                            continue;
+                        }
+                        if self.screen[py][px] {
+                            self.v[0xF] = 1;
+                        }
+                        self.screen[py][px] ^= true;
+                    }
+                }
+                return Ok(Step::Drew);
+            },
+            0xE => match kk {
+                0x9E => self.skip_if(self.keys[vx as usize & 0xF]),
+                0xA1 => self.skip_if(!self.keys[vx as usize & 0xF]),
+                _ => return Err(Stop::Error),
+            },
+            0xF => match kk {
+                0x07 => self.v[x] = self.dt,
+                0x0A => match self.keys.iter().position(|k| *k) {
+                    Some(key) => self.v[x] = key as u8,
+                    None => {
+                        self.pc -= 2;
+                        return Ok(Step::WaitingForKey);
+                    },
+                },
+                0x15 => self.dt = vx,
+                0x18 => self.st = vx,
+                0x1E => self.i = self.i.wrapping_add(vx as u16),
+                0x29 => self.i = (vx & 0xF) as u16 * 5,
+                0x33 => {
+                    let i = self.i as usize;
+                    self.write(i, vx / 100);
+                    self.write(i + 1, vx / 10 % 10);
+                    self.write(i + 2, vx % 10);
+                },
+                0x55 => {
+                    for r in 0..=x {
+                        self.write(self.i as usize + r, self.v[r]);
+                    }
+                },
+                0x65 => {
+                    for r in 0..=x {
+                        self.v[r] = self.read(self.i as usize + r);
+                    }
+                },
+                0x00 if x == 0 => return Err(Stop::Unsupported),
+                0x02 if x == 0 => return Err(Stop::Unsupported),
+                0x01 | 0x30 | 0x3A | 0x75 | 0x85 => return Err(Stop::Unsupported),
+                _ => return Err(Stop::Error),
+            },
+            _ => unreachable!(),
+        }
+
+        Ok(Step::Ran)
+    }
+}
@@ -0,0 +1,38 @@
+// the disassembler's output has to assemble back to the same bytes, for every
+// bundled rom and for every opcode on its own
+use std::{fs, path::Path};
+use chip8_rs::asm::assemble;
+use chip8_rs::disasm::disassemble;
+
+fn roundtrip(rom: &[u8]) -> Result<Vec<u8>, String> {
+    let source = disassemble(rom, 0x200).to_string();
+    assemble(&source).map_err(|e| format!("{}\n{}", e, source))
+}
+
+#[test]
+fn bundled_roms() {
+    let mut roms: Vec<_> = fs::read_dir(env!("CARGO_MANIFEST_DIR")).unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|e| e == "ch8"))
+        .collect();
+    roms.sort();
+    assert!(!roms.is_empty());
+
+    for path in roms {
+        let rom = fs::read(&path).unwrap();
+        let name = Path::new(&path).file_name().unwrap().to_string_lossy().to_string();
+        assert_eq!(roundtrip(&rom).unwrap_or_else(|e| panic!("{}: {}", name, e)), rom, "{}", name);
+    }
+}
+
+#[test]
+fn every_opcode() {
+    // 00E0 first so the word under test is reachable code, not data
+    for op in 0..=0xFFFFu16 {
+        let rom = [0x00, 0xE0, (op >> 8) as u8, op as u8, 0x00, 0x00, 0x00, 0x00];
+        match roundtrip(&rom) {
+            Ok(bytes) => assert_eq!(bytes, rom, "{:04X}", op),
+            Err(e) => panic!("{:04X}: {}", op, e),
+        }
+    }
+}